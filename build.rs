@@ -2,6 +2,9 @@ extern crate cc;
 extern crate winres;
 
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/c");
+
     let mut base = cc::Build::new();
     if std::env::var_os("CARGO_CFG_WINDOWS").is_some() {
         let mut res = winres::WindowsResource::new();
@@ -42,11 +45,10 @@ fn main() {
         }
     }
 
-    let mut config = base.clone();
-    config.file("src/c/sph_shabal.c").file("src/c/common.c").compile("shabal");
-
-    if std::env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "x86_64" {
-
+    // the Rust backends don't need any C, these are only built to compare
+    if std::env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "x86_64"
+        && std::env::var_os("CARGO_FEATURE_C_SIMD").is_some()
+    {
         base.clone().file("src/c/sph_shabal.c").file("src/c/common.c").compile("shabal");
 
         println!("cargo:rustc-link-search=native={}", std::env::var("OUT_DIR").unwrap());
@@ -0,0 +1,302 @@
+//! Local HTTP API to watch and control a headless hasher.
+//!
+//! [`ApiServer`] answers from an [`ApiState`], which [`ApiEvents`] keeps up to
+//! date from the events of the running [`Hasher`](crate::Hasher):
+//!
+//! - `GET /status` the current task, progress, speeds and ETA
+//! - `GET /tasks` the queued tasks
+//! - `POST /tasks` queues a [`TaskSpec`] given as JSON
+//! - `POST /stop` raises the stop flag of the running task
+
+use crate::cpu_hasher::init_simd;
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents, Phase};
+use crate::hasher::{HashReport, HasherTask, NONCE_SIZE};
+use crate::task_spec::TaskSpec;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunState {
+    #[default]
+    Idle,
+    Preallocating,
+    Resuming,
+    Hashing,
+    /// The stop flag is raised, the run ends after the current buffer.
+    Stopping,
+}
+
+/// The outcome of the last run, see [`HashReport`].
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub files: Vec<PathBuf>,
+    pub nonces: u64,
+    pub hashed_nonces: u64,
+    pub elapsed_secs: f64,
+    pub mib_per_sec: f64,
+    pub stopped: bool,
+}
+
+impl From<&HashReport> for RunSummary {
+    fn from(report: &HashReport) -> RunSummary {
+        RunSummary {
+            files: report.files.clone(),
+            nonces: report.nonces,
+            hashed_nonces: report.hashed_nonces,
+            elapsed_secs: report.elapsed.as_secs_f64(),
+            mib_per_sec: report.mib_per_sec(),
+            stopped: report.stopped,
+        }
+    }
+}
+
+/// What `GET /status` returns.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Status {
+    pub state: RunState,
+    pub task: Option<TaskSpec>,
+    /// SIMD extension of the CPU hashing, by its `--simd` name.
+    pub simd: Option<String>,
+    /// Nonces of the plot file being written and how many of them were on
+    /// disk already when the run started.
+    pub nonces: u64,
+    pub resumed_from: u64,
+    pub hashed: u64,
+    pub written: u64,
+    /// Nonces per minute.
+    pub hash_speed: f64,
+    /// MiB/s.
+    pub write_speed: f64,
+    /// Estimated seconds until the plot file is written.
+    pub eta_secs: Option<u64>,
+    pub queued: usize,
+    pub last_run: Option<RunSummary>,
+    pub last_error: Option<String>,
+}
+
+/// State shared between the hashing side and the server.
+pub struct ApiState {
+    status: Mutex<Status>,
+    queue: Mutex<VecDeque<TaskSpec>>,
+    queued: Condvar,
+    /// Stop flag of the running task, `None` while idle. Locked after
+    /// `status`.
+    stop_flag: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl ApiState {
+    pub fn new() -> Arc<ApiState> {
+        Arc::new(ApiState {
+            status: Mutex::new(Status::default()),
+            queue: Mutex::new(VecDeque::new()),
+            queued: Condvar::new(),
+            stop_flag: Mutex::new(None),
+        })
+    }
+
+    pub fn status(&self) -> Status {
+        let mut status = self.status.lock().unwrap().clone();
+        status.queued = self.queue.lock().unwrap().len();
+        status
+    }
+
+    pub fn tasks(&self) -> Vec<TaskSpec> {
+        self.queue.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Shows `task` as the current one, call it right before running it.
+    /// `POST /stop` raises the stop flag of `task` until it has finished.
+    pub fn start(&self, task: &HasherTask) {
+        let mut status = self.status.lock().unwrap();
+        *self.stop_flag.lock().unwrap() = task.stop_flag.clone();
+        let simd_ext = task.simd.clone().unwrap_or_else(init_simd);
+        *status = Status {
+            task: Some(TaskSpec::from_task(task)),
+            simd: (task.cpu_threads > 0).then(|| simd_ext.name().to_string()),
+            last_run: status.last_run.take(),
+            last_error: status.last_error.take(),
+            ..Status::default()
+        };
+    }
+
+    /// Records an error of a task that couldn't even be started.
+    pub fn fail(&self, error: String) {
+        let mut status = self.status.lock().unwrap();
+        status.state = RunState::Idle;
+        status.task = None;
+        status.last_error = Some(error);
+        *self.stop_flag.lock().unwrap() = None;
+    }
+
+    pub fn push(&self, task: TaskSpec) -> usize {
+        let mut queue = self.queue.lock().unwrap();
+        queue.push_back(task);
+        self.queued.notify_one();
+        queue.len()
+    }
+
+    /// Takes the next queued task, waiting up to `timeout` for one.
+    pub fn next_task(&self, timeout: Duration) -> Option<TaskSpec> {
+        let queue = self.queue.lock().unwrap();
+        let (mut queue, _) = self.queued.wait_timeout_while(queue, timeout, |queue| queue.is_empty()).unwrap();
+        queue.pop_front()
+    }
+
+    /// Stops the running task, false when there is none.
+    pub fn stop(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        let Some(stop_flag) = &*self.stop_flag.lock().unwrap() else {
+            return false;
+        };
+        stop_flag.store(true, Ordering::Relaxed);
+        if status.state != RunState::Idle {
+            status.state = RunState::Stopping;
+        }
+        true
+    }
+
+    fn on_event(&self, event: &HasherEvent) {
+        let mut status = self.status.lock().unwrap();
+        match event {
+            HasherEvent::Phase(Phase::Preallocating) => status.state = RunState::Preallocating,
+            HasherEvent::Phase(Phase::Resuming) => status.state = RunState::Resuming,
+            HasherEvent::Phase(Phase::Hashing { nonces, resumed_from }) => {
+                status.state = RunState::Hashing;
+                status.nonces = *nonces;
+                status.resumed_from = *resumed_from;
+                status.hashed = *resumed_from;
+                status.written = *resumed_from;
+            }
+            HasherEvent::Hashed(nonces) => status.hashed = *nonces,
+            HasherEvent::Written(nonces) => status.written = *nonces,
+            HasherEvent::HashSpeed(speed) => status.hash_speed = *speed,
+            HasherEvent::WriteSpeed(speed) => status.write_speed = *speed,
+            HasherEvent::Error(msg) => {
+                status.state = RunState::Idle;
+                status.last_error = Some(msg.clone());
+                *self.stop_flag.lock().unwrap() = None;
+            }
+            HasherEvent::Finished(report) => {
+                status.state = RunState::Idle;
+                *self.stop_flag.lock().unwrap() = None;
+                status.eta_secs = None;
+                status.last_run = Some(RunSummary::from(report));
+                return;
+            }
+            _ => return,
+        }
+        let stopped = self.stop_flag.lock().unwrap().as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed));
+        if status.state == RunState::Hashing && stopped {
+            status.state = RunState::Stopping;
+        }
+        // writing trails hashing, so it decides when the file is done
+        let remaining = status.nonces.saturating_sub(status.written);
+        status.eta_secs = if status.write_speed > 0.0 {
+            Some((remaining * NONCE_SIZE) as f64 / 1024.0 / 1024.0 / status.write_speed)
+        } else if status.hash_speed > 0.0 {
+            Some(remaining as f64 * 60.0 / status.hash_speed)
+        } else {
+            None
+        }
+        .map(|secs| secs.round() as u64);
+    }
+}
+
+/// Keeps an [`ApiState`] up to date and passes every event on to `inner`.
+pub struct ApiEvents {
+    state: Arc<ApiState>,
+    inner: Arc<dyn HasherEvents>,
+}
+
+impl ApiEvents {
+    pub fn new(state: Arc<ApiState>, inner: Arc<dyn HasherEvents>) -> ApiEvents {
+        ApiEvents { state, inner }
+    }
+}
+
+impl HasherEvents for ApiEvents {
+    fn on_event(&self, event: &HasherEvent) {
+        self.state.on_event(event);
+        self.inner.on_event(event);
+    }
+}
+
+/// Serves the API on its own thread until it is dropped.
+pub struct ApiServer {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ApiServer {
+    /// Listens on `addr`, port 0 picks a free one, see [`ApiServer::local_addr`].
+    pub fn start(addr: &str, state: Arc<ApiState>) -> Result<ApiServer, HasherError> {
+        let server = Server::http(addr).map_err(|e| HasherError::HttpServer {
+            addr: addr.to_string(),
+            reason: e.to_string(),
+        })?;
+        let server = Arc::new(server);
+        let incoming = server.clone();
+        let thread = thread::spawn(move || {
+            for request in incoming.incoming_requests() {
+                handle(request, &state);
+            }
+        });
+        Ok(ApiServer { server, thread: Some(thread) })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn handle(mut request: Request, state: &ApiState) {
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+    let (code, body) = match (request.method(), path.as_str()) {
+        (Method::Get, "/status") => (200, json(&state.status())),
+        (Method::Get, "/tasks") => (200, json(&state.tasks())),
+        (Method::Post, "/tasks") => {
+            let mut body = String::new();
+            let task = request
+                .as_reader()
+                .read_to_string(&mut body)
+                .map_err(|e| e.to_string())
+                .and_then(|_| serde_json::from_str::<TaskSpec>(&body).map_err(|e| e.to_string()))
+                .and_then(|task| task.validate().map(|_| task));
+            match task {
+                Ok(task) => (202, format!("{{\"queued\":{}}}", state.push(task))),
+                Err(e) => (400, error(&e)),
+            }
+        }
+        (Method::Post, "/stop") => (202, format!("{{\"stopping\":{}}}", state.stop())),
+        (_, "/status" | "/tasks" | "/stop") => (405, error("method not allowed")),
+        _ => (404, error("not found")),
+    };
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let _ = request.respond(Response::from_string(body).with_status_code(code).with_header(header));
+}
+
+fn json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+fn error(msg: &str) -> String {
+    json(&serde_json::json!({ "error": msg }))
+}
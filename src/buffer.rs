@@ -52,9 +52,7 @@ mod buffer_tests {
 
     #[test]
     fn buffer_creation_destruction_test() {
-        {
-            let _test = PageAlignedByteBuffer::new(1024 * 1024);
-        }
-        assert!(true);
+        let test = PageAlignedByteBuffer::new(1024 * 1024);
+        assert_eq!(test.get_buffer().lock().unwrap().len(), 1024 * 1024);
     }
 }
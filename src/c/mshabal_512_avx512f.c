@@ -720,7 +720,7 @@ void mshabal_hash_fast_avx512f(mshabal512_context_fast *sc, void *message, void
     one = _mm512_set1_epi32(C32(0xFFFFFFFF));
 
     // round 1
-#define M(i) _mm512_load_si512((__m512i *)message + i)
+#define M(i) _mm512_loadu_si512((__m512i *)message + i)
 
     while (num-- > 0) {
         for (j = 0; j < 16; j++) B[j] = _mm512_add_epi32(B[j], M(j));
@@ -1019,7 +1019,7 @@ void mshabal_deadline_fast_avx512f(mshabal512_context_fast *sc, void *message, v
     one = _mm512_set1_epi32(C32(0xFFFFFFFF));
 
     // round 1
-#define M(i) _mm512_load_si512((__m512i *)message + i)
+#define M(i) _mm512_loadu_si512((__m512i *)message + i)
 
     for (j = 0; j < 16; j++) B[j] = _mm512_add_epi32(B[j], M(j));
 
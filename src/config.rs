@@ -0,0 +1,226 @@
+//! `--config plot.toml`: defaults for the hashing options plus named profiles
+//! that override them.
+//!
+//! ```toml
+//! [defaults]
+//! numeric_id = 1234567890123456789
+//! paths = ["/mnt/hdd1", "/mnt/hdd2"]
+//! mem = "8GiB"
+//! simd = "fastest"
+//!
+//! [profiles.gpu]
+//! cpu = 0
+//! gpus = ["0:0:1024"]
+//! ```
+//!
+//! Keys that are left out fall back to the defaults, then to the built-in
+//! values. Command line flags override both.
+
+use crate::error::HasherError;
+use crate::resume::write_atomically;
+use humanize_rs::bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Every `--simd` value, including those of other architectures, so one file
+/// can serve several machines.
+const SIMD_NAMES: [&str; 8] = ["auto", "fastest", "avx512f", "avx2", "avx", "sse2", "neon", "rust"];
+
+/// Hashing options of the config file, `None` where a key is left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub numeric_id: Option<u64>,
+    /// Nonces per plot file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonces: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<String>>,
+    /// Memory limit like `--mem`, e.g. `"8GiB"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem: Option<String>,
+    /// CPU threads, 0 hashes on the GPUs only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<u8>,
+    /// `platform_id:device_id:cores` like `--gpu`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpus: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direct_io: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zcb: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub low_priority: Option<bool>,
+    /// A `--simd` value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub simd: Option<String>,
+}
+
+impl Settings {
+    /// These settings with every key that `other` sets taken from `other`.
+    pub fn merged(&self, other: &Settings) -> Settings {
+        Settings {
+            numeric_id: other.numeric_id.or(self.numeric_id),
+            nonces: other.nonces.or(self.nonces),
+            paths: other.paths.clone().or_else(|| self.paths.clone()),
+            mem: other.mem.clone().or_else(|| self.mem.clone()),
+            cpu: other.cpu.or(self.cpu),
+            gpus: other.gpus.clone().or_else(|| self.gpus.clone()),
+            direct_io: other.direct_io.or(self.direct_io),
+            zcb: other.zcb.or(self.zcb),
+            low_priority: other.low_priority.or(self.low_priority),
+            simd: other.simd.clone().or_else(|| self.simd.clone()),
+        }
+    }
+
+    /// Checks the values, errors name the key as `table.key`.
+    fn validate(&self, table: &str) -> Result<(), String> {
+        let invalid = |key: &str, reason: String| Err(format!("{}.{}: {}", table, key, reason));
+        if self.nonces == Some(0) {
+            return invalid("nonces", "must be at least 1".to_string());
+        }
+        if let Some(paths) = &self.paths {
+            if paths.is_empty() {
+                return invalid("paths", "must not be empty".to_string());
+            }
+            if paths.iter().any(|path| path.trim().is_empty()) {
+                return invalid("paths", "must not contain empty paths".to_string());
+            }
+        }
+        if let Some(mem) = &self.mem
+            && mem.parse::<Bytes>().is_err()
+        {
+            return invalid("mem", format!("'{}' is not a size like 8GiB", mem));
+        }
+        if let Some(gpu) = self.gpus.iter().flatten().find(|gpu| !is_gpu_spec(gpu)) {
+            return invalid("gpus", format!("'{}' is not platform_id:device_id:cores", gpu));
+        }
+        if let Some(simd) = &self.simd
+            && !SIMD_NAMES.iter().any(|name| name.eq_ignore_ascii_case(simd))
+        {
+            return invalid("simd", format!("'{}' is none of {}", simd, SIMD_NAMES.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+fn is_gpu_spec(spec: &str) -> bool {
+    let parts: Vec<&str> = spec.split(':').collect();
+    parts.len() == 3 && parts.iter().all(|part| part.parse::<u32>().is_ok())
+}
+
+/// The contents of a config file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub defaults: Settings,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    /// Reads and validates `file`.
+    pub fn load(file: &Path) -> Result<Config, HasherError> {
+        let invalid = |reason: String| HasherError::InvalidConfig { file: file.to_path_buf(), reason };
+        let text = fs::read_to_string(file).map_err(|source| HasherError::ReadConfig {
+            file: file.to_path_buf(),
+            source,
+        })?;
+        let config: Config = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        config.defaults.validate("defaults").map_err(invalid)?;
+        for (name, settings) in &config.profiles {
+            settings.validate(&format!("profiles.{}", name)).map_err(invalid)?;
+        }
+        Ok(config)
+    }
+
+    /// Like [`Config::load`], but a missing file is an empty config.
+    pub fn load_or_default(file: &Path) -> Result<Config, HasherError> {
+        if file.exists() {
+            Config::load(file)
+        } else {
+            Ok(Config::default())
+        }
+    }
+
+    pub fn save(&self, file: &Path) -> Result<(), HasherError> {
+        let write_error = |source| HasherError::WriteConfig { file: file.to_path_buf(), source };
+        let text = toml::to_string_pretty(self).unwrap();
+        if let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(write_error)?;
+        }
+        write_atomically(file, &[text.as_bytes()]).map_err(write_error)
+    }
+
+    /// The defaults, overridden by `profile` if given.
+    pub fn settings(&self, profile: Option<&str>) -> Result<Settings, HasherError> {
+        match profile {
+            Some(name) => match self.profiles.get(name) {
+                Some(settings) => Ok(self.defaults.merged(settings)),
+                None => Err(HasherError::UnknownProfile(name.to_string())),
+            },
+            None => Ok(self.defaults.clone()),
+        }
+    }
+
+    /// Stores `settings` as the defaults, or as `profile` if given.
+    pub fn set_settings(&mut self, profile: Option<&str>, settings: Settings) {
+        match profile {
+            Some(name) => {
+                self.profiles.insert(name.to_string(), settings);
+            }
+            None => self.defaults = settings,
+        }
+    }
+
+    /// Where the GUI keeps its settings without `--config`.
+    pub fn default_file() -> Option<PathBuf> {
+        #[cfg(windows)]
+        let dir = env::var_os("APPDATA").map(PathBuf::from);
+        #[cfg(not(windows))]
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        dir.map(|dir| dir.join("anne-hasher").join("plot.toml"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let file = env::temp_dir().join(format!("anne-hasher-config-{}.toml", std::process::id()));
+        let invalid = |text: &str| {
+            fs::write(&file, text).unwrap();
+            Config::load(&file).unwrap_err().to_string()
+        };
+        assert!(invalid("[defaults]\ncpus = 4\n").contains("unknown field `cpus`"));
+        assert!(invalid("[defaults]\nmem = 4\n").contains("mem = 4"));
+        assert!(invalid("[profiles.ssd]\nmem = \"lots\"\n").contains("profiles.ssd.mem"));
+        assert!(invalid("[profiles.gpu]\ngpus = [\"0:0\"]\n").contains("profiles.gpu.gpus"));
+
+        fs::write(&file, "[defaults]\nnumeric_id = 42\nmem = \"1GiB\"\ncpu = 4\n\n[profiles.gpu]\ncpu = 0\ngpus = [\"0:0:256\"]\n")
+            .unwrap();
+        let config = Config::load(&file).unwrap();
+        let settings = config.settings(Some("gpu")).unwrap();
+        assert_eq!(settings.numeric_id, Some(42));
+        assert_eq!(settings.mem.as_deref(), Some("1GiB"));
+        assert_eq!(settings.cpu, Some(0));
+        assert_eq!(settings.gpus, Some(vec!["0:0:256".to_string()]));
+        assert!(config.settings(Some("nope")).is_err());
+
+        // flags override the file
+        let cli = Settings { cpu: Some(8), ..Settings::default() };
+        assert_eq!(settings.merged(&cli).cpu, Some(8));
+
+        config.save(&file).unwrap();
+        assert_eq!(Config::load(&file).unwrap(), config);
+        fs::remove_file(&file).unwrap();
+    }
+}
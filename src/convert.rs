@@ -0,0 +1,529 @@
+use crate::buffer::PageAlignedByteBuffer;
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents, Phase};
+use crate::hasher::{NONCE_SIZE, NUM_SCOOPS, SCOOP_SIZE};
+use crate::resume::{
+    mark_complete, read_migrated, read_resume_info, write_atomically, write_resume_info, ResumeInfo, RECORD_SIZE,
+};
+use crate::utils::{
+    free_disk_space, get_sector_size, open, open_r, open_using_direct_io, parse_plot_file_name,
+    preallocate,
+};
+use humanize_rs::bytes::Bytes;
+use std::cmp::{max, min};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const HASH_SIZE: usize = 32;
+const DEFAULT_MEM: u64 = 1024 * 1024 * 1024;
+
+pub struct ConvertTask {
+    pub file: PathBuf,
+    /// Directory the converted file is written to, `None` converts in place.
+    pub output_dir: Option<PathBuf>,
+    /// Buffer size like `--mem`, `0B` uses 1 GiB.
+    pub mem: String,
+    pub direct_io: bool,
+    /// Converts to PoC1, otherwise to PoC2.
+    pub poc1: bool,
+    /// Stops after the current chunk, running the same task again resumes.
+    pub stop_flag: Option<Arc<AtomicBool>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConvertReport {
+    pub file: PathBuf,
+    pub output: PathBuf,
+    pub nonces: u64,
+    /// Nonces an earlier, interrupted run had converted already.
+    pub resumed_from: u64,
+    pub converted_nonces: u64,
+    pub elapsed: Duration,
+    pub stopped: bool,
+}
+
+impl ConvertReport {
+    pub fn mib_per_sec(&self) -> f64 {
+        (self.converted_nonces * NONCE_SIZE) as f64 / 1024.0 / 1024.0 / self.elapsed.as_secs_f64().max(0.001)
+    }
+}
+
+impl fmt::Display for ConvertReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.stopped {
+            return write!(
+                f,
+                "{}: stopped after {} of {} nonces, run again to resume",
+                self.output.display(),
+                self.resumed_from + self.converted_nonces,
+                self.nonces
+            );
+        }
+        write!(
+            f,
+            "{}: converted {} nonces in {:.0}s, {:.2} MiB/s",
+            self.output.display(),
+            self.converted_nonces,
+            self.elapsed.as_secs_f64(),
+            self.mib_per_sec()
+        )?;
+        if self.resumed_from > 0 {
+            write!(f, ", {} were converted before", self.resumed_from)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of the journal of an in-place conversion of `file`.
+pub fn journal_file(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".convert");
+    PathBuf::from(name)
+}
+
+/// Swaps the second hash of every scoop with the one of its mirror scoop,
+/// `count` nonces in file layout. This turns PoC1 into PoC2 and back.
+pub fn swap_scoop_halves(data: &mut [u8], count: u64) {
+    let row_len = (count * SCOOP_SIZE) as usize;
+    for scoop in 0..(NUM_SCOOPS / 2) as usize {
+        let mirror = NUM_SCOOPS as usize - 1 - scoop;
+        let (low, high) = data.split_at_mut(mirror * row_len);
+        let row = &mut low[scoop * row_len..(scoop + 1) * row_len];
+        let mirror_row = &mut high[..row_len];
+        for (a, b) in row
+            .chunks_exact_mut(SCOOP_SIZE as usize)
+            .zip(mirror_row.chunks_exact_mut(SCOOP_SIZE as usize))
+        {
+            a[HASH_SIZE..].swap_with_slice(&mut b[HASH_SIZE..]);
+        }
+    }
+}
+
+/// Converts a plot file between PoC1 and PoC2, both directions are the same
+/// swap of mirrored scoop halves.
+///
+/// Out of place the converted file is written like the writer does: chunk by
+/// chunk, synced and checkpointed in its resume info. In place every
+/// converted chunk goes to `{file}.convert` before it overwrites the file, an
+/// interrupted chunk is written again from there, so no nonce is ever
+/// swapped twice. That writes every chunk twice and needs free space for one
+/// chunk, up to `mem`, next to the file. Either way an interrupted conversion
+/// continues where it stopped when it's run again.
+///
+/// The resume info of a converted file records its format, a file that is
+/// already in the requested format is refused instead of swapped back.
+pub fn convert_plot_file(task: &ConvertTask, events: &dyn HasherEvents) -> Result<ConvertReport, HasherError> {
+    let start = Instant::now();
+    let file = task.file.as_path();
+    let (numeric_id, start_nonce, nonces, _) = check_plot_file(file)?;
+    let name = file.file_name().unwrap_or_default();
+    let read_error = |source| HasherError::ReadPlotFile { file: file.to_path_buf(), source };
+
+    let output = match &task.output_dir {
+        Some(dir) => dir.join(name),
+        None => file.to_path_buf(),
+    };
+    // converting into the directory of the file is converting in place
+    let in_place = task.output_dir.is_none()
+        || fs::canonicalize(&output).is_ok_and(|output| fs::canonicalize(file).is_ok_and(|file| file == output));
+    let output_dir = parent_dir(&output);
+
+    let mem = buffer_size(&task.mem)?;
+    let nonces_per_sector = nonces_per_sector(&output_dir, task.direct_io, events)?;
+    let direct_io = task.direct_io && nonces.is_multiple_of(nonces_per_sector);
+    let chunk_nonces = max(mem / NONCE_SIZE / nonces_per_sector, 1) * nonces_per_sector;
+
+    let journal = in_place.then(|| journal_file(file));
+    let format = if task.poc1 { "PoC1" } else { "PoC2" };
+    let converted = |progress| ResumeInfo {
+        poc1: task.poc1,
+        converted: true,
+        ..ResumeInfo::new(numeric_id, start_nonce, nonces, progress)
+    };
+    let resume_error = |source| HasherError::WriteResumeInfo { file: output.clone(), source };
+    // an unfinished in-place conversion hasn't recorded its format yet
+    if !journal.as_ref().is_some_and(|journal| journal.exists())
+        && ResumeInfo::read(file).is_ok_and(|info| !info.is_legacy() && info.poc1 == task.poc1)
+    {
+        return Err(HasherError::AlreadyConverted { file: file.to_path_buf(), format });
+    }
+    let resumed_from = match &journal {
+        Some(journal) => recover_journal(&output, journal, (numeric_id, start_nonce, nonces))?,
+        None => prepare_conversion(&output, converted(0), direct_io, events)?,
+    };
+
+    events.on_event(&HasherEvent::Phase(Phase::Converting { nonces, resumed_from }));
+    let write_error = |source| HasherError::WritePlotFile { file: output.clone(), source };
+    let journal_error = |source| HasherError::WriteResumeInfo {
+        file: journal.clone().unwrap_or_default(),
+        source,
+    };
+    let should_stop = || task.stop_flag.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed));
+
+    let mut progress = resumed_from;
+    let mut stopped = false;
+    if progress < nonces {
+        let buffer = PageAlignedByteBuffer::new((min(chunk_nonces, nonces) * NONCE_SIZE) as usize);
+        let data = buffer.get_buffer();
+        let mut data = data.lock().unwrap();
+        let mut source = open_r(file).map_err(read_error)?;
+        let mut target = open_output(&output, direct_io).map_err(write_error)?;
+        if journal.is_some() {
+            let required = min(chunk_nonces, nonces - progress) * NONCE_SIZE;
+            let available = free_disk_space(&output_dir)?;
+            if available < required {
+                return Err(HasherError::InsufficientDiskSpace { required, available });
+            }
+        }
+
+        while progress < nonces {
+            if should_stop() {
+                stopped = true;
+                break;
+            }
+            let count = min(chunk_nonces, nonces - progress);
+            let chunk = &mut data[..(count * NONCE_SIZE) as usize];
+            read_chunk(&mut source, nonces, progress, count, chunk).map_err(read_error)?;
+            swap_scoop_halves(chunk, count);
+
+            if let Some(journal) = &journal {
+                let header = ResumeInfo::new(numeric_id, start_nonce, nonces, progress).to_bytes();
+                write_atomically(journal, &[&header, chunk]).map_err(journal_error)?;
+            }
+            write_chunk(&mut target, nonces, progress, count, chunk).map_err(write_error)?;
+            target.sync_data().map_err(write_error)?;
+            progress += count;
+
+            // a journal stays valid until it's replaced by the next chunk
+            if journal.is_none() && progress < nonces {
+                converted(progress).write(&output).map_err(resume_error)?;
+            }
+            events.on_event(&HasherEvent::Written(progress));
+            events.on_event(&HasherEvent::WriteSpeed(
+                ((progress - resumed_from) * NONCE_SIZE) as f64 / 1024.0 / 1024.0 / start.elapsed().as_secs_f64(),
+            ));
+        }
+    }
+
+    if !stopped {
+        open(&output).and_then(|file| file.sync_all()).map_err(write_error)?;
+        // recorded before the journal goes, so a rerun never swaps back
+        converted(0).completed().write(&output).map_err(resume_error)?;
+    }
+    match &journal {
+        Some(journal) if stopped => {
+            // spares rewriting the last chunk on resume
+            let header = ResumeInfo::new(numeric_id, start_nonce, nonces, progress).to_bytes();
+            write_atomically(journal, &[&header]).map_err(journal_error)?;
+        }
+        Some(journal) => match fs::remove_file(journal) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(journal_error(e)),
+            _ => {}
+        },
+        None => {}
+    }
+
+    let report = ConvertReport {
+        file: file.to_path_buf(),
+        output,
+        nonces,
+        resumed_from,
+        converted_nonces: progress - resumed_from,
+        elapsed: start.elapsed(),
+        stopped,
+    };
+    events.on_event(&HasherEvent::Converted(report.clone()));
+    Ok(report)
+}
+
+/// Parameters of a completely written plot file whose size matches its name,
+/// and whether a conversion left it in PoC1 layout.
+pub(crate) fn check_plot_file(file: &Path) -> Result<(u64, u64, u64, bool), HasherError> {
+    let (numeric_id, start_nonce, nonces) = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_plot_file_name)
+        .ok_or_else(|| HasherError::InvalidPlotFileName(file.to_path_buf()))?;
+    let size = file
+        .metadata()
+        .map_err(|source| HasherError::ReadPlotFile { file: file.to_path_buf(), source })?
+        .len();
+    if size != nonces * NONCE_SIZE {
+        return Err(HasherError::PlotFileSize {
+            file: file.to_path_buf(),
+            expected: nonces * NONCE_SIZE,
+            actual: size,
+        });
+    }
+    // files without resume info weren't written by us, treat them as complete,
+    // a legacy marker is migrated first so that its bytes aren't copied along
+    let (written, poc1) = match read_migrated(file) {
+        Ok(info) => (info.progress, info.poc1),
+        Err(e) if e.kind() == ErrorKind::NotFound => (nonces, false),
+        Err(source) => return Err(HasherError::ReadResumeInfo { file: file.to_path_buf(), source }),
+    };
+    if written < nonces {
+        return Err(HasherError::PlotFileIncomplete { file: file.to_path_buf(), written, nonces });
+    }
+    Ok((numeric_id, start_nonce, nonces, poc1))
+}
+
+/// Directory of `file` as the disk helpers take it.
+pub(crate) fn parent_dir(file: &Path) -> String {
+    match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    }
+}
+
+/// Parses a `--mem` like buffer size, `0B` is the default of 1 GiB.
+pub(crate) fn buffer_size(mem: &str) -> Result<u64, HasherError> {
+    let mem = mem
+        .parse::<Bytes>()
+        .map_err(|_| HasherError::InvalidMemoryLimit(mem.to_string()))?
+        .size() as u64;
+    Ok(if mem == 0 { DEFAULT_MEM } else { mem })
+}
+
+/// Direct i/o needs every write aligned to the sector size, so chunks are
+/// multiples of this many nonces.
+pub(crate) fn nonces_per_sector(dir: &str, direct_io: bool, events: &dyn HasherEvents) -> Result<u64, HasherError> {
+    if direct_io {
+        Ok(max(1, get_sector_size(dir, events)? / SCOOP_SIZE))
+    } else {
+        Ok(1)
+    }
+}
+
+/// Nonces of a new file written chunk by chunk that are already on disk,
+/// the file is preallocated if it doesn't exist yet.
+pub(crate) fn prepare_output(
+    output: &Path,
+    size: u64,
+    direct_io: bool,
+    events: &dyn HasherEvents,
+) -> Result<u64, HasherError> {
+    if output.exists() {
+        events.on_event(&HasherEvent::Phase(Phase::Resuming));
+        // a file without resume info isn't ours and is never overwritten
+        return read_resume_info(output)
+            .map_err(|source| HasherError::ReadResumeInfo { file: output.to_path_buf(), source });
+    }
+    let available = free_disk_space(&parent_dir(output))?;
+    if available < size {
+        return Err(HasherError::InsufficientDiskSpace { required: size, available });
+    }
+    events.on_event(&HasherEvent::Phase(Phase::Preallocating));
+    preallocate(output, size, direct_io, events)?;
+    checkpoint_output(output, 0, size / NONCE_SIZE)?;
+    Ok(0)
+}
+
+/// Nonces of an out-of-place conversion that are already in `output`, which
+/// is preallocated if it doesn't exist yet. Only an unfinished conversion to
+/// the same format is resumed, any other file there is left alone.
+fn prepare_conversion(
+    output: &Path,
+    info: ResumeInfo,
+    direct_io: bool,
+    events: &dyn HasherEvents,
+) -> Result<u64, HasherError> {
+    let resume_error = |source| HasherError::ReadResumeInfo { file: output.to_path_buf(), source };
+    if output.exists() {
+        return match ResumeInfo::read_optional(output).map_err(resume_error)? {
+            Some(existing) if existing.converted && existing.poc1 == info.poc1 && !existing.complete => {
+                events.on_event(&HasherEvent::Phase(Phase::Resuming));
+                Ok(existing.progress)
+            }
+            Some(existing) if existing.converted && existing.poc1 == info.poc1 => Err(HasherError::AlreadyConverted {
+                file: output.to_path_buf(),
+                format: if info.poc1 { "PoC1" } else { "PoC2" },
+            }),
+            _ => Err(HasherError::OutputExists(output.to_path_buf())),
+        };
+    }
+    let size = info.nonces * NONCE_SIZE;
+    let available = free_disk_space(&parent_dir(output))?;
+    if available < size {
+        return Err(HasherError::InsufficientDiskSpace { required: size, available });
+    }
+    events.on_event(&HasherEvent::Phase(Phase::Preallocating));
+    preallocate(output, size, direct_io, events)?;
+    info.write(output)
+        .map_err(|source| HasherError::WriteResumeInfo { file: output.to_path_buf(), source })?;
+    Ok(0)
+}
+
+/// Records synced progress like the writer does, the last chunk is recorded
+/// by [`finish_output`].
+pub(crate) fn checkpoint_output(output: &Path, progress: u64, nonces: u64) -> Result<(), HasherError> {
+    if progress == nonces {
+        return Ok(());
+    }
+    write_resume_info(output, progress)
+        .map_err(|source| HasherError::WriteResumeInfo { file: output.to_path_buf(), source })
+}
+
+/// Syncs `output` and marks it complete, as PoC1 if it holds PoC1 data.
+pub(crate) fn finish_output(output: &Path, poc1: bool) -> Result<(), HasherError> {
+    open(output)
+        .and_then(|file| file.sync_all())
+        .map_err(|source| HasherError::WritePlotFile { file: output.to_path_buf(), source })?;
+    let resume_error = |source| HasherError::WriteResumeInfo { file: output.to_path_buf(), source };
+    if !poc1 {
+        return mark_complete(output).map_err(resume_error);
+    }
+    let info = ResumeInfo::for_plot_file(output, 0).map_err(resume_error)?.completed();
+    ResumeInfo { poc1, ..info }.write(output).map_err(resume_error)
+}
+
+/// Nonces of an in-place conversion that are done, after writing the chunk
+/// that was in flight again.
+fn recover_journal(file: &Path, journal: &Path, params: (u64, u64, u64)) -> Result<u64, HasherError> {
+    let read_error = |source| HasherError::ReadResumeInfo { file: journal.to_path_buf(), source };
+    let bytes = match fs::read(journal) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(read_error(e)),
+    };
+    let info = ResumeInfo::from_bytes(&bytes).map_err(read_error)?;
+    let chunk = &bytes[RECORD_SIZE..];
+    let count = chunk.len() as u64 / NONCE_SIZE;
+    if (info.numeric_id, info.start_nonce, info.nonces) != params
+        || chunk.len() as u64 != count * NONCE_SIZE
+        || info.progress + count > info.nonces
+    {
+        return Err(read_error(Error::new(
+            ErrorKind::InvalidData,
+            "conversion journal doesn't match the plot file",
+        )));
+    }
+    if count > 0 {
+        let write_error = |source| HasherError::WritePlotFile { file: file.to_path_buf(), source };
+        let mut target = open(file).map_err(write_error)?;
+        write_chunk(&mut target, info.nonces, info.progress, count, chunk).map_err(write_error)?;
+        target.sync_data().map_err(write_error)?;
+    }
+    Ok(info.progress + count)
+}
+
+pub(crate) fn open_output(file: &Path, direct_io: bool) -> Result<File, Error> {
+    if !direct_io {
+        return open(file);
+    }
+    match open_using_direct_io(file) {
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => open(file),
+        result => result,
+    }
+}
+
+/// Reads `count` nonces from `offset` on into `chunk` in file layout.
+pub(crate) fn read_chunk(plot: &mut File, nonces: u64, offset: u64, count: u64, chunk: &mut [u8]) -> Result<(), Error> {
+    for (scoop, row) in chunk.chunks_exact_mut((count * SCOOP_SIZE) as usize).enumerate() {
+        plot.seek(SeekFrom::Start((scoop as u64 * nonces + offset) * SCOOP_SIZE))?;
+        plot.read_exact(row)?;
+    }
+    Ok(())
+}
+
+/// Writes `count` nonces in file layout from `chunk` to `offset` on.
+pub(crate) fn write_chunk(plot: &mut File, nonces: u64, offset: u64, count: u64, chunk: &[u8]) -> Result<(), Error> {
+    for (scoop, row) in chunk.chunks_exact((count * SCOOP_SIZE) as usize).enumerate() {
+        plot.seek(SeekFrom::Start((scoop as u64 * nonces + offset) * SCOOP_SIZE))?;
+        plot.write_all(row)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_hasher::{init_simd, noncegen};
+    use crate::events::NoEvents;
+    use crate::resume::write_legacy_marker;
+    use crate::utils::plot_file_name;
+
+    #[test]
+    fn test_convert_plot_file() {
+        let numeric_id = 7900104405094198526;
+        let (start_nonce, nonces) = (1337, 5);
+        let dir = std::env::temp_dir().join(format!("anne-hasher-convert-{}", std::process::id()));
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        let file = dir.join(plot_file_name(numeric_id, start_nonce, nonces));
+
+        let mut poc2 = vec![0u8; (nonces * NONCE_SIZE) as usize];
+        noncegen(&init_simd(), &mut poc2, 0, numeric_id, start_nonce, nonces);
+        let mut poc1 = poc2.clone();
+        swap_scoop_halves(&mut poc1, nonces);
+        assert_ne!(poc1, poc2);
+        fs::write(&file, &poc2).unwrap();
+        // a file of the old hasher, its marker is migrated before it's swapped
+        write_legacy_marker(&file, nonces as u32).unwrap();
+
+        // 2 nonces per chunk, stopped before the first one
+        let stop_flag = Arc::new(AtomicBool::new(true));
+        let mut task = ConvertTask {
+            file: file.clone(),
+            output_dir: Some(out.clone()),
+            mem: (2 * NONCE_SIZE).to_string(),
+            direct_io: false,
+            poc1: true,
+            stop_flag: Some(stop_flag.clone()),
+        };
+        let report = convert_plot_file(&task, &NoEvents).unwrap();
+        assert!(report.stopped);
+        assert_eq!(ResumeInfo::read(&report.output).unwrap().progress, 0);
+
+        stop_flag.store(false, Ordering::Relaxed);
+        let report = convert_plot_file(&task, &NoEvents).unwrap();
+        assert_eq!(report.converted_nonces, nonces);
+        assert_eq!(fs::read(&report.output).unwrap(), poc1);
+        let info = ResumeInfo::read(&report.output).unwrap();
+        assert!(info.complete && info.poc1);
+
+        // a finished output isn't converted again, nor is a file of another run
+        assert!(matches!(convert_plot_file(&task, &NoEvents), Err(HasherError::AlreadyConverted { .. })));
+        mark_complete(&report.output).unwrap();
+        assert!(matches!(convert_plot_file(&task, &NoEvents), Err(HasherError::OutputExists(_))));
+        assert_eq!(fs::read(&report.output).unwrap(), poc1);
+
+        // in place, after a crash halfway through writing nonces 2..4
+        let poc1_chunk = |offset, count| {
+            let mut chunk = vec![0u8; (count * NONCE_SIZE) as usize];
+            noncegen(&init_simd(), &mut chunk, 0, numeric_id, start_nonce + offset, count);
+            swap_scoop_halves(&mut chunk, count);
+            chunk
+        };
+        let mut target = open(&file).unwrap();
+        write_chunk(&mut target, nonces, 0, 2, &poc1_chunk(0, 2)).unwrap();
+        let pending = poc1_chunk(2, 2);
+        for (scoop, row) in pending.chunks_exact(2 * SCOOP_SIZE as usize).take(NUM_SCOOPS as usize / 2).enumerate() {
+            target.seek(SeekFrom::Start((scoop as u64 * nonces + 2) * SCOOP_SIZE)).unwrap();
+            target.write_all(row).unwrap();
+        }
+        let header = ResumeInfo::new(numeric_id, start_nonce, nonces, 2).to_bytes();
+        write_atomically(&journal_file(&file), &[&header, &pending]).unwrap();
+
+        task.output_dir = None;
+        let report = convert_plot_file(&task, &NoEvents).unwrap();
+        assert_eq!((report.resumed_from, report.converted_nonces), (4, 1));
+        assert_eq!(fs::read(&file).unwrap(), poc1);
+        assert!(!journal_file(&file).exists());
+
+        // running it again doesn't undo it, converting back does
+        assert!(matches!(convert_plot_file(&task, &NoEvents), Err(HasherError::AlreadyConverted { .. })));
+        assert_eq!(fs::read(&file).unwrap(), poc1);
+        task.poc1 = false;
+        convert_plot_file(&task, &NoEvents).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), poc2);
+        assert!(!ResumeInfo::read(&file).unwrap().poc1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
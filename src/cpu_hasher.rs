@@ -1,51 +1,66 @@
-use crate::poc_hashing::noncegen_rust;
-use libc::{c_void, size_t};
+#[cfg(target_arch = "aarch64")]
+use crate::mshabal_aarch64::noncegen_neon;
+#[cfg(target_arch = "x86_64")]
+use crate::mshabal_x86::{noncegen_avx, noncegen_avx2, noncegen_avx512f, noncegen_sse2};
+use crate::error::HasherError;
+use crate::mshabal::noncegen_portable;
 use std::slice::from_raw_parts_mut;
+use std::str::FromStr;
 use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Instant;
 
 const NUM_SCOOPS: usize = 4096;
 const SCOOP_SIZE: usize = 64;
 const NONCE_SIZE: usize = NUM_SCOOPS * SCOOP_SIZE;
+/// Nonces hashed at once without a SIMD backend, wider didn't pay off on x86_64
+/// with its SSE2 baseline.
+const PORTABLE_LANES: usize = 8;
 
-#[cfg(target_arch = "x86_64")]
-unsafe extern "C" {
-    pub fn init_shabal_sse2() -> ();
-    pub fn init_shabal_avx() -> ();
-    pub fn init_shabal_avx2() -> ();
-    pub fn init_shabal_avx512f() -> ();
-
-    pub fn noncegen_sse2(
-        cache: *mut c_void,
-        cache_size: size_t,
-        chunk_offset: size_t,
-        numeric_ID: u64,
-        local_startnonce: u64,
-        local_nonces: u64,
-    );
-    pub fn noncegen_avx(
-        cache: *mut c_void,
-        cache_size: size_t,
-        chunk_offset: size_t,
-        numeric_ID: u64,
-        local_startnonce: u64,
-        local_nonces: u64,
-    );
-    pub fn noncegen_avx2(
-        cache: *mut c_void,
-        cache_size: size_t,
-        chunk_offset: size_t,
-        numeric_ID: u64,
-        local_startnonce: u64,
-        local_nonces: u64,
-    );
-    pub fn noncegen_avx512f(
-        cache: *mut c_void,
-        cache_size: size_t,
-        chunk_offset: size_t,
-        numeric_ID: u64,
-        local_startnonce: u64,
-        local_nonces: u64,
-    );
+/// The C implementations the Rust backends were ported from, for comparison.
+#[cfg(all(target_arch = "x86_64", feature = "c_simd"))]
+mod c {
+    use libc::{c_void, size_t};
+
+    unsafe extern "C" {
+        pub fn init_shabal_sse2() -> ();
+        pub fn init_shabal_avx() -> ();
+        pub fn init_shabal_avx2() -> ();
+        pub fn init_shabal_avx512f() -> ();
+
+        pub fn noncegen_sse2(
+            cache: *mut c_void,
+            cache_size: size_t,
+            chunk_offset: size_t,
+            numeric_ID: u64,
+            local_startnonce: u64,
+            local_nonces: u64,
+        );
+        pub fn noncegen_avx(
+            cache: *mut c_void,
+            cache_size: size_t,
+            chunk_offset: size_t,
+            numeric_ID: u64,
+            local_startnonce: u64,
+            local_nonces: u64,
+        );
+        pub fn noncegen_avx2(
+            cache: *mut c_void,
+            cache_size: size_t,
+            chunk_offset: size_t,
+            numeric_ID: u64,
+            local_startnonce: u64,
+            local_nonces: u64,
+        );
+        pub fn noncegen_avx512f(
+            cache: *mut c_void,
+            cache_size: size_t,
+            chunk_offset: size_t,
+            numeric_ID: u64,
+            local_startnonce: u64,
+            local_nonces: u64,
+        );
+    }
 }
 
 pub struct SafePointer {
@@ -66,6 +81,7 @@ pub struct CpuTask {
 unsafe impl Send for CpuTask {}
 unsafe impl Sync for CpuTask {}
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
 pub enum SimdExtension {
     #[cfg(target_arch = "x86_64")]
@@ -76,6 +92,8 @@ pub enum SimdExtension {
     AVX,
     #[cfg(target_arch = "x86_64")]
     SSE2,
+    #[cfg(target_arch = "aarch64")]
+    NEON,
     None,
 }
 
@@ -83,22 +101,248 @@ pub fn init_simd() -> SimdExtension {
     #[cfg(target_arch = "x86_64")]
     {
         if is_x86_feature_detected!("avx512f") {
-            unsafe { init_shabal_avx512f(); }
             return SimdExtension::AVX512f;
         } else if is_x86_feature_detected!("avx2") {
-            unsafe { init_shabal_avx2(); }
             return SimdExtension::AVX2;
         } else if is_x86_feature_detected!("avx") {
-            unsafe { init_shabal_avx(); }
             return SimdExtension::AVX;
         } else if is_x86_feature_detected!("sse2") {
-            unsafe { init_shabal_sse2(); }
             return SimdExtension::SSE2;
         }
     }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        return SimdExtension::NEON;
+    }
     SimdExtension::None
 }
 
+impl SimdExtension {
+    /// Every backend, widest first, ending with the portable Rust one that
+    /// hashes several nonces at once in plain arrays.
+    pub const ALL: &'static [SimdExtension] = &[
+        #[cfg(target_arch = "x86_64")]
+        SimdExtension::AVX512f,
+        #[cfg(target_arch = "x86_64")]
+        SimdExtension::AVX2,
+        #[cfg(target_arch = "x86_64")]
+        SimdExtension::AVX,
+        #[cfg(target_arch = "x86_64")]
+        SimdExtension::SSE2,
+        #[cfg(target_arch = "aarch64")]
+        SimdExtension::NEON,
+        SimdExtension::None,
+    ];
+
+    /// The name `--simd` takes. `rust` is the portable multi-lane backend,
+    /// not the scalar [`noncegen_rust`](crate::poc_hashing::noncegen_rust).
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            SimdExtension::AVX512f => "avx512f",
+            #[cfg(target_arch = "x86_64")]
+            SimdExtension::AVX2 => "avx2",
+            #[cfg(target_arch = "x86_64")]
+            SimdExtension::AVX => "avx",
+            #[cfg(target_arch = "x86_64")]
+            SimdExtension::SSE2 => "sse2",
+            #[cfg(target_arch = "aarch64")]
+            SimdExtension::NEON => "neon",
+            SimdExtension::None => "rust",
+        }
+    }
+
+    /// Whether this CPU has the instructions of the backend.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            SimdExtension::AVX512f => is_x86_feature_detected!("avx512f"),
+            #[cfg(target_arch = "x86_64")]
+            SimdExtension::AVX2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            SimdExtension::AVX => is_x86_feature_detected!("avx"),
+            #[cfg(target_arch = "x86_64")]
+            SimdExtension::SSE2 => is_x86_feature_detected!("sse2"),
+            #[cfg(target_arch = "aarch64")]
+            SimdExtension::NEON => std::arch::is_aarch64_feature_detected!("neon"),
+            SimdExtension::None => true,
+        }
+    }
+}
+
+/// Parses a backend name, which has to be supported by this CPU.
+impl FromStr for SimdExtension {
+    type Err = HasherError;
+
+    fn from_str(name: &str) -> Result<SimdExtension, HasherError> {
+        let simd_ext = SimdExtension::ALL
+            .iter()
+            .find(|simd_ext| simd_ext.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| HasherError::UnknownSimd(name.to_string()))?;
+        if !simd_ext.is_supported() {
+            return Err(HasherError::UnsupportedSimd(simd_ext.name()));
+        }
+        Ok(simd_ext.clone())
+    }
+}
+
+/// The backends this CPU supports, widest first.
+pub fn supported_simd() -> Vec<SimdExtension> {
+    SimdExtension::ALL.iter().filter(|simd_ext| simd_ext.is_supported()).cloned().collect()
+}
+
+/// Hashes `nonces` nonces on each of `threads` threads with `simd_ext` and
+/// returns the nonces per minute. Nonces that don't fill all lanes are hashed
+/// by the scalar code, a multiple of 16 keeps every backend busy.
+pub fn bench_simd(simd_ext: &SimdExtension, threads: usize, nonces: u64) -> f64 {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for i in 0..threads as u64 {
+            scope.spawn(move || {
+                let mut cache = vec![0u8; nonces as usize * NONCE_SIZE];
+                noncegen(simd_ext, &mut cache, 0, 0, i * nonces, nonces);
+            });
+        }
+    });
+    (threads as u64 * nonces) as f64 * 60.0 / start.elapsed().as_secs_f64()
+}
+
+/// Benchmarks the SIMD backends on `threads` threads and returns the fastest
+/// one, which isn't always the widest, e.g. when AVX512 lowers the clock.
+pub fn fastest_simd(threads: usize) -> SimdExtension {
+    let candidates: Vec<SimdExtension> = supported_simd()
+        .into_iter()
+        .filter(|simd_ext| !matches!(simd_ext, SimdExtension::None))
+        .collect();
+    if candidates.len() < 2 {
+        return init_simd();
+    }
+    candidates
+        .into_iter()
+        .map(|simd_ext| {
+            let speed = bench_simd(&simd_ext, threads, 16);
+            (simd_ext, speed)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(simd_ext, _)| simd_ext)
+        .unwrap()
+}
+
+/// Resolves a `--simd` value, `auto` is [`init_simd`] and `fastest` is
+/// [`fastest_simd`] on `threads` threads.
+pub fn select_simd(name: &str, threads: usize) -> Result<SimdExtension, HasherError> {
+    match name {
+        "auto" => Ok(init_simd()),
+        "fastest" => Ok(fastest_simd(threads)),
+        name => name.parse(),
+    }
+}
+
+/// Hashes `local_nonces` nonces starting at `local_startnonce` into `cache`
+/// using the given SIMD extension.
+///
+/// `cache` is a scoop-major buffer holding `cache.len() / NONCE_SIZE` nonces,
+/// the new nonces are placed at nonce offset `chunk_offset`. Panics if this
+/// CPU lacks the instructions of `simd_ext`, see
+/// [`SimdExtension::is_supported`].
+pub fn noncegen(
+    simd_ext: &SimdExtension,
+    cache: &mut [u8],
+    chunk_offset: usize,
+    numeric_id: u64,
+    local_startnonce: u64,
+    local_nonces: u64,
+) {
+    let cache_size = cache.len() / NONCE_SIZE;
+    assert!(
+        chunk_offset + local_nonces as usize <= cache_size,
+        "nonces exceed cache size"
+    );
+
+    // the detection is cached, checking it per call is cheap and keeps the
+    // `target_feature` backends below sound
+    assert!(simd_ext.is_supported(), "this CPU doesn't support {}", simd_ext.name());
+    match simd_ext {
+        #[cfg(target_arch = "x86_64")]
+        SimdExtension::AVX512f => unsafe {
+            noncegen_avx512f(cache, chunk_offset, numeric_id, local_startnonce, local_nonces)
+        },
+        #[cfg(target_arch = "x86_64")]
+        SimdExtension::AVX2 => unsafe {
+            noncegen_avx2(cache, chunk_offset, numeric_id, local_startnonce, local_nonces)
+        },
+        #[cfg(target_arch = "x86_64")]
+        SimdExtension::AVX => unsafe {
+            noncegen_avx(cache, chunk_offset, numeric_id, local_startnonce, local_nonces)
+        },
+        #[cfg(target_arch = "x86_64")]
+        SimdExtension::SSE2 => unsafe {
+            noncegen_sse2(cache, chunk_offset, numeric_id, local_startnonce, local_nonces)
+        },
+        #[cfg(target_arch = "aarch64")]
+        SimdExtension::NEON => unsafe {
+            noncegen_neon(cache, chunk_offset, numeric_id, local_startnonce, local_nonces)
+        },
+        SimdExtension::None => noncegen_portable::<PORTABLE_LANES>(
+            cache,
+            chunk_offset,
+            numeric_id,
+            local_startnonce,
+            local_nonces,
+        ),
+    }
+}
+
+/// [`noncegen`] with the C backends, which are built with the `c_simd`
+/// feature to compare against. Panics like [`noncegen`] on unsupported
+/// backends.
+#[cfg(all(target_arch = "x86_64", feature = "c_simd"))]
+pub fn noncegen_c(
+    simd_ext: &SimdExtension,
+    cache: &mut [u8],
+    chunk_offset: usize,
+    numeric_id: u64,
+    local_startnonce: u64,
+    local_nonces: u64,
+) {
+    use libc::c_void;
+    use std::sync::Once;
+
+    static INIT: [Once; 4] = [Once::new(), Once::new(), Once::new(), Once::new()];
+
+    let cache_size = cache.len() / NONCE_SIZE;
+    assert!(
+        chunk_offset + local_nonces as usize <= cache_size,
+        "nonces exceed cache size"
+    );
+    assert!(simd_ext.is_supported(), "this CPU doesn't support {}", simd_ext.name());
+    let cache_ptr = cache.as_mut_ptr() as *mut c_void;
+
+    unsafe {
+        match simd_ext {
+            SimdExtension::AVX512f => {
+                INIT[0].call_once(|| c::init_shabal_avx512f());
+                c::noncegen_avx512f(cache_ptr, cache_size, chunk_offset, numeric_id, local_startnonce, local_nonces)
+            }
+            SimdExtension::AVX2 => {
+                INIT[1].call_once(|| c::init_shabal_avx2());
+                c::noncegen_avx2(cache_ptr, cache_size, chunk_offset, numeric_id, local_startnonce, local_nonces)
+            }
+            SimdExtension::AVX => {
+                INIT[2].call_once(|| c::init_shabal_avx());
+                c::noncegen_avx(cache_ptr, cache_size, chunk_offset, numeric_id, local_startnonce, local_nonces)
+            }
+            SimdExtension::SSE2 => {
+                INIT[3].call_once(|| c::init_shabal_sse2());
+                c::noncegen_sse2(cache_ptr, cache_size, chunk_offset, numeric_id, local_startnonce, local_nonces)
+            }
+            SimdExtension::None => {
+                crate::poc_hashing::noncegen_rust(cache, chunk_offset, numeric_id, local_startnonce, local_nonces)
+            }
+        }
+    }
+}
+
 pub fn hash_cpu(
     tx: Sender<(u8, u8, u64)>,
     hasher_task: CpuTask,
@@ -113,58 +357,15 @@ pub fn hash_cpu(
 
     move || {
         let cache_ptr = cache_ptr_as_usize as *mut u8;
-
-        unsafe {
-            match simd_ext {
-                #[cfg(target_arch = "x86_64")]
-                SimdExtension::AVX512f => noncegen_avx512f(
-                    cache_ptr as *mut c_void,
-                    cache_size,
-                    chunk_offset,
-                    numeric_id,
-                    local_startnonce,
-                    local_nonces,
-                ),
-                #[cfg(target_arch = "x86_64")]
-                SimdExtension::AVX2 => noncegen_avx2(
-                    cache_ptr as *mut c_void,
-                    cache_size,
-                    chunk_offset,
-                    numeric_id,
-                    local_startnonce,
-                    local_nonces,
-                ),
-                #[cfg(target_arch = "x86_64")]
-                SimdExtension::AVX => noncegen_avx(
-                    cache_ptr as *mut c_void,
-                    cache_size,
-                    chunk_offset,
-                    numeric_id,
-                    local_startnonce,
-                    local_nonces,
-                ),
-                #[cfg(target_arch = "x86_64")]
-                SimdExtension::SSE2 => noncegen_sse2(
-                    cache_ptr as *mut c_void,
-                    cache_size,
-                    chunk_offset,
-                    numeric_id,
-                    local_startnonce,
-                    local_nonces,
-                ),
-
-                _ => {
-                    let data = from_raw_parts_mut(cache_ptr, cache_size * NONCE_SIZE);
-                    noncegen_rust(
-                        data,
-                        chunk_offset,
-                        numeric_id,
-                        local_startnonce,
-                        local_nonces,
-                    )
-                }
-            }
-        }
+        let data = unsafe { from_raw_parts_mut(cache_ptr, cache_size * NONCE_SIZE) };
+        noncegen(
+            &simd_ext,
+            data,
+            chunk_offset,
+            numeric_id,
+            local_startnonce,
+            local_nonces,
+        );
 
         let _ = tx.send((0u8, 1u8, 0));
         let _ = tx.send((0u8, 0u8, local_nonces));
@@ -178,6 +379,7 @@ mod test {
     use self::crypto::sha2::Sha256;
     use super::*;
     use crate::hasher;
+    use crate::poc_hashing::noncegen_rust;
 
     #[test]
     fn test_noncegen() {
@@ -192,75 +394,68 @@ mod test {
         };
 
         #[cfg(target_arch = "x86_64")]
-        if is_x86_feature_detected!("avx512f") {
+        for (supported, simd_ext) in [
+            (is_x86_feature_detected!("avx512f"), SimdExtension::AVX512f),
+            (is_x86_feature_detected!("avx2"), SimdExtension::AVX2),
+            (is_x86_feature_detected!("avx"), SimdExtension::AVX),
+            (is_x86_feature_detected!("sse2"), SimdExtension::SSE2),
+        ] {
             let mut buf = vec![0; 32 * hasher::NONCE_SIZE as usize];
-            unsafe {
-                init_shabal_avx512f();
-                noncegen_avx512f(
-                    buf.as_mut_ptr() as *mut c_void,
-                    32,
-                    0,
-                    numeric_id,
-                    start_nonce,
-                    32,
-                );
+            if !supported {
+                // refused instead of running instructions the CPU lacks
+                let result = std::panic::catch_unwind(move || {
+                    noncegen(&simd_ext, &mut buf, 0, numeric_id, start_nonce, 32)
+                });
+                assert!(result.is_err());
+                continue;
             }
+            noncegen(&simd_ext, &mut buf, 0, numeric_id, start_nonce, 32);
             check_result(&buf);
-        }
 
-        #[cfg(target_arch = "x86_64")]
-        if is_x86_feature_detected!("avx2") {
-            let mut buf = vec![0; 32 * hasher::NONCE_SIZE as usize];
-            unsafe {
-                init_shabal_avx2();
-                noncegen_avx2(
-                    buf.as_mut_ptr() as *mut c_void,
-                    32,
-                    0,
-                    numeric_id,
-                    start_nonce,
-                    32,
-                );
+            #[cfg(feature = "c_simd")]
+            {
+                let mut buf = vec![0; 32 * hasher::NONCE_SIZE as usize];
+                noncegen_c(&simd_ext, &mut buf, 0, numeric_id, start_nonce, 32);
+                check_result(&buf);
             }
-            check_result(&buf);
         }
 
-        #[cfg(target_arch = "x86_64")]
-        if is_x86_feature_detected!("avx") {
+        let mut buf = vec![0; 32 * hasher::NONCE_SIZE as usize];
+        noncegen(&SimdExtension::None, &mut buf, 0, numeric_id, start_nonce, 32);
+        check_result(&buf);
+
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
             let mut buf = vec![0; 32 * hasher::NONCE_SIZE as usize];
-            unsafe {
-                init_shabal_avx();
-                noncegen_avx(
-                    buf.as_mut_ptr() as *mut c_void,
-                    32,
-                    0,
-                    numeric_id,
-                    start_nonce,
-                    32,
-                );
-            }
+            noncegen(&SimdExtension::NEON, &mut buf, 0, numeric_id, start_nonce, 32);
             check_result(&buf);
         }
 
-        #[cfg(target_arch = "x86_64")]
-        if is_x86_feature_detected!("sse2") {
-            let mut buf = vec![0; 32 * hasher::NONCE_SIZE as usize];
-            unsafe {
-                init_shabal_sse2();
-                noncegen_sse2(
-                    buf.as_mut_ptr() as *mut c_void,
-                    32,
-                    0,
-                    numeric_id,
-                    start_nonce,
-                    32,
-                );
-            }
-            check_result(&buf);
+        // a part of a larger buffer with nonces left over for the fallback
+        let init = [0xaa; hasher::NONCE_SIZE as usize];
+        let mut buf = vec![0xaa; 64 * hasher::NONCE_SIZE as usize];
+        noncegen(&init_simd(), &mut buf, 7, numeric_id, start_nonce, 21);
+        noncegen(&init_simd(), &mut buf, 28, numeric_id, start_nonce + 21, 11);
+        let mut expected = vec![0; 32 * hasher::NONCE_SIZE as usize];
+        noncegen_rust(&mut expected, 0, numeric_id, start_nonce, 32);
+        for (scoop, (row, expected)) in buf.chunks_exact(64 * 64).zip(expected.chunks_exact(32 * 64)).enumerate() {
+            assert_eq!(&row[7 * 64..39 * 64], expected, "scoop {}", scoop);
+            assert_eq!(&row[..7 * 64], &init[..7 * 64]);
+            assert_eq!(&row[39 * 64..], &init[..25 * 64]);
         }
 
         let mut buf = vec![0; 32 * hasher::NONCE_SIZE as usize];
         noncegen_rust(&mut buf, 0, numeric_id, start_nonce, 32);
         check_result(&buf);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_select_simd() {
+        assert!(matches!(select_simd("rust", 1), Ok(SimdExtension::None)));
+        assert!(matches!(select_simd("avx9", 1), Err(HasherError::UnknownSimd(_))));
+        for simd_ext in SimdExtension::ALL {
+            let parsed = simd_ext.name().to_uppercase().parse::<SimdExtension>();
+            assert_eq!(parsed.is_ok(), simd_ext.is_supported());
+        }
+    }
+}
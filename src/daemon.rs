@@ -0,0 +1,398 @@
+//! A queue of plotting jobs in a directory, worked off by `anne-hasher daemon`.
+//!
+//! Every `NAME.json` in the queue directory is a job, a [`TaskSpec`]. Jobs run
+//! in the order of their names, one at a time or with
+//! [`DaemonTask::per_disk`] one per disk. How a job went is recorded in
+//! `NAME.state` next to it, see [`JobRecord`]. Jobs that were running or got
+//! stopped run again on the next start and resume their plot files, done and
+//! failed ones are skipped until their `.state` file is deleted.
+
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents};
+use crate::hasher::{HashReport, Hasher};
+use crate::resume::{read_resume_info, write_atomically};
+use crate::task_spec::TaskSpec;
+use crate::utils::{parse_plot_file_name, plot_file_name};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the queue directory is scanned for new jobs.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct DaemonTask {
+    pub queue_dir: PathBuf,
+    /// Jobs on different disks run at the same time, jobs sharing a disk
+    /// still run one after another.
+    pub per_disk: bool,
+    /// Returns once no job is left instead of waiting for new ones.
+    pub once: bool,
+    /// Stops the running jobs after their current buffer, they resume on
+    /// the next start.
+    pub stop_flag: Option<Arc<AtomicBool>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done,
+    Failed,
+}
+
+/// What the daemon knows about a job, `NAME.state` next to `NAME.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub state: JobState,
+    /// Start nonce and nonces per file of the first run, later runs resume
+    /// the same files.
+    pub start_nonce: u64,
+    pub nonces: u64,
+    pub files: Vec<PathBuf>,
+    pub attempts: u32,
+    /// Nonces hashed over all runs.
+    pub hashed_nonces: u64,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Unix time of the first start and of the end of the last run.
+    pub started_at: u64,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+}
+
+impl JobRecord {
+    fn read(file: &Path) -> Result<Option<JobRecord>, String> {
+        match fs::read(file) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn write(&self, file: &Path) -> Result<(), HasherError> {
+        let json = serde_json::to_vec_pretty(self).unwrap();
+        write_atomically(file, &[&json]).map_err(|source| HasherError::JobQueue { path: file.to_path_buf(), source })
+    }
+}
+
+/// Jobs done and failed by [`run_daemon`].
+#[derive(Debug, Clone, Default)]
+pub struct DaemonReport {
+    pub done: usize,
+    pub failed: usize,
+    /// Whether the daemon ended because the stop flag was raised.
+    pub stopped: bool,
+}
+
+impl fmt::Display for DaemonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} job(s) done, {} failed", self.done, self.failed)?;
+        if self.stopped {
+            write!(f, ", stopped")?;
+        }
+        Ok(())
+    }
+}
+
+struct Job {
+    name: String,
+    spec: Result<TaskSpec, String>,
+    record: Result<Option<JobRecord>, String>,
+    state_file: PathBuf,
+}
+
+struct RunningJob {
+    record: JobRecord,
+    state_file: PathBuf,
+    disks: Vec<String>,
+}
+
+/// Runs the jobs of `task.queue_dir` until the stop flag is raised, or with
+/// `task.once` until none is left. `events` gets the events of every job,
+/// with [`DaemonTask::per_disk`] only those that don't draw on the terminal.
+pub fn run_daemon(task: &DaemonTask, events: Arc<dyn HasherEvents>) -> Result<DaemonReport, HasherError> {
+    let stop_flag = task.stop_flag.clone().unwrap_or_default();
+    let log = |msg: String| events.on_event(&HasherEvent::Log(msg));
+    let warn = |msg: String| events.on_event(&HasherEvent::Warning(msg));
+    let job_events: Arc<dyn HasherEvents> = if task.per_disk {
+        Arc::new(ConcurrentEvents(events.clone()))
+    } else {
+        events.clone()
+    };
+
+    let (tx, rx) = channel::<(String, Result<HashReport, HasherError>)>();
+    let mut running: HashMap<String, RunningJob> = HashMap::new();
+    let mut warned = HashSet::new();
+    let mut report = DaemonReport::default();
+
+    log(format!("Watching {} for jobs...", task.queue_dir.display()));
+    loop {
+        let stopping = stop_flag.load(Ordering::Relaxed);
+        let mut started = false;
+        if !stopping {
+            let jobs = scan_queue(&task.queue_dir)?;
+            for job in &jobs {
+                if running.contains_key(&job.name) || (!task.per_disk && !running.is_empty()) {
+                    continue;
+                }
+                let (spec, record) = match (&job.spec, &job.record) {
+                    (_, Ok(Some(record))) if matches!(record.state, JobState::Done | JobState::Failed) => continue,
+                    (Ok(spec), Ok(record)) => (spec, record),
+                    (Err(e), _) | (_, Err(e)) => {
+                        if warned.insert(job.name.clone()) {
+                            warn(format!("skipping job {}: {}", job.name, e));
+                        }
+                        continue;
+                    }
+                };
+                let disks: Vec<String> = spec.paths.iter().map(|path| disk_of(path)).collect();
+                if running.values().any(|other| other.disks.iter().any(|disk| disks.contains(disk))) {
+                    continue;
+                }
+
+                let hasher_task = spec.validate().map_err(HasherError::InvalidJob).and_then(|_| {
+                    let mut spec = spec.clone();
+                    if let Some(record) = record {
+                        spec.start_nonce = Some(record.start_nonce);
+                    }
+                    let mut hasher_task = spec.to_task(stop_flag.clone(), events.as_ref())?;
+                    // concurrent jobs of one ID on other disks must not get the same nonces
+                    if spec.start_nonce.is_none() {
+                        let records = jobs
+                            .iter()
+                            .filter_map(|job| job.record.as_ref().ok().and_then(Option::as_ref))
+                            .chain(running.values().map(|job| &job.record));
+                        hasher_task.start_nonce = hasher_task.start_nonce.max(reserved_end(records, spec.numeric_id));
+                    }
+                    Ok(hasher_task)
+                });
+                let mut record = record.clone().unwrap_or_else(|| JobRecord {
+                    state: JobState::Running,
+                    start_nonce: 0,
+                    nonces: 0,
+                    files: Vec::new(),
+                    attempts: 0,
+                    hashed_nonces: 0,
+                    error: None,
+                    started_at: unix_time(),
+                    finished_at: None,
+                });
+                record.attempts += 1;
+                let hasher_task = match hasher_task {
+                    Ok(hasher_task) => hasher_task,
+                    Err(e) => {
+                        warn(format!("job {} failed: {}", job.name, e));
+                        record.state = JobState::Failed;
+                        record.error = Some(e.to_string());
+                        record.finished_at = Some(unix_time());
+                        record.write(&job.state_file)?;
+                        report.failed += 1;
+                        continue;
+                    }
+                };
+
+                if record.attempts > 1 {
+                    let on_disk: u64 = record.files.iter().filter_map(|file| read_resume_info(file).ok()).sum();
+                    log(format!(
+                        "Resuming job {}, {} of {} nonces on disk",
+                        job.name,
+                        on_disk,
+                        record.nonces * record.files.len() as u64
+                    ));
+                } else {
+                    log(format!("Starting job {}", job.name));
+                }
+                record.state = JobState::Running;
+                record.error = None;
+                record.start_nonce = hasher_task.start_nonce;
+                record.nonces = hasher_task.nonces;
+                record.files = hasher_task
+                    .output_paths
+                    .iter()
+                    .enumerate()
+                    .map(|(i, path)| {
+                        let start_nonce = hasher_task.start_nonce + i as u64 * hasher_task.nonces;
+                        Path::new(path).join(plot_file_name(hasher_task.numeric_id, start_nonce, hasher_task.nonces))
+                    })
+                    .collect();
+                record.write(&job.state_file)?;
+
+                let hasher = Hasher::with_events(job_events.clone());
+                let (tx, name) = (tx.clone(), job.name.clone());
+                thread::spawn(move || {
+                    let _ = tx.send((name, hasher.run(hasher_task)));
+                });
+                running.insert(job.name.clone(), RunningJob { record, state_file: job.state_file.clone(), disks });
+                started = true;
+            }
+        }
+
+        if running.is_empty() && (stopping || (task.once && !started)) {
+            break;
+        }
+        let (name, result) = match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(finished) => finished,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => unreachable!("the daemon holds a sender"),
+        };
+
+        let RunningJob { mut record, state_file, .. } = running.remove(&name).unwrap();
+        record.finished_at = Some(unix_time());
+        match result {
+            Ok(hash_report) => {
+                record.hashed_nonces += hash_report.hashed_nonces;
+                if hash_report.stopped {
+                    record.state = JobState::Stopped;
+                    log(format!("Job {} stopped, it resumes on the next start", name));
+                } else {
+                    record.state = JobState::Done;
+                    report.done += 1;
+                    log(format!(
+                        "Job {} done, {} nonces at {:.2} MiB/s",
+                        name,
+                        hash_report.hashed_nonces,
+                        hash_report.mib_per_sec()
+                    ));
+                }
+            }
+            Err(e) => {
+                record.state = JobState::Failed;
+                record.error = Some(e.to_string());
+                report.failed += 1;
+                warn(format!("job {} failed: {}", name, e));
+            }
+        }
+        record.write(&state_file)?;
+    }
+
+    report.stopped = stop_flag.load(Ordering::Relaxed);
+    Ok(report)
+}
+
+/// The jobs in `dir`, ordered by name.
+fn scan_queue(dir: &Path) -> Result<Vec<Job>, HasherError> {
+    let queue_error = |source| HasherError::JobQueue { path: dir.to_path_buf(), source };
+    let mut jobs = Vec::new();
+    for entry in fs::read_dir(dir).map_err(queue_error)? {
+        let path = entry.map_err(queue_error)?.path();
+        if path.extension().is_none_or(|ext| ext != "json") || !path.is_file() {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let spec = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| serde_json::from_slice::<TaskSpec>(&bytes).map_err(|e| e.to_string()));
+        let state_file = path.with_extension("state");
+        jobs.push(Job {
+            name: name.to_string(),
+            spec,
+            record: JobRecord::read(&state_file),
+            state_file,
+        });
+    }
+    jobs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(jobs)
+}
+
+/// First nonce of `numeric_id` after the files of every job that has started.
+fn reserved_end<'a>(records: impl Iterator<Item = &'a JobRecord>, numeric_id: u64) -> u64 {
+    records
+        .flat_map(|record| &record.files)
+        .filter_map(|file| parse_plot_file_name(file.file_name()?.to_str()?))
+        .filter(|(id, _, _)| *id == numeric_id)
+        .map(|(_, start_nonce, nonces)| start_nonce + nonces)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Identifies the disk `path` is on, paths on one disk give the same string.
+#[cfg(unix)]
+fn disk_of(path: &str) -> String {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).map(|meta| meta.dev().to_string()).unwrap_or_else(|_| path.to_string())
+}
+
+#[cfg(not(unix))]
+fn disk_of(path: &str) -> String {
+    fs::canonicalize(path)
+        .ok()
+        .and_then(|path| path.components().next().map(|c| c.as_os_str().to_string_lossy().to_uppercase()))
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+/// Passes on what several jobs at once can report side by side, progress and
+/// per-run lines would interleave on the terminal.
+struct ConcurrentEvents(Arc<dyn HasherEvents>);
+
+impl HasherEvents for ConcurrentEvents {
+    fn on_event(&self, event: &HasherEvent) {
+        if let HasherEvent::Warning(_)
+        | HasherEvent::DeviceHashed { .. }
+        | HasherEvent::HasherStall(_)
+        | HasherEvent::WriterStall(_)
+        | HasherEvent::Checkpoint { .. }
+        | HasherEvent::WriteError { .. } = event
+        {
+            self.0.on_event(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::NoEvents;
+    use crate::resume::ResumeInfo;
+
+    #[test]
+    fn test_run_daemon() {
+        let dir = std::env::temp_dir().join(format!("anne-hasher-daemon-{}", std::process::id()));
+        let (queue, plots) = (dir.join("queue"), dir.join("plots"));
+        fs::create_dir_all(&queue).unwrap();
+        fs::create_dir_all(&plots).unwrap();
+
+        let job = |nonces: u64| {
+            format!(
+                r#"{{"numeric_id": 42, "nonces": {}, "paths": [{:?}], "mem": "8MiB", "cpu_threads": 1, "direct_io": false}}"#,
+                nonces,
+                plots.display().to_string()
+            )
+        };
+        fs::write(queue.join("1-first.json"), job(8)).unwrap();
+        fs::write(queue.join("2-second.json"), job(4)).unwrap();
+        fs::write(queue.join("3-broken.json"), job(0)).unwrap();
+        fs::write(queue.join("notes.txt"), "not a job").unwrap();
+
+        let task = DaemonTask { queue_dir: queue.clone(), per_disk: false, once: true, stop_flag: None };
+        let report = run_daemon(&task, Arc::new(NoEvents)).unwrap();
+        assert_eq!((report.done, report.failed), (2, 1));
+
+        let first = JobRecord::read(&queue.join("1-first.state")).unwrap().unwrap();
+        let second = JobRecord::read(&queue.join("2-second.state")).unwrap().unwrap();
+        let broken = JobRecord::read(&queue.join("3-broken.state")).unwrap().unwrap();
+        assert_eq!((first.state, first.start_nonce, first.hashed_nonces), (JobState::Done, 0, 8));
+        assert_eq!((second.state, second.start_nonce), (JobState::Done, 8));
+        assert_eq!(broken.state, JobState::Failed);
+        assert!(ResumeInfo::read(&second.files[0]).unwrap().complete);
+
+        // finished jobs aren't run again
+        let report = run_daemon(&task, Arc::new(NoEvents)).unwrap();
+        assert_eq!((report.done, report.failed), (0, 0));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents};
+use crate::hasher::{NONCE_SIZE, NUM_SCOOPS, SCOOP_SIZE};
+use crate::resume::ResumeInfo;
+use crate::shabal256::shabal256;
+use crate::utils::{open_r, parse_plot_file_name};
+use std::cmp::min;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+// nonces whose scoops are read at once, 16 MiB
+const READ_NONCES: u64 = 262144;
+
+pub struct DeadlineTask {
+    pub file: PathBuf,
+    pub gensig: [u8; 32],
+    pub height: u64,
+    /// Scales the raw deadlines to seconds like a miner does, if given.
+    pub base_target: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadlineReport {
+    pub file: PathBuf,
+    pub numeric_id: u64,
+    pub height: u64,
+    pub scoop: u64,
+    /// Nonces on disk according to the resume info, only these are mined.
+    pub checked_nonces: u64,
+    /// Best nonce and its raw deadline, `None` if no nonce is on disk yet.
+    pub best: Option<(u64, u64)>,
+    pub base_target: Option<u64>,
+}
+
+impl DeadlineReport {
+    /// The best deadline in seconds, needs a base target.
+    pub fn deadline(&self) -> Option<u64> {
+        match (self.best, self.base_target) {
+            (Some((_, raw)), Some(base_target)) if base_target > 0 => Some(raw / base_target),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DeadlineReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: height {}, scoop {}, {} nonces",
+            self.file.display(),
+            self.height,
+            self.scoop,
+            self.checked_nonces
+        )?;
+        match (self.best, self.deadline()) {
+            (None, _) => write!(f, ", nothing to mine"),
+            (Some((nonce, _)), Some(deadline)) => write!(
+                f,
+                ", best nonce {}, deadline {}s ({}d {:02}:{:02}:{:02})",
+                nonce,
+                deadline,
+                deadline / 86400,
+                deadline / 3600 % 24,
+                deadline / 60 % 60,
+                deadline % 60
+            ),
+            (Some((nonce, raw)), None) => write!(f, ", best nonce {}, raw deadline {}", nonce, raw),
+        }
+    }
+}
+
+/// Scoop of every nonce that is mined at `height`.
+pub fn calculate_scoop(gensig: &[u8; 32], height: u64) -> u64 {
+    let mut data = [0u8; 40];
+    data[..32].copy_from_slice(gensig);
+    data[32..].copy_from_slice(&height.to_be_bytes());
+    let hash = shabal256(&data);
+    u64::from(u16::from_be_bytes([hash[30], hash[31]])) % NUM_SCOOPS
+}
+
+/// Raw deadline of a 64 byte scoop, before dividing by the base target.
+pub fn calculate_deadline(gensig: &[u8; 32], scoop_data: &[u8]) -> u64 {
+    let mut data = [0u8; 32 + SCOOP_SIZE as usize];
+    data[..32].copy_from_slice(gensig);
+    data[32..].copy_from_slice(&scoop_data[..SCOOP_SIZE as usize]);
+    let hash = shabal256(&data);
+    u64::from_le_bytes(hash[..8].try_into().unwrap())
+}
+
+/// Parses a generation signature given as 64 hex digits.
+pub fn parse_gensig(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut gensig = [0u8; 32];
+    for (byte, digits) in gensig.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(gensig)
+}
+
+/// Mines a plot file at one block like a miner would: reads the scoop of
+/// the block for every nonce and returns the best deadline.
+///
+/// Only the nonces covered by the resume info are mined, so partially written
+/// files can be checked as well.
+pub fn find_best_deadline(task: &DeadlineTask, events: &dyn HasherEvents) -> Result<DeadlineReport, HasherError> {
+    let file = task.file.as_path();
+    let (numeric_id, start_nonce, nonces) = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_plot_file_name)
+        .ok_or_else(|| HasherError::InvalidPlotFileName(file.to_path_buf()))?;
+    let read_error = |source| HasherError::ReadPlotFile { file: file.to_path_buf(), source };
+
+    let size = file.metadata().map_err(read_error)?.len();
+    if size != nonces * NONCE_SIZE {
+        return Err(HasherError::PlotFileSize {
+            file: file.to_path_buf(),
+            expected: nonces * NONCE_SIZE,
+            actual: size,
+        });
+    }
+
+    // files without resume info weren't written by us, treat them as complete
+    let info = ResumeInfo::read_optional(file)
+        .map_err(|source| HasherError::ReadResumeInfo { file: file.to_path_buf(), source })?;
+    let written_nonces = info.as_ref().map_or(nonces, |info| info.progress);
+
+    let scoop = calculate_scoop(&task.gensig, task.height);
+    let open_row = |scoop: u64| {
+        let mut plot = open_r(file)?;
+        plot.seek(SeekFrom::Start(scoop * nonces * SCOOP_SIZE))?;
+        Ok(plot)
+    };
+    let mut plot = open_row(scoop).map_err(read_error)?;
+    // PoC1 files keep the second hash of a scoop in its mirror scoop
+    let mut mirror = if info.is_some_and(|info| info.poc1) {
+        Some(open_row(NUM_SCOOPS - 1 - scoop).map_err(read_error)?)
+    } else {
+        None
+    };
+
+    let mut best: Option<(u64, u64)> = None;
+    let mut buffer = vec![0u8; (min(READ_NONCES, written_nonces) * SCOOP_SIZE) as usize];
+    let mut mirror_buffer = vec![0u8; if mirror.is_some() { buffer.len() } else { 0 }];
+    let mut offset = 0;
+    while offset < written_nonces {
+        let count = min(READ_NONCES, written_nonces - offset);
+        let row = &mut buffer[..(count * SCOOP_SIZE) as usize];
+        plot.read_exact(row).map_err(read_error)?;
+        if let Some(mirror) = mirror.as_mut() {
+            let mirror_row = &mut mirror_buffer[..row.len()];
+            mirror.read_exact(mirror_row).map_err(read_error)?;
+            let half = SCOOP_SIZE as usize / 2;
+            for (data, mirror_data) in row
+                .chunks_exact_mut(SCOOP_SIZE as usize)
+                .zip(mirror_row.chunks_exact(SCOOP_SIZE as usize))
+            {
+                data[half..].copy_from_slice(&mirror_data[half..]);
+            }
+        }
+        for (n, scoop_data) in row.chunks_exact(SCOOP_SIZE as usize).enumerate() {
+            let deadline = calculate_deadline(&task.gensig, scoop_data);
+            if best.is_none_or(|(_, best_deadline)| deadline < best_deadline) {
+                best = Some((start_nonce + offset + n as u64, deadline));
+            }
+        }
+        offset += count;
+    }
+
+    let report = DeadlineReport {
+        file: file.to_path_buf(),
+        numeric_id,
+        height: task.height,
+        scoop,
+        checked_nonces: written_nonces,
+        best,
+        base_target: task.base_target,
+    };
+    events.on_event(&HasherEvent::Deadline(report.clone()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_hasher::{init_simd, noncegen};
+    use crate::events::NoEvents;
+    use crate::resume::write_resume_info;
+    use crate::utils::plot_file_name;
+    use std::fs;
+
+    #[test]
+    fn test_find_best_deadline() {
+        let gensig = parse_gensig("4a6f686e6e7946464d206861742064656e206772f6df74656e2050656e697321").unwrap();
+        // deadline of an all zero scoop known from other miners
+        assert_eq!(calculate_deadline(&gensig, &[0u8; 64]), 3084580316385335914);
+
+        let numeric_id = 7900104405094198526;
+        let (start_nonce, nonces) = (1337, 8);
+        let dir = std::env::temp_dir().join(format!("anne-hasher-deadline-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(plot_file_name(numeric_id, start_nonce, nonces));
+
+        let mut plot = vec![0u8; (nonces * NONCE_SIZE) as usize];
+        noncegen(&init_simd(), &mut plot, 0, numeric_id, start_nonce, nonces);
+        fs::write(&file, &plot).unwrap();
+        write_resume_info(&file, nonces).unwrap();
+
+        let height = 4242;
+        let scoop = calculate_scoop(&gensig, height);
+        let row = (scoop * nonces * SCOOP_SIZE) as usize;
+        let expected = plot[row..row + (nonces * SCOOP_SIZE) as usize]
+            .chunks_exact(SCOOP_SIZE as usize)
+            .enumerate()
+            .map(|(n, data)| (start_nonce + n as u64, calculate_deadline(&gensig, data)))
+            .min_by_key(|&(_, deadline)| deadline);
+
+        let task = DeadlineTask { file: file.clone(), gensig, height, base_target: Some(1000) };
+        let report = find_best_deadline(&task, &NoEvents).unwrap();
+        assert_eq!(report.scoop, scoop);
+        assert_eq!(report.best, expected);
+        assert_eq!(report.deadline(), expected.map(|(_, raw)| raw / 1000));
+
+        // a file converted to PoC1 has the same deadlines
+        let mut poc1 = plot.clone();
+        crate::convert::swap_scoop_halves(&mut poc1, nonces);
+        fs::write(&file, &poc1).unwrap();
+        let info = ResumeInfo::new(numeric_id, start_nonce, nonces, 0).completed();
+        ResumeInfo { poc1: true, ..info }.write(&file).unwrap();
+        assert_eq!(find_best_deadline(&task, &NoEvents).unwrap().best, expected);
+
+        // unreadable resume info isn't taken for a complete file
+        fs::write(crate::resume::resume_file(&file), b"garbage").unwrap();
+        assert!(matches!(find_best_deadline(&task, &NoEvents), Err(HasherError::ReadResumeInfo { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+use crate::cpu_hasher::SimdExtension;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Everything that can make a plotting run or a plot file check fail.
+#[derive(Debug)]
+pub enum HasherError {
+    /// The output directory doesn't exist.
+    PathNotFound(String),
+    /// Free space on the target drive couldn't be queried.
+    DiskSpace { path: String, source: io::Error },
+    /// The plot file doesn't fit on the target drive.
+    InsufficientDiskSpace { required: u64, available: u64 },
+    /// The physical sector size of the target drive couldn't be determined.
+    SectorSize { path: String, reason: String },
+    /// The `--mem` value couldn't be parsed.
+    InvalidMemoryLimit(String),
+    /// The `--mem` value doesn't leave room for the GPU buffers.
+    InsufficientHostMemory { required: u64, available: u64 },
+    /// An existing plot file has no readable resume info.
+    ReadResumeInfo { file: PathBuf, source: io::Error },
+    /// Resume info couldn't be written to the plot file.
+    WriteResumeInfo { file: PathBuf, source: io::Error },
+    /// Space for a new plot file couldn't be allocated.
+    Preallocate { file: PathBuf, source: io::Error },
+    /// The plot file couldn't be opened for writing.
+    OpenPlotFile { file: PathBuf, source: io::Error },
+    /// Writing nonces to the plot file failed.
+    WritePlotFile { file: PathBuf, source: io::Error },
+    /// The plot file vanished while it was being written.
+    PlotFileMissing(PathBuf),
+    /// The file name isn't `{id}_{start_nonce}_{nonces}`.
+    InvalidPlotFileName(PathBuf),
+    /// The file name isn't `{id}_{start_nonce}_{nonces}_{stagger}`.
+    InvalidStaggerFileName(PathBuf),
+    /// The file size doesn't match the nonce count in its name.
+    PlotFileSize { file: PathBuf, expected: u64, actual: u64 },
+    /// Reading an existing plot file failed.
+    ReadPlotFile { file: PathBuf, source: io::Error },
+    /// The plot file is still being written, but has to be complete.
+    PlotFileIncomplete { file: PathBuf, written: u64, nonces: u64 },
+    /// An earlier conversion already left the plot file in the requested format.
+    AlreadyConverted { file: PathBuf, format: &'static str },
+    /// The output file exists and isn't one the task can resume.
+    OutputExists(PathBuf),
+    /// Plot files can't be merged or split as requested.
+    InvalidRepack(String),
+    /// A new plot file would repeat nonces of an existing one.
+    NonceOverlap { file: PathBuf, existing: PathBuf, nonces: Range<u64> },
+    /// A staged plot file couldn't be moved to its final path.
+    MovePlotFile { file: PathBuf, dest: PathBuf, source: io::Error },
+    /// The `--simd` value isn't a known backend.
+    UnknownSimd(String),
+    /// The CPU lacks the instructions of the requested backend.
+    UnsupportedSimd(&'static str),
+    /// The HTTP API couldn't listen on its address.
+    HttpServer { addr: String, reason: String },
+    /// A config file couldn't be read or saved.
+    ReadConfig { file: PathBuf, source: io::Error },
+    WriteConfig { file: PathBuf, source: io::Error },
+    /// A config file isn't valid TOML or has invalid values, `reason` names
+    /// the offending key.
+    InvalidConfig { file: PathBuf, reason: String },
+    /// `--profile` names no profile of the config file.
+    UnknownProfile(String),
+    /// The job queue directory or a job's state file couldn't be accessed.
+    JobQueue { path: PathBuf, source: io::Error },
+    /// A queued job can't run as given.
+    InvalidJob(String),
+    /// The metrics endpoint couldn't listen on its address.
+    MetricsServer { addr: String, reason: String },
+    /// A worker thread panicked.
+    ThreadPanicked(&'static str),
+}
+
+impl fmt::Display for HasherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HasherError::PathNotFound(path) => {
+                write!(f, "specified target path does not exist, path={}", path)
+            }
+            HasherError::DiskSpace { path, source } => {
+                write!(f, "couldn't determine free disk space, path={}: {}", path, source)
+            }
+            HasherError::InsufficientDiskSpace { required, available } => write!(
+                f,
+                "insufficient disk space, MiB_required={:.2}, MiB_available={:.2}",
+                *required as f64 / 1024.0 / 1024.0,
+                *available as f64 / 1024.0 / 1024.0
+            ),
+            HasherError::SectorSize { path, reason } => {
+                write!(f, "couldn't determine sector size, path={}: {}", path, reason)
+            }
+            HasherError::InvalidMemoryLimit(input) => write!(
+                f,
+                "can't parse memory limit parameter, input={}\n\
+                 Please specify a number followed by a unit. If no unit is provided, bytes will be assumed.\n\
+                 Supported units: B, KiB, MiB, GiB, TiB, PiB, EiB, KB, MB, GB, TB, PB, EB\n\
+                 Example: --mem 10GiB",
+                input
+            ),
+            HasherError::InsufficientHostMemory { required, available } => write!(
+                f,
+                "insufficient host memory for GPU hashing, MiB_required={:.2}, MiB_available={:.2}",
+                *required as f64 / 1024.0 / 1024.0,
+                *available as f64 / 1024.0 / 1024.0
+            ),
+            HasherError::ReadResumeInfo { file, source } => write!(
+                f,
+                "couldn't read resume info from file '{}': {}\n\
+                 If you are sure that this file is incomplete or corrupted, then delete it before continuing.",
+                file.display(),
+                source
+            ),
+            HasherError::WriteResumeInfo { file, source } => {
+                write!(f, "couldn't write resume info to file '{}': {}", file.display(), source)
+            }
+            HasherError::Preallocate { file, source } => write!(
+                f,
+                "couldn't preallocate space for file '{}': {}\n\
+                 Probable causes are:\n \
+                 * fallocate() is only supported on ext4 filesystems.\n \
+                 * O_DIRECT requires aligned sizes, try running without direct I/O.\n \
+                 * Insufficient space.",
+                file.display(),
+                source
+            ),
+            HasherError::OpenPlotFile { file, source } => {
+                write!(f, "couldn't open plot file '{}': {}", file.display(), source)
+            }
+            HasherError::WritePlotFile { file, source } => {
+                write!(f, "couldn't write to plot file '{}': {}", file.display(), source)
+            }
+            HasherError::PlotFileMissing(file) => {
+                write!(f, "plot file '{}' was removed while hashing", file.display())
+            }
+            HasherError::InvalidPlotFileName(file) => write!(
+                f,
+                "'{}' is not named like a plot file ({{id}}_{{start_nonce}}_{{nonces}})",
+                file.display()
+            ),
+            HasherError::InvalidStaggerFileName(file) => write!(
+                f,
+                "'{}' is not named like a legacy plot file ({{id}}_{{start_nonce}}_{{nonces}}_{{stagger}})",
+                file.display()
+            ),
+            HasherError::PlotFileSize { file, expected, actual } => write!(
+                f,
+                "plot file '{}' has {} bytes, expected {}",
+                file.display(),
+                actual,
+                expected
+            ),
+            HasherError::ReadPlotFile { file, source } => {
+                write!(f, "couldn't read plot file '{}': {}", file.display(), source)
+            }
+            HasherError::PlotFileIncomplete { file, written, nonces } => write!(
+                f,
+                "plot file '{}' is incomplete, {} of {} nonces written",
+                file.display(),
+                written,
+                nonces
+            ),
+            HasherError::AlreadyConverted { file, format } => write!(
+                f,
+                "plot file '{}' has already been converted to {}, converting it again would swap it back",
+                file.display(),
+                format
+            ),
+            HasherError::OutputExists(file) => {
+                write!(f, "'{}' already exists and isn't an unfinished conversion of this file", file.display())
+            }
+            HasherError::InvalidRepack(reason) => write!(f, "can't repack plot files: {}", reason),
+            HasherError::NonceOverlap { file, existing, nonces } => write!(
+                f,
+                "nonces {}..{} of '{}' are already in '{}'",
+                nonces.start,
+                nonces.end - 1,
+                file.display(),
+                existing.display()
+            ),
+            HasherError::MovePlotFile { file, dest, source } => write!(
+                f,
+                "couldn't move plot file '{}' to '{}': {}",
+                file.display(),
+                dest.display(),
+                source
+            ),
+            HasherError::UnknownSimd(name) => {
+                let names: Vec<&str> = SimdExtension::ALL.iter().map(|simd_ext| simd_ext.name()).collect();
+                write!(f, "unknown SIMD extension '{}', expected one of auto, fastest, {}", name, names.join(", "))
+            }
+            HasherError::UnsupportedSimd(name) => write!(f, "this CPU doesn't support {}", name),
+            HasherError::HttpServer { addr, reason } => {
+                write!(f, "can't start the HTTP API on {}: {}", addr, reason)
+            }
+            HasherError::ReadConfig { file, source } => {
+                write!(f, "couldn't read config file '{}': {}", file.display(), source)
+            }
+            HasherError::WriteConfig { file, source } => {
+                write!(f, "couldn't save config file '{}': {}", file.display(), source)
+            }
+            HasherError::InvalidConfig { file, reason } => {
+                write!(f, "invalid config file '{}': {}", file.display(), reason)
+            }
+            HasherError::UnknownProfile(name) => write!(f, "the config file has no profile '{}'", name),
+            HasherError::JobQueue { path, source } => {
+                write!(f, "couldn't access job queue '{}': {}", path.display(), source)
+            }
+            HasherError::InvalidJob(reason) => write!(f, "invalid job: {}", reason),
+            HasherError::MetricsServer { addr, reason } => {
+                write!(f, "can't serve metrics on {}: {}", addr, reason)
+            }
+            HasherError::ThreadPanicked(name) => write!(f, "{} thread panicked", name),
+        }
+    }
+}
+
+impl Error for HasherError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HasherError::DiskSpace { source, .. }
+            | HasherError::ReadResumeInfo { source, .. }
+            | HasherError::WriteResumeInfo { source, .. }
+            | HasherError::Preallocate { source, .. }
+            | HasherError::OpenPlotFile { source, .. }
+            | HasherError::WritePlotFile { source, .. }
+            | HasherError::ReadPlotFile { source, .. }
+            | HasherError::MovePlotFile { source, .. }
+            | HasherError::ReadConfig { source, .. }
+            | HasherError::WriteConfig { source, .. }
+            | HasherError::JobQueue { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,556 @@
+use crate::convert::ConvertReport;
+use crate::deadline::DeadlineReport;
+use crate::hasher::{HashReport, RunInfo, NONCE_SIZE};
+use crate::repack::RepackReport;
+use crate::verify::VerifyReport;
+use crossbeam_channel::Sender;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Coarse stages of a plotting run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Phase {
+    /// Space for a new plot file is being allocated.
+    Preallocating,
+    /// An existing plot file is being inspected for resume info.
+    Resuming,
+    /// Hashing and writing have started; `resumed_from` nonces were already on disk.
+    Hashing { nonces: u64, resumed_from: u64 },
+    /// An existing plot file is being checked, `nonces` of it get regenerated.
+    Verifying { nonces: u64 },
+    /// A plot file is being converted between PoC1 and PoC2; `resumed_from`
+    /// nonces were converted already.
+    Converting { nonces: u64, resumed_from: u64 },
+    /// Plot files are being merged or split, `nonces` get copied in total and
+    /// `resumed_from` of them were copied already.
+    Repacking { nonces: u64, resumed_from: u64 },
+}
+
+/// Everything a running [`Hasher`](crate::Hasher) reports while it works.
+#[derive(Debug, Clone)]
+pub enum HasherEvent {
+    Phase(Phase),
+    /// The checks of a hashing run passed, the same details are logged as well.
+    Started(RunInfo),
+    /// Informational status line.
+    Log(String),
+    Warning(String),
+    /// The run failed, [`Hasher::run`](crate::Hasher::run) returns the error as well.
+    Error(String),
+    /// Nonces of the plot file that have been hashed so far, including resumed ones.
+    Hashed(u64),
+    /// Nonces of the plot file that are on disk so far, including resumed ones.
+    Written(u64),
+    /// Hashing speed in nonces per minute.
+    HashSpeed(f64),
+    /// Write speed in MiB/s.
+    WriteSpeed(f64),
+    /// A device finished `nonces` nonces, device 0 is the CPU thread pool and
+    /// device `n` the `n`-th GPU of the task.
+    DeviceHashed { device: u8, nonces: u64 },
+    /// Hashing speed of one device in nonces per minute.
+    DeviceHashSpeed { device: u8, speed: f64 },
+    /// Time hashing waited for an empty buffer.
+    HasherStall(Duration),
+    /// Time a writer waited for a full buffer.
+    WriterStall(Duration),
+    /// The resume info of a plot file now records `nonces` on disk.
+    Checkpoint { file: PathBuf, nonces: u64 },
+    /// Writing a plot file or its resume info failed. Fatal failures end the
+    /// run with an `Error` as well.
+    WriteError { file: PathBuf, error: String },
+    /// The run ended, either complete or stopped.
+    Finished(HashReport),
+    /// A plot file has been verified.
+    Verified(VerifyReport),
+    /// A staged plot file has reached its final path.
+    Moved(PathBuf),
+    /// A plot file has been mined at a block.
+    Deadline(DeadlineReport),
+    /// A plot file conversion ended, either complete or stopped.
+    Converted(ConvertReport),
+    /// Plot files have been merged or split, or the run was stopped.
+    Repacked(RepackReport),
+}
+
+/// Sink for [`HasherEvent`]s.
+///
+/// Events are emitted from the scheduler and writer threads, so implementations
+/// should return quickly.
+pub trait HasherEvents: Send + Sync {
+    fn on_event(&self, event: &HasherEvent);
+}
+
+/// Discards all events.
+pub struct NoEvents;
+
+impl HasherEvents for NoEvents {
+    fn on_event(&self, _event: &HasherEvent) {}
+}
+
+/// Forwards events to a channel. Progress, speed, stall and checkpoint
+/// updates are dropped instead of blocking the hasher when the channel is
+/// full.
+impl HasherEvents for Sender<HasherEvent> {
+    fn on_event(&self, event: &HasherEvent) {
+        match event {
+            HasherEvent::Hashed(_)
+            | HasherEvent::Written(_)
+            | HasherEvent::HashSpeed(_)
+            | HasherEvent::WriteSpeed(_)
+            | HasherEvent::DeviceHashed { .. }
+            | HasherEvent::DeviceHashSpeed { .. }
+            | HasherEvent::HasherStall(_)
+            | HasherEvent::WriterStall(_)
+            | HasherEvent::Checkpoint { .. } => {
+                let _ = self.try_send(event.clone());
+            }
+            _ => {
+                let _ = self.send(event.clone());
+            }
+        }
+    }
+}
+
+struct Bars {
+    mb: MultiProgress,
+    hashed: ProgressBar,
+    written: Option<ProgressBar>,
+    resumed_from: u64,
+}
+
+/// Prints status lines and draws hashing/writing progress bars on the terminal.
+#[derive(Default)]
+pub struct ConsoleEvents {
+    bars: Mutex<Option<Bars>>,
+}
+
+impl ConsoleEvents {
+    pub fn new() -> ConsoleEvents {
+        ConsoleEvents::default()
+    }
+
+    fn progress_bar(mb: &MultiProgress, len: u64, prefix: &'static str) -> ProgressBar {
+        let pb = mb.add(ProgressBar::new(len));
+        pb.set_style(ProgressStyle::default_bar()
+            .template("{prefix:>12} {wide_bar} {bytes:>8} {bytes_per_sec:>10}")
+            .expect("Failed to set template")
+            .progress_chars("██░"));
+        pb.set_prefix(prefix);
+        pb.enable_steady_tick(Duration::from_millis(200));
+        pb.tick();
+        pb
+    }
+}
+
+impl HasherEvents for ConsoleEvents {
+    fn on_event(&self, event: &HasherEvent) {
+        let mut bars = self.bars.lock().unwrap();
+        match event {
+            HasherEvent::Phase(Phase::Preallocating) => println!("Pre-allocating file, please wait..."),
+            HasherEvent::Phase(Phase::Resuming) => println!("File already exists, reading resume info..."),
+            HasherEvent::Phase(Phase::Hashing { nonces, resumed_from }) => {
+                if *resumed_from == 0 {
+                    println!("Starting hashing...\n");
+                } else {
+                    println!("Resuming hashing from nonce offset {}...\n", resumed_from);
+                }
+                let mb = MultiProgress::new();
+                let len = (nonces - resumed_from) * NONCE_SIZE;
+                *bars = Some(Bars {
+                    hashed: ConsoleEvents::progress_bar(&mb, len, "Hashing:"),
+                    written: Some(ConsoleEvents::progress_bar(&mb, len, "Writing:")),
+                    mb,
+                    resumed_from: *resumed_from,
+                });
+            }
+            HasherEvent::Phase(Phase::Verifying { nonces }) => {
+                let mb = MultiProgress::new();
+                *bars = Some(Bars {
+                    hashed: ConsoleEvents::progress_bar(&mb, nonces * NONCE_SIZE, "Verifying:"),
+                    written: None,
+                    mb,
+                    resumed_from: 0,
+                });
+            }
+            HasherEvent::Phase(Phase::Converting { nonces, resumed_from })
+            | HasherEvent::Phase(Phase::Repacking { nonces, resumed_from }) => {
+                let prefix = match event {
+                    HasherEvent::Phase(Phase::Converting { .. }) => "Converting:",
+                    _ => "Copying:",
+                };
+                let mb = MultiProgress::new();
+                *bars = Some(Bars {
+                    hashed: ConsoleEvents::progress_bar(&mb, (nonces - resumed_from) * NONCE_SIZE, prefix),
+                    written: None,
+                    mb,
+                    resumed_from: *resumed_from,
+                });
+            }
+            HasherEvent::Log(msg) => match bars.as_ref() {
+                Some(b) => {
+                    let _ = b.mb.println(msg);
+                }
+                None => println!("{}", msg),
+            },
+            HasherEvent::Warning(msg) => match bars.as_ref() {
+                Some(b) => {
+                    let _ = b.mb.println(format!("Warning: {}", msg));
+                }
+                None => eprintln!("Warning: {}", msg),
+            },
+            HasherEvent::Error(msg) => {
+                if let Some(b) = bars.take() {
+                    let _ = b.mb.clear();
+                }
+                eprintln!("Error: {}", msg);
+                eprintln!("Shutting down...");
+            }
+            HasherEvent::Hashed(nonces) => {
+                if let Some(b) = bars.as_ref() {
+                    b.hashed.set_position((nonces - b.resumed_from) * NONCE_SIZE);
+                }
+            }
+            HasherEvent::Written(nonces) => {
+                // conversions only write, their single bar tracks that
+                if let Some(b) = bars.as_ref() {
+                    b.written
+                        .as_ref()
+                        .unwrap_or(&b.hashed)
+                        .set_position((nonces - b.resumed_from) * NONCE_SIZE);
+                }
+            }
+            HasherEvent::Started(_)
+            | HasherEvent::HashSpeed(_)
+            | HasherEvent::WriteSpeed(_)
+            | HasherEvent::DeviceHashed { .. }
+            | HasherEvent::DeviceHashSpeed { .. }
+            | HasherEvent::HasherStall(_)
+            | HasherEvent::WriterStall(_)
+            | HasherEvent::Checkpoint { .. }
+            | HasherEvent::WriteError { .. }
+            | HasherEvent::Moved(_) => {}
+            HasherEvent::Finished(report) => {
+                if let Some(b) = bars.take() {
+                    let _ = b.mb.clear();
+                }
+                if report.stopped {
+                    println!("\nHashing interrupted.");
+                } else if report.hashed_nonces > 0 {
+                    println!("\n{}", report);
+                    println!("Hashing completed!");
+                }
+            }
+            HasherEvent::Verified(report) => {
+                if let Some(b) = bars.take() {
+                    let _ = b.mb.clear();
+                }
+                println!("{}", report);
+            }
+            HasherEvent::Converted(report) => {
+                if let Some(b) = bars.take() {
+                    let _ = b.mb.clear();
+                }
+                println!("{}", report);
+            }
+            HasherEvent::Repacked(report) => {
+                if let Some(b) = bars.take() {
+                    let _ = b.mb.clear();
+                }
+                println!("{}", report);
+            }
+            HasherEvent::Deadline(report) => println!("{}", report),
+        }
+    }
+}
+
+#[derive(Default)]
+struct JsonState {
+    /// Nonces of the current run and its progress, see [`HasherEvent::Hashed`].
+    nonces: u64,
+    hashed: u64,
+    written: u64,
+    hash_speed: f64,
+    write_speed: f64,
+    last_progress: Option<Instant>,
+    /// Totals of the finished runs for [`JsonEvents::summary`].
+    files: Vec<PathBuf>,
+    total_nonces: u64,
+    hashed_nonces: u64,
+    elapsed: Duration,
+}
+
+/// Prints every event as one line of JSON on stdout, for scripts that wrap the
+/// hasher. Progress is printed at most once a second.
+#[derive(Default)]
+pub struct JsonEvents {
+    state: Mutex<JsonState>,
+}
+
+impl JsonEvents {
+    pub fn new() -> JsonEvents {
+        JsonEvents::default()
+    }
+
+    /// Prints the totals of all runs and the exit status of the process, 0
+    /// when complete, 130 when stopped and anything else when failed.
+    pub fn summary(&self, exit_status: i32) {
+        let state = self.state.lock().unwrap();
+        let status = match exit_status {
+            0 => "completed",
+            130 => "stopped",
+            _ => "failed",
+        };
+        let secs = state.elapsed.as_secs_f64();
+        print_json(&json!({
+            "event": "summary",
+            "status": status,
+            "exit_status": exit_status,
+            "files": state.files,
+            "nonces": state.total_nonces,
+            "hashed_nonces": state.hashed_nonces,
+            "elapsed_secs": secs,
+            "mib_per_sec": if secs > 0.0 { (state.hashed_nonces * NONCE_SIZE) as f64 / 1048576.0 / secs } else { 0.0 },
+        }));
+    }
+
+    fn progress(state: &mut JsonState) -> Option<Value> {
+        if state.last_progress.is_some_and(|last| last.elapsed() < Duration::from_secs(1)) {
+            return None;
+        }
+        state.last_progress = Some(Instant::now());
+        Some(json!({
+            "event": "progress",
+            "nonces": state.nonces,
+            "hashed": state.hashed,
+            "written": state.written,
+            "hash_speed": state.hash_speed,
+            "write_speed": state.write_speed,
+        }))
+    }
+
+    fn to_json(state: &mut JsonState, event: &HasherEvent) -> Option<Value> {
+        let message = |event: &str, msg: &str| Some(json!({ "event": event, "message": msg.trim() }));
+        match event {
+            HasherEvent::Phase(phase) => {
+                let (name, nonces, resumed_from) = match phase {
+                    Phase::Preallocating => ("preallocating", None, None),
+                    Phase::Resuming => ("resuming", None, None),
+                    Phase::Hashing { nonces, resumed_from } => ("hashing", Some(*nonces), Some(*resumed_from)),
+                    Phase::Verifying { nonces } => ("verifying", Some(*nonces), None),
+                    Phase::Converting { nonces, resumed_from } => ("converting", Some(*nonces), Some(*resumed_from)),
+                    Phase::Repacking { nonces, resumed_from } => ("repacking", Some(*nonces), Some(*resumed_from)),
+                };
+                let mut value = json!({ "event": "phase", "phase": name });
+                if let Some(nonces) = nonces {
+                    state.nonces = nonces;
+                    state.hashed = resumed_from.unwrap_or(0);
+                    state.written = resumed_from.unwrap_or(0);
+                    state.hash_speed = 0.0;
+                    state.write_speed = 0.0;
+                    state.last_progress = None;
+                    value["nonces"] = nonces.into();
+                }
+                if let Some(resumed_from) = resumed_from {
+                    value["resumed_from"] = resumed_from.into();
+                }
+                Some(value)
+            }
+            HasherEvent::Started(info) => Some(json!({
+                "event": "started",
+                "version": env!("CARGO_PKG_VERSION"),
+                "cpu": info.cpu_name,
+                "cores": info.cores,
+                "cpu_threads": info.cpu_threads,
+                "simd": info.simd,
+                "numeric_id": info.numeric_id,
+                "start_nonce": info.start_nonce,
+                "nonces": info.nonces,
+                "files": info.files,
+                "memory": {
+                    "available": info.memory.available,
+                    "usable": info.memory.usable,
+                    "gpu": info.memory.gpu,
+                    "buffers": info.memory.buffers,
+                    "buffer_size": info.memory.mem / info.memory.buffers,
+                },
+            })),
+            HasherEvent::Log(msg) if msg.trim().is_empty() => None,
+            HasherEvent::Log(msg) => message("log", msg),
+            HasherEvent::Warning(msg) => message("warning", msg),
+            HasherEvent::Error(msg) => message("error", msg),
+            HasherEvent::Hashed(nonces) => {
+                state.hashed = *nonces;
+                JsonEvents::progress(state)
+            }
+            HasherEvent::Written(nonces) => {
+                state.written = *nonces;
+                JsonEvents::progress(state)
+            }
+            HasherEvent::HashSpeed(speed) => {
+                state.hash_speed = *speed;
+                JsonEvents::progress(state)
+            }
+            HasherEvent::WriteSpeed(speed) => {
+                state.write_speed = *speed;
+                JsonEvents::progress(state)
+            }
+            HasherEvent::WriteError { file, error } => {
+                Some(json!({ "event": "write_error", "file": file, "error": error }))
+            }
+            HasherEvent::DeviceHashed { .. }
+            | HasherEvent::DeviceHashSpeed { .. }
+            | HasherEvent::HasherStall(_)
+            | HasherEvent::WriterStall(_)
+            | HasherEvent::Checkpoint { .. } => None,
+            HasherEvent::Finished(report) => {
+                state.files.extend(report.files.iter().cloned());
+                state.total_nonces += report.nonces;
+                state.hashed_nonces += report.hashed_nonces;
+                state.elapsed += report.elapsed;
+                Some(json!({
+                    "event": "finished",
+                    "status": if report.stopped { "stopped" } else { "completed" },
+                    "files": report.files,
+                    "nonces": report.nonces,
+                    "resumed_from": report.resumed_from,
+                    "hashed_nonces": report.hashed_nonces,
+                    "elapsed_secs": report.elapsed.as_secs_f64(),
+                    "mib_per_sec": report.mib_per_sec(),
+                    "nonces_per_minute": report.nonces_per_minute(),
+                    "hasher_stall_secs": report.hasher_stall.as_secs_f64(),
+                    "writer_stall_secs": report.writer_stalls.iter().map(Duration::as_secs_f64).collect::<Vec<_>>(),
+                }))
+            }
+            HasherEvent::Moved(file) => Some(json!({ "event": "moved", "file": file })),
+            HasherEvent::Verified(r) => Some(json!({
+                "event": "verified",
+                "status": if r.is_ok() { "ok" } else { "bad" },
+                "file": r.file,
+                "numeric_id": r.numeric_id,
+                "start_nonce": r.start_nonce,
+                "nonces": r.nonces,
+                "written_nonces": r.written_nonces,
+                "checked_nonces": r.checked_nonces,
+                "bad_nonces": r.bad_nonces(),
+                "bad_ranges": r
+                    .bad_ranges
+                    .iter()
+                    .map(|range| json!({ "start": range.start, "end": range.end }))
+                    .collect::<Vec<_>>(),
+                "summary": r.to_string(),
+            })),
+            HasherEvent::Deadline(r) => Some(json!({
+                "event": "deadline",
+                "file": r.file,
+                "numeric_id": r.numeric_id,
+                "height": r.height,
+                "scoop": r.scoop,
+                "checked_nonces": r.checked_nonces,
+                "best_nonce": r.best.map(|(nonce, _)| nonce),
+                "raw_deadline": r.best.map(|(_, raw)| raw),
+                "deadline": r.deadline(),
+                "summary": r.to_string(),
+            })),
+            HasherEvent::Converted(r) => Some(json!({
+                "event": "converted",
+                "status": if r.stopped { "stopped" } else { "completed" },
+                "file": r.file,
+                "output": r.output,
+                "nonces": r.nonces,
+                "resumed_from": r.resumed_from,
+                "converted_nonces": r.converted_nonces,
+                "elapsed_secs": r.elapsed.as_secs_f64(),
+                "mib_per_sec": r.mib_per_sec(),
+                "summary": r.to_string(),
+            })),
+            HasherEvent::Repacked(r) => Some(json!({
+                "event": "repacked",
+                "status": if r.stopped { "stopped" } else { "completed" },
+                "files": r.files,
+                "outputs": r.outputs,
+                "nonces": r.nonces,
+                "resumed_from": r.resumed_from,
+                "copied_nonces": r.copied_nonces,
+                "removed": r.removed,
+                "elapsed_secs": r.elapsed.as_secs_f64(),
+                "summary": r.to_string(),
+            })),
+        }
+    }
+}
+
+impl HasherEvents for JsonEvents {
+    fn on_event(&self, event: &HasherEvent) {
+        // printing under the lock keeps lines of several threads apart
+        let mut state = self.state.lock().unwrap();
+        if let Some(value) = JsonEvents::to_json(&mut state, event) {
+            print_json(&value);
+        }
+    }
+}
+
+fn print_json(value: &Value) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{}", value);
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_events() {
+        let mut state = JsonState::default();
+        let mut to_json = |event: HasherEvent| JsonEvents::to_json(&mut state, &event);
+
+        let phase = to_json(HasherEvent::Phase(Phase::Hashing { nonces: 16, resumed_from: 4 })).unwrap();
+        assert_eq!(phase, json!({ "event": "phase", "phase": "hashing", "nonces": 16, "resumed_from": 4 }));
+        assert_eq!(to_json(HasherEvent::Phase(Phase::Preallocating)).unwrap(), json!({ "event": "phase", "phase": "preallocating" }));
+        assert_eq!(to_json(HasherEvent::Warning("disk slow\n".to_string())).unwrap()["message"], "disk slow");
+        assert!(to_json(HasherEvent::Log(String::new())).is_none());
+
+        // the first update is printed, the next ones only after a second
+        let progress = to_json(HasherEvent::Hashed(8)).unwrap();
+        assert_eq!(progress["hashed"], 8);
+        assert_eq!(progress["written"], 4);
+        assert!(to_json(HasherEvent::Written(8)).is_none());
+
+        let report = HashReport {
+            files: vec![PathBuf::from("42_0_16")],
+            nonces: 16,
+            resumed_from: 4,
+            hashed_nonces: 12,
+            elapsed: Duration::from_secs(3),
+            stopped: true,
+            buffers: 2,
+            hasher_stall: Duration::ZERO,
+            writer_stalls: vec![Duration::ZERO],
+        };
+        let finished = to_json(HasherEvent::Finished(report)).unwrap();
+        assert_eq!(finished["status"], "stopped");
+        assert_eq!(finished["files"], json!(["42_0_16"]));
+
+        // reports come with their fields, not only as text
+        let report = VerifyReport {
+            file: PathBuf::from("42_0_16"),
+            numeric_id: 42,
+            start_nonce: 0,
+            nonces: 16,
+            written_nonces: 16,
+            checked_nonces: 16,
+            bad_ranges: vec![3..5, 9..10],
+        };
+        let verified = to_json(HasherEvent::Verified(report)).unwrap();
+        assert_eq!(verified["status"], "bad");
+        assert_eq!(verified["bad_nonces"], 3);
+        assert_eq!(verified["bad_ranges"][1], json!({ "start": 9, "end": 10 }));
+        assert_eq!(state.hashed_nonces, 12);
+        assert_eq!(state.elapsed, Duration::from_secs(3));
+    }
+}
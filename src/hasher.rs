@@ -1,84 +1,187 @@
 
 use humanize_rs::bytes::Bytes;
 
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 #[cfg(target_arch = "x86_64")]
 use raw_cpuid::CpuId;
 
 use crate::cpu_hasher::{SimdExtension, init_simd};
 use crate::buffer::PageAlignedByteBuffer;
+use crate::error::HasherError;
+use crate::events::{ConsoleEvents, HasherEvent, HasherEvents, Phase};
+use crate::inventory::Inventory;
 #[cfg(feature = "opencl")]
 use crate::ocl::gpu_get_info;
-use crate::scheduler::create_scheduler_thread;
+use crate::scheduler::{create_scheduler_thread, Stripe};
 #[cfg(windows)]
 use crate::utils::set_thread_ideal_processor;
-use crate::utils::{free_disk_space, get_sector_size, preallocate};
-use crate::writer::{create_writer_thread, read_resume_info, write_resume_info};
-use core_affinity;
+use crate::utils::{free_disk_space, get_sector_size, plot_file_name, preallocate};
+use crate::resume::{read_resume_info, write_resume_info};
+use crate::writer::create_writer_thread;
 use crossbeam_channel::bounded;
-#[cfg(feature = "gui")]
-use crossbeam_channel::Sender;
 use std::cmp::{max, min};
-use std::path::Path;
-use std::process;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use std::thread;
+use std::time::Duration;
 use stopwatch::Stopwatch;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 
 pub const SCOOP_SIZE: u64 = 64;
 pub const NUM_SCOOPS: u64 = 4096;
 pub const NONCE_SIZE: u64 = SCOOP_SIZE * NUM_SCOOPS;
 
-#[cfg(feature = "gui")]
+pub struct Hasher {
+    events: Arc<dyn HasherEvents>,
+}
+
+impl Default for Hasher {
+    fn default() -> Hasher {
+        Hasher::new()
+    }
+}
+/// Outcome of a [`Hasher::run`] that didn't fail.
+#[derive(Debug, Clone)]
+pub struct HashReport {
+    /// The plot files that were written, one per output path.
+    pub files: Vec<PathBuf>,
+    /// Total nonces in all plot files.
+    pub nonces: u64,
+    /// Nonces that were already on disk when the run started.
+    pub resumed_from: u64,
+    /// Nonces hashed and written by this run.
+    pub hashed_nonces: u64,
+    pub elapsed: Duration,
+    /// Whether the run ended early because the stop flag was raised.
+    pub stopped: bool,
+    /// Page-aligned buffers cycling between hashing and writing.
+    pub buffers: u64,
+    /// Time hashing waited for the writers to hand back an empty buffer.
+    pub hasher_stall: Duration,
+    /// Time each writer waited for a full buffer, in the order of `files`.
+    pub writer_stalls: Vec<Duration>,
+}
+
+impl HashReport {
+    pub fn mib_per_sec(&self) -> f64 {
+        self.hashed_nonces as f64 * 1000.0 / (self.elapsed.as_millis() as f64 + 1.0) / 4.0
+    }
+
+    pub fn nonces_per_minute(&self) -> f64 {
+        self.hashed_nonces as f64 * 1000.0 / (self.elapsed.as_millis() as f64 + 1.0) * 60.0
+    }
+}
+
+impl fmt::Display for HashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let elapsed = self.elapsed.as_secs();
+        write!(
+            f,
+            "Generated {} nonces in {}h{:02}m{:02}s, {:.2} MiB/s, {:.0} nonces/m.",
+            self.hashed_nonces,
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60,
+            self.mib_per_sec(),
+            self.nonces_per_minute()
+        )?;
+        let writer_stalls: Vec<String> = self
+            .writer_stalls
+            .iter()
+            .map(|stall| format!("{:.1}s", stall.as_secs_f64()))
+            .collect();
+        write!(
+            f,
+            "\nStalls with {} buffers: hashing waited {:.1}s for empty buffers, writing waited {} for full buffers.",
+            self.buffers,
+            self.hasher_stall.as_secs_f64(),
+            writer_stalls.join("/")
+        )
+    }
+}
+
+/// How a run splits its memory, see [`HasherEvent::Started`].
 #[derive(Debug, Clone)]
-pub enum ProgressUpdate {
-    Log(String),
-    Progress(f32),
-    WriteProgress(f32),
-    Speed(f64),
-    WriteSpeed(f64),
-    Error(String),
-    Done
+pub struct MemoryPlan {
+    /// Available memory of the system in bytes.
+    pub available: u64,
+    /// The share of it buffers may take, 75%.
+    pub usable: u64,
+    /// Host memory the GPUs need on top of the buffers.
+    pub gpu: u64,
+    /// Memory of all buffers together.
+    pub mem: u64,
+    pub buffers: u64,
 }
 
-pub struct Hasher {}
+/// What a run works with, reported once its checks passed.
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub cpu_name: String,
+    pub cores: u32,
+    pub cpu_threads: u8,
+    /// Name of the CPU hashing backend like `--simd` takes it.
+    pub simd: &'static str,
+    pub numeric_id: u64,
+    pub start_nonce: u64,
+    /// Nonces per plot file, after rounding to the sector size.
+    pub nonces: u64,
+    pub files: Vec<PathBuf>,
+    pub memory: MemoryPlan,
+}
 
 pub struct HasherTask {
     pub numeric_id: u64,
     pub start_nonce: u64,
+    /// Nonces per plot file.
     pub nonces: u64,
-    pub output_path: String,
+    /// One plot file is written to each path, all of them at the same time.
+    /// The files get consecutive nonce ranges starting at `start_nonce`.
+    pub output_paths: Vec<String>,
     pub mem: String,
+    /// Further directories with plot files the new ones must not overlap,
+    /// the output paths are always checked.
+    pub known_paths: Vec<String>,
+    /// Buffers the memory is split into, 0 uses one per output path plus one.
+    /// More buffers let hashing run ahead when a writer hiccups.
+    pub buffers: u8,
     pub cpu_threads: u8,
     pub gpus: Option<Vec<String>>,
     pub direct_io: bool,
     pub benchmark: bool,
+    /// Backend for CPU hashing, `None` uses the widest one the CPU supports.
+    pub simd: Option<SimdExtension>,
     #[cfg(feature = "opencl")]
     pub zcb: bool,
-
-    #[cfg(feature = "gui")]
-    pub progress_tx: Option<Sender<ProgressUpdate>>,
     pub stop_flag: Option<Arc<AtomicBool>>,
 }
 
 impl Hasher {
+    /// A hasher that reports to the terminal.
     pub fn new() -> Hasher {
-        Hasher {}
+        Hasher::with_events(Arc::new(ConsoleEvents::new()))
     }
 
-    pub fn run(&self, mut task: HasherTask) {
-        // let cpuid = CpuId::new();
-        // let cpu_name = cpuid
-        //     .get_processor_brand_string()
-        //     .map(|s| s.as_str().trim().to_string())
-        //     .unwrap_or_else(|| {
-        //         cpuid
-        //             .get_vendor_info()
-        //             .map(|v| v.as_str().to_string())
-        //             .unwrap_or_else(|| "Unknown CPU".to_string())
-        //     });
+    /// A hasher that reports to `events` instead of the terminal.
+    pub fn with_events(events: Arc<dyn HasherEvents>) -> Hasher {
+        Hasher { events }
+    }
+
+    pub fn run(&self, task: HasherTask) -> Result<HashReport, HasherError> {
+        let result = self.hash(task);
+        match &result {
+            Ok(report) => self.events.on_event(&HasherEvent::Finished(report.clone())),
+            Err(e) => self.events.on_event(&HasherEvent::Error(e.to_string())),
+        }
+        result
+    }
+
+    fn hash(&self, mut task: HasherTask) -> Result<HashReport, HasherError> {
+        let events = &self.events;
+        let log = |msg: String| events.on_event(&HasherEvent::Log(msg));
+
         let cpu_name: String = {
             #[cfg(target_arch = "x86_64")]
             {
@@ -92,45 +195,38 @@ impl Hasher {
                 }
             }
 
-            #[cfg(not(target_arch = "x86_64"))]
+            #[cfg(all(target_arch = "aarch64", target_os = "macos"))]
             {
                 "Apple Silicon (aarch64)".to_string()
             }
+
+            #[cfg(not(any(target_arch = "x86_64", all(target_arch = "aarch64", target_os = "macos"))))]
+            {
+                format!("Unknown CPU ({})", std::env::consts::ARCH)
+            }
         };
 
         let cores = sys_info::cpu_num().unwrap();
         let memory = sys_info::mem_info().unwrap();
 
-        let simd_ext = init_simd();
-
-        #[cfg(feature = "gui")]
-        if let Some(tx) = &task.progress_tx {
-            let _ = tx.send(ProgressUpdate::Log(format!("anne-hasher {}\n", env!("CARGO_PKG_VERSION"))));
-            if task.benchmark {
-                let _ = tx.send(ProgressUpdate::Log("*BENCHMARK MODE*\n".to_string()));
-            }
-            let _ = tx.send(ProgressUpdate::Log(format!(
-                "CPU: {} [using {} of {} cores{}{:?}]",
-                cpu_name,
-                task.cpu_threads,
-                cores,
-                if let SimdExtension::None = &simd_ext { "" } else { " + " },
-                &simd_ext
-            )));
-        }
+        let simd_ext = match task.simd.take() {
+            Some(simd_ext) if !simd_ext.is_supported() => return Err(HasherError::UnsupportedSimd(simd_ext.name())),
+            Some(simd_ext) => simd_ext,
+            None => init_simd(),
+        };
 
-        println!("anne-hasher {}\n", env!("CARGO_PKG_VERSION"));
+        log(format!("anne-hasher {}\n", env!("CARGO_PKG_VERSION")));
         if task.benchmark {
-                println!("*BENCHMARK MODE*\n");
+            log("*BENCHMARK MODE*\n".to_string());
         }
-        println!(
+        log(format!(
             "CPU: {} [using {} of {} cores{}{:?}]",
             cpu_name,
             task.cpu_threads,
             cores,
             if let SimdExtension::None = &simd_ext { "" } else { " + " },
             &simd_ext
-        );
+        ));
 
         #[cfg(not(feature = "opencl"))]
         let gpu_mem_needed = 0u64;
@@ -143,9 +239,26 @@ impl Hasher {
         #[cfg(feature = "opencl")]
         let gpu_mem_needed = if task.zcb { gpu_mem_needed } else { gpu_mem_needed / 2 };
 
-        let free_disk_space = free_disk_space(&task.output_path);
+        if task.output_paths.is_empty() {
+            return Err(HasherError::PathNotFound(String::new()));
+        }
+        let mut free_disk_spaces = Vec::with_capacity(task.output_paths.len());
+        for path in &task.output_paths {
+            if !Path::new(path).exists() {
+                return Err(HasherError::PathNotFound(path.clone()));
+            }
+            free_disk_spaces.push(free_disk_space(path)?);
+        }
+        let listed = |path: &String| task.output_paths.iter().filter(|other| *other == path).count() as u64;
         if task.nonces == 0 {
-            task.nonces = free_disk_space / NONCE_SIZE;
+            task.nonces = task
+                .output_paths
+                .iter()
+                .zip(&free_disk_spaces)
+                .map(|(path, free_disk_space)| free_disk_space / listed(path))
+                .min()
+                .unwrap()
+                / NONCE_SIZE;
         }
 
         let gpu = task.gpus.is_some();
@@ -153,9 +266,11 @@ impl Hasher {
         let mut rounded_nonces_to_sector_size = false;
         let mut nonces_per_sector = 1;
         if task.direct_io {
-            let sector_size = get_sector_size(&task.output_path);
-            nonces_per_sector = sector_size / SCOOP_SIZE;
-            if task.nonces % nonces_per_sector > 0 {
+            // sector sizes are powers of two, the largest one suits every disk
+            for path in &task.output_paths {
+                nonces_per_sector = max(nonces_per_sector, get_sector_size(path, events.as_ref())? / SCOOP_SIZE);
+            }
+            if !task.nonces.is_multiple_of(nonces_per_sector) {
                 rounded_nonces_to_sector_size = true;
                 task.nonces /= nonces_per_sector;
                 task.nonces *= nonces_per_sector;
@@ -164,204 +279,116 @@ impl Hasher {
 
         let segmentsize = task.nonces * NONCE_SIZE;
 
-        let file = Path::new(&task.output_path).join(format!(
-            "{}_{}_{}",
-            task.numeric_id,
-            task.start_nonce,
-            task.nonces
-        ));
-
-        if !file.parent().unwrap().exists() {
-            let msg = format!(
-                "Error: specified target path does not exist, path={}",
-                &task.output_path
-            );
-            println!("{}", msg);
-            #[cfg(feature = "gui")]
-            if let Some(tx) = &task.progress_tx {
-                let _ = tx.send(ProgressUpdate::Error(msg));
+        let files: Vec<PathBuf> = task
+            .output_paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let start_nonce = task.start_nonce + i as u64 * task.nonces;
+                Path::new(path).join(plot_file_name(task.numeric_id, start_nonce, task.nonces))
+            })
+            .collect();
+
+        // a path listed several times, like the staging directory, needs room
+        // for all of its new files
+        for (path, &free_disk_space) in task.output_paths.iter().zip(&free_disk_spaces) {
+            let new_files = task
+                .output_paths
+                .iter()
+                .zip(&files)
+                .filter(|&(other, file)| other == path && !file.exists())
+                .count() as u64;
+            if free_disk_space < new_files * segmentsize && !task.benchmark {
+                return Err(HasherError::InsufficientDiskSpace {
+                    required: new_files * segmentsize,
+                    available: free_disk_space,
+                });
             }
-            println!("Shutting down...");
-            return;
         }
 
-        if free_disk_space < segmentsize && !file.exists() && !task.benchmark {
-            let msg = format!(
-                "Error: insufficient disk space, MiB_required={:.2}, MiB_available={:.2}",
-                segmentsize as f64 / 1024.0 / 1024.0,
-                free_disk_space as f64 / 1024.0 / 1024.0
-            );
-            println!("{}", msg);
-            #[cfg(feature = "gui")]
-            if let Some(tx) = &task.progress_tx {
-                let _ = tx.send(ProgressUpdate::Error(msg));
-            }
-            println!("Shutting down...");
-            return;
+        if !task.benchmark {
+            check_overlaps(&task, &files)?;
         }
 
-        let mem = match calculate_mem_to_use(&task, &memory, nonces_per_sector, gpu, gpu_mem_needed) {
-            Ok(x) => x,
-            Err(_) => return,
-        };
+        let plan = calculate_mem_to_use(&task, events.as_ref(), &memory, nonces_per_sector, gpu, gpu_mem_needed)?;
+        let mem = plan.mem;
 
-        #[cfg(feature = "gui")]
-        if let Some(tx) = &task.progress_tx {
-            let _ = tx.send(ProgressUpdate::Log(format!(
-                "RAM: Total={:.2} GiB, Free={:.2} GiB, Usage={:.2} GiB",
-                memory.total as f64 / 1024.0 / 1024.0,
-                get_avail_mem(&memory) as f64 / 1024.0 / 1024.0,
-                (mem + gpu_mem_needed) as f64 / 1024.0 / 1024.0 / 1024.0
-            )));
-
-            #[cfg(feature = "opencl")]
-            let _ = tx.send(ProgressUpdate::Log(format!(
-                "     HDDcache={:.2} GiB, GPUcache={:.2} GiB,\n",
-                mem as f64 / 1024.0 / 1024.0 / 1024.0,
-                gpu_mem_needed as f64 / 1024.0 / 1024.0 / 1024.0
-            )));
-
-            let _ = tx.send(ProgressUpdate::Log(format!("Numeric ID:  {}", task.numeric_id)));
-            let _ = tx.send(ProgressUpdate::Log(format!("Start Nonce: {}", task.start_nonce)));
-            let _ = tx.send(ProgressUpdate::Log(format!(
-                "Nonces:      {}{}",
-                task.nonces,
-                if rounded_nonces_to_sector_size {
-                    " (rounded to sector size for fast direct i/o)"
-                } else {
-                    ""
-                }
-            )));
-            let _ = tx.send(ProgressUpdate::Log(format!("Output File: {}\n", file.display())));
-        }
+        log(format!(
+            "RAM: Total={:.2} GiB, Free={:.2} GiB, Usage={:.2} GiB",
+            memory.total as f64 / 1024.0 / 1024.0,
+            get_avail_mem(&memory) as f64 / 1024.0 / 1024.0,
+            (mem + gpu_mem_needed) as f64 / 1024.0 / 1024.0 / 1024.0
+        ));
 
-        println!(
-                "RAM: Total={:.2} GiB, Free={:.2} GiB, Usage={:.2} GiB",
-                memory.total as f64 / 1024.0 / 1024.0,
-                get_avail_mem(&memory) as f64 / 1024.0 / 1024.0,
-                (mem + gpu_mem_needed) as f64 / 1024.0 / 1024.0 / 1024.0
-            );
-
-            #[cfg(feature = "opencl")]
-            println!(
-                "     HDDcache={:.2} GiB, GPUcache={:.2} GiB,\n",
-                mem as f64 / 1024.0 / 1024.0 / 1024.0,
-                gpu_mem_needed as f64 / 1024.0 / 1024.0 / 1024.0
-            );
-
-            println!("Numeric ID:  {}", task.numeric_id);
-            println!("Start Nonce: {}", task.start_nonce);
-            println!(
-                "Nonces:      {}{}",
-                task.nonces,
-                if rounded_nonces_to_sector_size {
-                    " (rounded to sector size for fast direct i/o)"
-                } else {
-                    ""
-                }
-            );
-            println!("Output File: {}\n", file.display());
-
-        let mut progress = 0u64;
-        if file.exists() {
-            println!("File already exists, reading resume info...");
-            #[cfg(feature = "gui")]
-            if let Some(tx) = &task.progress_tx {
-                let _ = tx.send(ProgressUpdate::Log("File exists, reading resume info...".to_string()));
-            }
+        #[cfg(feature = "opencl")]
+        log(format!(
+            "     HDDcache={:.2} GiB, GPUcache={:.2} GiB,\n",
+            mem as f64 / 1024.0 / 1024.0 / 1024.0,
+            gpu_mem_needed as f64 / 1024.0 / 1024.0 / 1024.0
+        ));
 
-            let resume_info = read_resume_info(&file);
-            match resume_info {
-                Ok(x) => progress = x,
-                Err(_) => {
-                    let msg = format!("Error: couldn't read resume info from file '{}'", file.display());
-                    println!("{}", msg);
-                    println!("If you are sure that this file is incomplete or corrupted, then delete it before continuing.");
-                    println!("Shutting Down...");
-                    #[cfg(feature = "gui")]
-                    if let Some(tx) = &task.progress_tx {
-                        let _ = tx.send(ProgressUpdate::Error(msg));
-                    }
-                    return;
-                }
-            }
-            println!("OK");
-            #[cfg(feature = "gui")]
-            if let Some(tx) = &task.progress_tx {
-                let _ = tx.send(ProgressUpdate::Log("Resume info loaded.".to_string()));
-            }
-        } else {
-            print!("Pre-allocating file, please wait...");
-            #[cfg(feature = "gui")]
-            if let Some(tx) = &task.progress_tx {
-                let _ = tx.send(ProgressUpdate::Log("Pre-allocating file, please wait...".to_string()));
-            }
-            if !task.benchmark {
-                preallocate(&file, segmentsize, task.direct_io);
-                if write_resume_info(&file, 0u64).is_err() {
-                    println!("Error: couldn't write resume info");
-                    #[cfg(feature = "gui")]
-                    if let Some(tx) = &task.progress_tx {
-                        let _ = tx.send(ProgressUpdate::Error("Failed to write resume info".to_string()));
-                    }
-                }
-            }
-            println!("OK");
-            #[cfg(feature = "gui")]
-            if let Some(tx) = &task.progress_tx {
-                let _ = tx.send(ProgressUpdate::Log("Pre-allocation complete.".to_string()));
+        log(format!("Numeric ID:  {}", task.numeric_id));
+        log(format!("Start Nonce: {}", task.start_nonce));
+        log(format!(
+            "Nonces:      {}{}",
+            task.nonces,
+            if rounded_nonces_to_sector_size {
+                " (rounded to sector size for fast direct i/o)"
+            } else {
+                ""
             }
+        ));
+        for file in &files {
+            log(format!("Output File: {}", file.display()));
         }
-
-        if progress == 0 {
-            println!("Starting hashing...\n");
-        } else {
-            println!("Resuming hashing from nonce offset {}...\n", progress);
-        }
-        #[cfg(feature = "gui")]
-        if let Some(tx) = &task.progress_tx {
-            if progress == 0 {
-                let _ = tx.send(ProgressUpdate::Log("Starting hashing...\n".to_string()));
+        log(String::new());
+        events.on_event(&HasherEvent::Started(RunInfo {
+            cpu_name,
+            cores,
+            cpu_threads: task.cpu_threads,
+            simd: simd_ext.name(),
+            numeric_id: task.numeric_id,
+            start_nonce: task.start_nonce,
+            nonces: task.nonces,
+            files: files.clone(),
+            memory: plan,
+        }));
+
+        let mut progress = Vec::with_capacity(files.len());
+        for file in &files {
+            if file.exists() {
+                events.on_event(&HasherEvent::Phase(Phase::Resuming));
+                progress.push(read_resume_info(file).map_err(|source| HasherError::ReadResumeInfo {
+                    file: file.clone(),
+                    source,
+                })?);
             } else {
-                let _ = tx.send(ProgressUpdate::Log(format!("Resuming hashing from nonce offset {}...\n", progress)));
+                events.on_event(&HasherEvent::Phase(Phase::Preallocating));
+                if !task.benchmark {
+                    preallocate(file, segmentsize, task.direct_io, events.as_ref())?;
+                    write_resume_info(file, 0u64).map_err(|source| HasherError::WriteResumeInfo {
+                        file: file.clone(),
+                        source,
+                    })?;
+                }
+                progress.push(0);
             }
         }
+        let resumed_from: u64 = progress.iter().sum();
 
-        let num_buffer = { 2 };
+        let num_buffer = num_buffers(&task);
         let buffer_size = mem / num_buffer;
         let (tx_empty_buffers, rx_empty_buffers) = bounded(num_buffer as usize);
-        let (tx_full_buffers, rx_full_buffers) = bounded(num_buffer as usize);
 
         for _ in 0..num_buffer {
             let buffer = PageAlignedByteBuffer::new(buffer_size as usize);
             tx_empty_buffers.send(buffer).unwrap();
         }
 
-        let mb = MultiProgress::new();
-
-        let p1x = {
-            let pb = mb.add(ProgressBar::new(segmentsize - progress * NONCE_SIZE));
-            pb.set_style(ProgressStyle::default_bar()
-                .template("{prefix:>12} {wide_bar} {bytes:>8} {bytes_per_sec:>10}")
-                .expect("Failed to set template")
-                .progress_chars("██░"));
-            pb.set_prefix("Hashing:");
-            pb.enable_steady_tick(std::time::Duration::from_millis(200));
-            pb.tick();
-            pb
-        };
-
-        let p2x = {
-            let pb = mb.add(ProgressBar::new(segmentsize - progress * NONCE_SIZE));
-            pb.set_style(ProgressStyle::default_bar()
-                .template("{prefix:>12} {wide_bar} {bytes:>8} {bytes_per_sec:>10}")
-                .expect("Failed to set template")
-                .progress_chars("██░"));
-            pb.set_prefix("Writing:");
-            pb.enable_steady_tick(std::time::Duration::from_millis(200));
-            pb.tick();
-            pb
-        };
+        events.on_event(&HasherEvent::Phase(Phase::Hashing {
+            nonces: task.nonces * files.len() as u64,
+            resumed_from,
+        }));
 
         let sw = Stopwatch::start_new();
 
@@ -374,6 +401,29 @@ impl Hasher {
             Vec::new()
         };
 
+        let total_written = Arc::new(AtomicU64::new(resumed_from));
+        let mut stripes = Vec::with_capacity(files.len());
+        let mut writers = Vec::with_capacity(files.len());
+        for (i, file) in files.iter().enumerate() {
+            let (tx_full_buffers, rx_full_buffers) = bounded(num_buffer as usize);
+            stripes.push(Stripe {
+                start_nonce: task.start_nonce + i as u64 * task.nonces,
+                nonces_hashed: progress[i],
+                tx_buffers_to_writer: tx_full_buffers,
+            });
+            writers.push(thread::spawn({
+                create_writer_thread(
+                    task.clone(),
+                    file.clone(),
+                    progress[i],
+                    total_written.clone(),
+                    self.events.clone(),
+                    rx_full_buffers,
+                    tx_empty_buffers.clone(),
+                )
+            }));
+        }
+
         let hasher = thread::spawn({
             create_scheduler_thread(
                 task.clone(),
@@ -391,34 +441,30 @@ impl Hasher {
                     })
                     .build()
                     .unwrap(),
-                progress,
-                Some(p1x),
+                stripes,
+                self.events.clone(),
                 rx_empty_buffers.clone(),
-                tx_full_buffers.clone(),
                 simd_ext,
             )
         });
 
-        let writer = thread::spawn({
-            create_writer_thread(
-                task.clone(),
-                progress,
-                Some(p2x),
-                rx_full_buffers.clone(),
-                tx_empty_buffers.clone(),
-            )
-        });
+        // only the threads hold the channel ends from here on, so the scheduler
+        // or a writer exiting early disconnects the others instead of leaving
+        // them blocked
+        drop(tx_empty_buffers);
+        drop(rx_empty_buffers);
 
-        let writer_result = writer.join();
+        let writer_results: Vec<_> = writers.into_iter().map(|writer| writer.join()).collect();
         let hasher_result = hasher.join();
 
-        if let Err(e) = writer_result {
-            eprintln!("Writer thread panicked: {:?}", e);
-        }
-        
-        if let Err(e) = hasher_result {
-            eprintln!("Hasher thread panicked: {:?}", e);
+        let mut nonces_written = 0;
+        let mut writer_stalls = Vec::with_capacity(writer_results.len());
+        for result in writer_results {
+            let (written, stall) = result.map_err(|_| HasherError::ThreadPanicked("writer"))??;
+            nonces_written += written;
+            writer_stalls.push(stall);
         }
+        let hasher_stall = hasher_result.map_err(|_| HasherError::ThreadPanicked("hasher"))?;
 
         let was_stopped = if let Some(stop_flag) = &task.stop_flag {
             stop_flag.load(Ordering::Relaxed)
@@ -426,86 +472,43 @@ impl Hasher {
             false
         };
 
-        let _ = mb.clear();
-
-        let elapsed = sw.elapsed_ms() as u64;
-        let hours = elapsed / 1000 / 60 / 60;
-        let minutes = elapsed / 1000 / 60 - hours * 60;
-        let seconds = elapsed / 1000 - hours * 60 * 60 - minutes * 60;
-
-        let completed_nonces = task.nonces - progress;
-            
-        if was_stopped {
-
-            println!("\nHashing interrupted.");
-        } else if completed_nonces > 0 {
-
-            println!(
-                "\nGenerated {} nonces in {}h{:02}m{:02}s, {:.2} MiB/s, {:.0} nonces/m.",
-                completed_nonces,
-                hours,
-                minutes,
-                seconds,
-                completed_nonces as f64 * 1000.0 / (elapsed as f64 + 1.0) / 4.0,
-                completed_nonces as f64 * 1000.0 / (elapsed as f64 + 1.0) * 60.0
-            );
-            println!("Hashing completed!");
-        }
+        let report = HashReport {
+            files,
+            nonces: task.nonces * task.output_paths.len() as u64,
+            resumed_from,
+            hashed_nonces: nonces_written - resumed_from,
+            elapsed: Duration::from_millis(sw.elapsed_ms() as u64),
+            stopped: was_stopped,
+            buffers: num_buffer,
+            hasher_stall,
+            writer_stalls,
+        };
 
-        #[cfg(feature = "gui")]
-        if let Some(tx) = &task.progress_tx {
-            let completed_nonces = task.nonces - progress;
-            
-            if was_stopped {
-
-                let _ = tx.send(ProgressUpdate::Error("STOP_REQUESTED".to_string()));
-            } else if completed_nonces > 0 {
-
-                let _ = tx.send(ProgressUpdate::Log(format!(
-                    "\nGenerated {} nonces in {}h{:02}m{:02}s, {:.2} MiB/s, {:.0} nonces/m.",
-                    completed_nonces,
-                    hours,
-                    minutes,
-                    seconds,
-                    completed_nonces as f64 * 1000.0 / (elapsed as f64 + 1.0) / 4.0,
-                    completed_nonces as f64 * 1000.0 / (elapsed as f64 + 1.0) * 60.0
-                )));
-                let _ = tx.send(ProgressUpdate::Progress(1.0));
-                let _ = tx.send(ProgressUpdate::Speed(completed_nonces as f64 * 1000.0 / (elapsed as f64 + 1.0) * 60.0));
-                let _ = tx.send(ProgressUpdate::Done);
-            }
-        }
+        Ok(report)
     }
 }
 
 fn calculate_mem_to_use(
     task: &HasherTask,
+    events: &dyn HasherEvents,
     memory: &sys_info::MemInfo,
     nonces_per_sector: u64,
     gpu: bool,
     gpu_mem_needed: u64,
-) -> Result<u64, &'static str> {
-    let segmentsize = task.nonces * NONCE_SIZE;
-
-    let mut mem = match task.mem.parse::<Bytes>() {
-        Ok(x) => x.size() as u64,
-        Err(_) => {
-            println!(
-                "Error: Can't parse memory limit parameter, input={}",
-                task.mem,
-            );
-            println!("\nPlease specify a number followed by a unit. If no unit is provided, bytes will be assumed.");
-            println!("Supported units: B, KiB, MiB, GiB, TiB, PiB, EiB, KB, MB, GB, TB, PB, EB");
-            println!("Example: --mem 10GiB\n");
-            println!("Shutting down...");
-            return Err("invalid unit");
-        }
-    };
+) -> Result<MemoryPlan, HasherError> {
+    let segmentsize = task.nonces * NONCE_SIZE * task.output_paths.len() as u64;
+
+    let mut mem = task
+        .mem
+        .parse::<Bytes>()
+        .map_err(|_| HasherError::InvalidMemoryLimit(task.mem.clone()))?
+        .size() as u64;
     
     if gpu && mem > 0 && mem < gpu_mem_needed + nonces_per_sector * NONCE_SIZE {
-        println!("Error: Insufficient host memory for GPU hashing!");
-        println!("Shutting down...");
-        process::exit(0);
+        return Err(HasherError::InsufficientHostMemory {
+            required: gpu_mem_needed + nonces_per_sector * NONCE_SIZE,
+            available: mem,
+        });
     }
 
     if gpu && mem > 0 {
@@ -523,7 +526,7 @@ fn calculate_mem_to_use(
         nonces_per_sector
     };
 
-    let avail_mem_bytes = get_avail_mem(&memory) * 1024;
+    let avail_mem_bytes = get_avail_mem(memory) * 1024;
     
 
     let max_buffer_from_free_mem = (avail_mem_bytes as f64 * 0.75) as u64;
@@ -537,28 +540,59 @@ fn calculate_mem_to_use(
     
     mem = min(mem, mem_without_gpu);
 
-    let num_buffer = 2;
+    let num_buffer = num_buffers(task);
     mem /= num_buffer * NONCE_SIZE * nonces_per_sector;
     mem *= num_buffer * NONCE_SIZE * nonces_per_sector;
 
     mem = max(mem, num_buffer * NONCE_SIZE * nonces_per_sector);
     
-    println!("Memory calculation:");
-    println!("  Available memory: {:.2} GiB", avail_mem_bytes as f64 / 1024.0 / 1024.0 / 1024.0);
-    println!("  75% of available: {:.2} GiB", max_buffer_from_free_mem as f64 / 1024.0 / 1024.0 / 1024.0);
-    println!("  GPU memory needed: {:.2} GiB", gpu_mem_needed as f64 / 1024.0 / 1024.0 / 1024.0);
-    println!("  Final buffer size: {:.2} GiB", mem as f64 / 1024.0 / 1024.0 / 1024.0);
-    println!("  Buffer configuration: {} buffer(s) of {} nonces each", num_buffer, mem / NONCE_SIZE / num_buffer);
-    
-    #[cfg(feature = "gui")]
-    if let Some(tx) = &task.progress_tx {
-        let _ = tx.send(crate::hasher::ProgressUpdate::Log(
-            format!("Buffer size: {:.2} GiB ({} buffers)", 
-                   mem as f64 / 1024.0 / 1024.0 / 1024.0, num_buffer)
-        ));
+    let log = |msg: String| events.on_event(&HasherEvent::Log(msg));
+    log("Memory calculation:".to_string());
+    log(format!("  Available memory: {:.2} GiB", avail_mem_bytes as f64 / 1024.0 / 1024.0 / 1024.0));
+    log(format!("  75% of available: {:.2} GiB", max_buffer_from_free_mem as f64 / 1024.0 / 1024.0 / 1024.0));
+    log(format!("  GPU memory needed: {:.2} GiB", gpu_mem_needed as f64 / 1024.0 / 1024.0 / 1024.0));
+    log(format!("  Final buffer size: {:.2} GiB", mem as f64 / 1024.0 / 1024.0 / 1024.0));
+    log(format!("  Buffer configuration: {} buffer(s) of {} nonces each", num_buffer, mem / NONCE_SIZE / num_buffer));
+
+    Ok(MemoryPlan {
+        available: avail_mem_bytes,
+        usable: max_buffer_from_free_mem,
+        gpu: gpu_mem_needed,
+        mem,
+        buffers: num_buffer,
+    })
+}
+
+/// Refuses new plot files that share nonces with any existing one. The files
+/// of the task itself are resumed and don't count.
+fn check_overlaps(task: &HasherTask, files: &[PathBuf]) -> Result<(), HasherError> {
+    let dirs: Vec<&String> = task.output_paths.iter().chain(&task.known_paths).collect();
+    let inventory = Inventory::scan(&dirs);
+    for (i, file) in files.iter().enumerate() {
+        let start_nonce = task.start_nonce + i as u64 * task.nonces;
+        let own = fs::canonicalize(file).ok();
+        let existing = inventory
+            .overlapping(task.numeric_id, start_nonce..start_nonce + task.nonces)
+            .into_iter()
+            .find(|plot| own.is_none() || fs::canonicalize(&plot.file).ok() != own);
+        if let Some(plot) = existing {
+            return Err(HasherError::NonceOverlap {
+                file: file.clone(),
+                existing: plot.file.clone(),
+                nonces: max(start_nonce, plot.start_nonce)..min(start_nonce + task.nonces, plot.range().end),
+            });
+        }
     }
+    Ok(())
+}
 
-    Ok(mem)
+/// `task.buffers`, by default one buffer per writer plus one being hashed.
+fn num_buffers(task: &HasherTask) -> u64 {
+    if task.buffers > 0 {
+        u64::from(task.buffers)
+    } else {
+        max(2, task.output_paths.len() as u64 + 1)
+    }
 }
 
 #[cfg(not(windows))]
@@ -569,4 +603,4 @@ fn get_avail_mem(memory: &sys_info::MemInfo) -> u64 {
 #[cfg(windows)]
 fn get_avail_mem(memory: &sys_info::MemInfo) -> u64 {
     memory.free
-}
\ No newline at end of file
+}
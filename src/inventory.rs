@@ -0,0 +1,209 @@
+use crate::hasher::NONCE_SIZE;
+use crate::resume::ResumeInfo;
+use crate::utils::parse_plot_file_name;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A plot file found by [`Inventory::scan`].
+#[derive(Debug, Clone)]
+pub struct PlotEntry {
+    pub file: PathBuf,
+    pub numeric_id: u64,
+    pub start_nonce: u64,
+    pub nonces: u64,
+    /// Nonces on disk, `None` for files without resume info, which weren't
+    /// written by us and are taken as complete.
+    pub progress: Option<u64>,
+}
+
+impl PlotEntry {
+    pub fn range(&self) -> Range<u64> {
+        self.start_nonce..self.start_nonce + self.nonces
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.progress.is_none_or(|progress| progress == self.nonces)
+    }
+}
+
+/// Two plot files of the same numeric ID sharing `nonces`.
+#[derive(Debug, Clone)]
+pub struct Overlap {
+    pub first: PathBuf,
+    pub second: PathBuf,
+    pub nonces: Range<u64>,
+}
+
+/// All plot files in a set of directories, by numeric ID and start nonce.
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    pub plots: BTreeMap<u64, Vec<PlotEntry>>,
+}
+
+impl Inventory {
+    /// Collects the plot files in `dirs` by their names. Missing directories
+    /// are skipped and a directory given twice is only scanned once.
+    pub fn scan<P: AsRef<Path>>(dirs: &[P]) -> Inventory {
+        let mut scanned = Vec::new();
+        let mut inventory = Inventory::default();
+        for dir in dirs {
+            let dir = dir.as_ref();
+            let key = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+            if scanned.contains(&key) {
+                continue;
+            }
+            scanned.push(key);
+
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Some((numeric_id, start_nonce, nonces)) =
+                    entry.file_name().to_str().and_then(parse_plot_file_name)
+                else {
+                    continue;
+                };
+                let file = entry.path();
+                let progress = ResumeInfo::read(&file).ok().map(|info| info.progress);
+                inventory.plots.entry(numeric_id).or_default().push(PlotEntry {
+                    file,
+                    numeric_id,
+                    start_nonce,
+                    nonces,
+                    progress,
+                });
+            }
+        }
+        for plots in inventory.plots.values_mut() {
+            plots.sort_by_key(|plot| (plot.start_nonce, plot.nonces));
+        }
+        inventory
+    }
+
+    /// Plot files of `numeric_id` that share nonces with `nonces`.
+    pub fn overlapping(&self, numeric_id: u64, nonces: Range<u64>) -> Vec<&PlotEntry> {
+        self.plots
+            .get(&numeric_id)
+            .into_iter()
+            .flatten()
+            .filter(|plot| plot.start_nonce < nonces.end && nonces.start < plot.range().end)
+            .collect()
+    }
+
+    /// Every pair of plot files that share nonces.
+    pub fn overlaps(&self) -> Vec<Overlap> {
+        let mut overlaps = Vec::new();
+        for plots in self.plots.values() {
+            // sorted by start, so only later files can overlap an earlier one
+            for (i, first) in plots.iter().enumerate() {
+                for second in plots[i + 1..].iter().take_while(|p| p.start_nonce < first.range().end) {
+                    overlaps.push(Overlap {
+                        first: first.file.clone(),
+                        second: second.file.clone(),
+                        nonces: second.start_nonce..first.range().end.min(second.range().end),
+                    });
+                }
+            }
+        }
+        overlaps
+    }
+
+    /// First nonce after every plot file of `numeric_id`.
+    pub fn next_start_nonce(&self, numeric_id: u64) -> u64 {
+        self.plots
+            .get(&numeric_id)
+            .into_iter()
+            .flatten()
+            .map(|plot| plot.range().end)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Inventory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.plots.is_empty() {
+            return write!(f, "No hash files found.");
+        }
+        for (numeric_id, plots) in &self.plots {
+            let nonces: u64 = plots.iter().map(|plot| plot.nonces).sum();
+            writeln!(
+                f,
+                "Numeric ID {}: {} file(s), {} nonces ({:.2} GiB)",
+                numeric_id,
+                plots.len(),
+                nonces,
+                (nonces * NONCE_SIZE) as f64 / 1024.0 / 1024.0 / 1024.0
+            )?;
+            for plot in plots {
+                write!(
+                    f,
+                    "  {:>12}..{:<12} {}",
+                    plot.start_nonce,
+                    plot.range().end - 1,
+                    plot.file.display()
+                )?;
+                match plot.progress {
+                    Some(progress) if progress < plot.nonces => {
+                        writeln!(f, " (partial, {} of {} nonces)", progress, plot.nonces)?
+                    }
+                    _ => writeln!(f)?,
+                }
+            }
+        }
+        let overlaps = self.overlaps();
+        if overlaps.is_empty() {
+            write!(f, "No overlapping nonces.")
+        } else {
+            write!(f, "{} overlap(s):", overlaps.len())?;
+            for overlap in &overlaps {
+                write!(
+                    f,
+                    "\n  nonces {}..{} in {} and {}",
+                    overlap.nonces.start,
+                    overlap.nonces.end - 1,
+                    overlap.first.display(),
+                    overlap.second.display()
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inventory_overlaps() {
+        let dir = std::env::temp_dir().join(format!("anne-hasher-inventory-{}", std::process::id()));
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        for file in [a.join("42_0_100"), a.join("42_100_100"), b.join("42_150_100"), b.join("7_0_100")] {
+            fs::write(file, []).unwrap();
+        }
+        fs::write(b.join("not_a_plot"), []).unwrap();
+
+        // a directory given twice doesn't overlap with itself
+        let inventory = Inventory::scan(&[&a, &b, &a]);
+        assert_eq!(inventory.plots[&42].len(), 3);
+        assert_eq!(inventory.next_start_nonce(42), 250);
+        assert_eq!(inventory.next_start_nonce(7), 100);
+
+        let overlaps = inventory.overlaps();
+        assert_eq!(overlaps.len(), 1);
+        assert_eq!(overlaps[0].nonces, 150..200);
+        assert_eq!(overlaps[0].second, b.join("42_150_100"));
+
+        assert_eq!(inventory.overlapping(42, 199..300).len(), 2);
+        assert!(inventory.overlapping(42, 250..300).is_empty());
+        assert!(inventory.overlapping(8, 0..100).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
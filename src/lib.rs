@@ -0,0 +1,95 @@
+//! anne-hasher - a PoST hasher written in Rust
+//!
+//! The library exposes everything the `anne-hasher` binary is built from, so
+//! plotting can be embedded into other tools:
+//!
+//! ```no_run
+//! use anne_hasher::{Hasher, HasherTask};
+//!
+//! let task = HasherTask {
+//!     numeric_id: 1234567890123456789,
+//!     start_nonce: 0,
+//!     nonces: 1024,
+//!     output_paths: vec!["/tmp".to_string()],
+//!     known_paths: Vec::new(),
+//!     mem: "0B".to_string(),
+//!     buffers: 0,
+//!     cpu_threads: 4,
+//!     gpus: None,
+//!     direct_io: false,
+//!     benchmark: false,
+//!     simd: None,
+//!     #[cfg(feature = "opencl")]
+//!     zcb: false,
+//!     stop_flag: None,
+//! };
+//! match Hasher::new().run(task) {
+//!     Ok(report) => println!("{} nonces at {:.2} MiB/s", report.hashed_nonces, report.mib_per_sec()),
+//!     Err(e) => eprintln!("plotting failed: {}", e),
+//! }
+//! ```
+
+#[cfg(feature = "http")]
+pub mod api;
+pub mod buffer;
+pub mod config;
+pub mod convert;
+pub mod cpu_hasher;
+pub mod daemon;
+pub mod deadline;
+pub mod error;
+pub mod events;
+#[cfg(feature = "opencl")]
+mod gpu_hasher;
+pub mod hasher;
+pub mod inventory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mover;
+mod mshabal;
+#[cfg(target_arch = "aarch64")]
+mod mshabal_aarch64;
+#[cfg(target_arch = "x86_64")]
+mod mshabal_x86;
+#[cfg(feature = "opencl")]
+pub mod ocl;
+pub mod poc_hashing;
+pub mod reorder;
+pub mod repack;
+pub mod resume;
+mod scheduler;
+pub mod shabal256;
+pub mod task_spec;
+pub mod utils;
+pub mod verify;
+pub mod writer;
+
+#[cfg(feature = "http")]
+pub use crate::api::{ApiEvents, ApiServer, ApiState};
+pub use crate::buffer::PageAlignedByteBuffer;
+pub use crate::config::{Config, Settings};
+pub use crate::convert::{convert_plot_file, swap_scoop_halves, ConvertReport, ConvertTask};
+pub use crate::cpu_hasher::{
+    bench_simd, fastest_simd, init_simd, noncegen, select_simd, supported_simd, SimdExtension,
+};
+pub use crate::daemon::{run_daemon, DaemonReport, DaemonTask, JobRecord, JobState};
+pub use crate::deadline::{calculate_deadline, calculate_scoop, find_best_deadline, DeadlineReport, DeadlineTask};
+pub use crate::error::HasherError;
+pub use crate::events::{ConsoleEvents, HasherEvent, HasherEvents, JsonEvents, NoEvents, Phase};
+pub use crate::hasher::{
+    HashReport, Hasher, HasherTask, MemoryPlan, RunInfo, NONCE_SIZE, NUM_SCOOPS, SCOOP_SIZE,
+};
+pub use crate::inventory::{Inventory, Overlap, PlotEntry};
+#[cfg(feature = "metrics")]
+pub use crate::metrics::{Metrics, MetricsEvents, MetricsServer};
+pub use crate::mover::{check_dest_space, move_plot_file, Mover};
+pub use crate::poc_hashing::{generate_nonce, generate_scoop, noncegen_rust, PocVersion};
+pub use crate::reorder::{reorder_plot_file, ReorderTask};
+pub use crate::repack::{merge_plot_files, split_plot_file, MergeTask, RepackReport, SplitTask};
+pub use crate::shabal256::{shabal256, shabal256_fast};
+pub use crate::task_spec::TaskSpec;
+pub use crate::utils::{
+    calculate_rounded_nonces, next_start_nonce, parse_plot_file_name, parse_stagger_file_name, plot_file_name,
+};
+pub use crate::verify::{verify_plot_file, VerifyReport, VerifyTask};
+pub use crate::resume::{mark_complete, read_resume_info, write_resume_info, ResumeInfo};
@@ -1,45 +1,164 @@
 // #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![cfg_attr(not(feature = "show_console"), windows_subsystem = "windows")]
-mod cpu_hasher;
-#[cfg(feature = "opencl")]
-mod gpu_hasher;
-#[cfg(feature = "opencl")]
-mod ocl;
-mod hasher;
-mod poc_hashing;
-mod scheduler;
-mod shabal256;
-mod utils;
-mod writer;
-mod buffer;
-
 use std::cmp::min;
+#[cfg(feature = "gui")]
 use std::env;
+use std::path::PathBuf;
 use std::process;
 
 use clap::{ Arg, ArgAction, ArgGroup, Command };
-use hasher::{ Hasher, HasherTask };
+use clap::parser::ValueSource;
+use anne_hasher::{ ConsoleEvents, HashReport, Hasher, HasherError, HasherEvent, HasherEvents, HasherTask };
+use anne_hasher::{ check_dest_space, Mover };
+use anne_hasher::JsonEvents;
+use anne_hasher::{ verify_plot_file, VerifyTask };
+use anne_hasher::{ find_best_deadline, DeadlineTask };
+use anne_hasher::{ convert_plot_file, ConvertTask };
+use anne_hasher::{ reorder_plot_file, ReorderTask };
+use anne_hasher::{ merge_plot_files, split_plot_file, MergeTask, RepackReport, SplitTask };
+use anne_hasher::{ run_daemon, DaemonTask };
+use anne_hasher::{ bench_simd, select_simd, supported_simd };
+use anne_hasher::Inventory;
+use anne_hasher::{ Config, Settings };
+#[cfg(feature = "http")]
+use anne_hasher::{ ApiEvents, ApiServer, ApiState };
+#[cfg(feature = "metrics")]
+use anne_hasher::{ Metrics, MetricsEvents, MetricsServer };
+use anne_hasher::deadline::parse_gensig;
+#[cfg(feature = "opencl")]
+use anne_hasher::ocl;
 
+use anne_hasher::utils::set_low_prio;
+use anne_hasher::calculate_rounded_nonces;
 #[cfg(feature = "gui")]
-use hasher::ProgressUpdate;
-
-use utils::set_low_prio;
-use utils::calculate_rounded_nonces;
+use anne_hasher::next_start_nonce;
 // use crate::utils::{timestamp};
 use std::sync::atomic::{ AtomicBool, Ordering };
-use std::sync::Arc;
+use std::sync::{ Arc, OnceLock };
+use std::fmt::Display;
+#[cfg(feature = "http")]
+use std::thread;
+#[cfg(feature = "http")]
+use std::time::Duration;
 
 #[cfg(feature = "gui")]
 mod gui_app {
     use super::*;
-    use crossbeam_channel::{ bounded, Receiver };
+    use anne_hasher::{ HasherEvent, HasherEvents, Phase };
+    use anne_hasher::parse_plot_file_name;
+    use anne_hasher::resume::resume_file;
+    use crossbeam_channel::{ bounded, Receiver, Sender };
+    use std::sync::atomic::{ AtomicU32, AtomicU64 };
     use eframe::egui::{ Color32, Context, RichText, Ui, Vec2, Widget };
-    use std::path::PathBuf;
     use image::GenericImageView;
+
+    #[derive(Debug, Clone)]
+    pub enum ProgressUpdate {
+        Log(String),
+        Progress(f32),
+        WriteProgress(f32),
+        Speed(f64),
+        WriteSpeed(f64),
+        Error(String),
+        Done,
+    }
+
+    /// Turns hasher events into the fractional updates the UI draws.
+    struct GuiEvents {
+        tx: Sender<ProgressUpdate>,
+        nonces: AtomicU64,
+        last_write_percent: AtomicU32,
+    }
+
+    impl GuiEvents {
+        fn new(tx: Sender<ProgressUpdate>) -> GuiEvents {
+            GuiEvents {
+                tx,
+                nonces: AtomicU64::new(0),
+                last_write_percent: AtomicU32::new(0),
+            }
+        }
+
+        fn fraction(&self, nonces: u64) -> f32 {
+            (nonces as f32 / self.nonces.load(Ordering::Relaxed).max(1) as f32).min(1.0)
+        }
+    }
+
+    impl HasherEvents for GuiEvents {
+        fn on_event(&self, event: &HasherEvent) {
+            let log = |msg: String| {
+                let _ = self.tx.send(ProgressUpdate::Log(msg));
+            };
+            match event {
+                HasherEvent::Phase(Phase::Preallocating) => {
+                    log("Pre-allocating file, please wait...".to_string());
+                }
+                HasherEvent::Phase(Phase::Resuming) => {
+                    log("File exists, reading resume info...".to_string());
+                }
+                HasherEvent::Phase(Phase::Hashing { nonces, resumed_from }) => {
+                    self.nonces.store(*nonces, Ordering::Relaxed);
+                    if *resumed_from == 0 {
+                        log("Starting hashing...\n".to_string());
+                    } else {
+                        log(format!("Resuming hashing from nonce offset {}...\n", resumed_from));
+                    }
+                }
+                HasherEvent::Log(msg) => log(msg.clone()),
+                HasherEvent::Warning(msg) => log(format!("Warning: {}", msg)),
+                HasherEvent::Error(e) => {
+                    let _ = self.tx.send(ProgressUpdate::Error(e.clone()));
+                }
+                HasherEvent::Hashed(nonces) => {
+                    let _ = self.tx.try_send(ProgressUpdate::Progress(self.fraction(*nonces)));
+                }
+                HasherEvent::Written(nonces) => {
+                    let progress = self.fraction(*nonces);
+                    let percent = (progress * 100.0).floor() as u32;
+                    if self.last_write_percent.swap(percent, Ordering::Relaxed) != percent {
+                        let _ = self.tx.try_send(ProgressUpdate::WriteProgress(progress));
+                    }
+                }
+                HasherEvent::HashSpeed(speed) => {
+                    let _ = self.tx.try_send(ProgressUpdate::Speed(*speed));
+                }
+                HasherEvent::WriteSpeed(speed) => {
+                    let _ = self.tx.try_send(ProgressUpdate::WriteSpeed(*speed));
+                }
+                HasherEvent::Started(_)
+                | HasherEvent::Phase(Phase::Verifying { .. })
+                | HasherEvent::Verified(_)
+                | HasherEvent::Phase(Phase::Converting { .. })
+                | HasherEvent::Phase(Phase::Repacking { .. })
+                | HasherEvent::DeviceHashed { .. }
+                | HasherEvent::DeviceHashSpeed { .. }
+                | HasherEvent::HasherStall(_)
+                | HasherEvent::WriterStall(_)
+                | HasherEvent::Checkpoint { .. }
+                | HasherEvent::WriteError { .. }
+                | HasherEvent::Moved(_)
+                | HasherEvent::Deadline(_)
+                | HasherEvent::Converted(_)
+                | HasherEvent::Repacked(_) => {}
+                HasherEvent::Finished(report) => {
+                    if report.stopped {
+                        let _ = self.tx.send(ProgressUpdate::Error("STOP_REQUESTED".to_string()));
+                    } else {
+                        if report.hashed_nonces > 0 {
+                            log(format!("\n{}", report));
+                        }
+                        let _ = self.tx.send(ProgressUpdate::Progress(1.0));
+                        let _ = self.tx.send(ProgressUpdate::WriteProgress(1.0));
+                        let _ = self.tx.send(ProgressUpdate::Done);
+                    }
+                }
+            }
+        }
+    }
     use egui::IconData;
 
     #[cfg(feature = "opencl")]
-    use crate::ocl::GpuInfo;
+    use anne_hasher::ocl::GpuInfo;
 
     const BG_DARK: Color32 = Color32::from_rgb(17, 17, 17);
     const TEXT_LIGHT: Color32 = Color32::from_rgb(254, 254, 254);
@@ -125,7 +244,9 @@ mod gui_app {
         }
     }
 
-    pub fn launch() -> eframe::Result<()> {
+    /// Opens the window, with the settings of `profile` in `config_file` or the
+    /// default config file filled in. They are saved back on every start.
+    pub fn launch(config_file: Option<PathBuf>, profile: Option<String>) -> eframe::Result<()> {
         println!("Launching GUI...");
 
         let icon_data = load_icon_data();
@@ -152,7 +273,7 @@ mod gui_app {
             options,
             Box::new(|_cc| {
                 println!("Creating app instance...");
-                Ok(Box::new(AnneGuiApp::default()))
+                Ok(Box::new(AnneGuiApp::with_config(config_file, profile)))
             })
         )
     }
@@ -197,6 +318,13 @@ mod gui_app {
         stop_flag: Option<Arc<AtomicBool>>,
         error: Option<String>,
         rx: Option<Receiver<ProgressUpdate>>,
+        config_file: Option<PathBuf>,
+        profile: Option<String>,
+        /// The loaded settings, keeps the keys without a field like `mem`.
+        settings: Settings,
+        /// The configured `--gpu` spec until the GPUs are detected.
+        #[cfg(feature = "opencl")]
+        pending_gpu: Option<String>,
     }
 
     impl Default for AnneGuiApp {
@@ -269,6 +397,99 @@ mod gui_app {
                 stop_flag: None,
                 error: None,
                 rx: None,
+                config_file: None,
+                profile: None,
+                settings: Settings::default(),
+                #[cfg(feature = "opencl")]
+                pending_gpu: None,
+            }
+        }
+    }
+
+    impl AnneGuiApp {
+        fn with_config(config_file: Option<PathBuf>, profile: Option<String>) -> Self {
+            let mut app = AnneGuiApp {
+                config_file: config_file.or_else(Config::default_file),
+                profile,
+                ..AnneGuiApp::default()
+            };
+            let Some(file) = app.config_file.clone() else {
+                return app;
+            };
+            let settings = match
+                Config::load_or_default(&file).and_then(|config|
+                    config.settings(app.profile.as_deref())
+                )
+            {
+                Ok(settings) => settings,
+                Err(e) => {
+                    app.logs.push(format!("Warning: {}", e));
+                    return app;
+                }
+            };
+
+            if let Some(numeric_id) = settings.numeric_id {
+                app.numeric_id = numeric_id.to_string();
+            }
+            if let Some(nonces) = settings.nonces {
+                app.nonces = nonces.to_string();
+                app.total_nonces = nonces;
+            }
+            if let Some(path) = settings.paths.iter().flatten().next() {
+                app.path = PathBuf::from(path);
+            }
+            if let Some(cpu) = settings.cpu {
+                app.cpu_cores = cpu.to_string();
+            }
+            if let Some(direct_io) = settings.direct_io {
+                app.disable_direct_io = !direct_io;
+            }
+            if let Some(low_priority) = settings.low_priority {
+                app.low_priority = low_priority;
+            }
+            #[cfg(feature = "opencl")]
+            {
+                if let Some(zcb) = settings.zcb {
+                    app.zero_copy = zcb;
+                }
+                app.pending_gpu = settings.gpus.iter().flatten().next().cloned();
+            }
+            app.logs.push(format!("Loaded settings from {}", file.display()));
+            app.settings = settings;
+            app
+        }
+
+        /// Stores the fields in the config file, under the profile if one was given.
+        fn save_settings(&mut self, gpus: &Option<Vec<String>>) {
+            let Some(file) = self.config_file.clone() else {
+                return;
+            };
+            let mut settings = self.settings.merged(
+                &(Settings {
+                    numeric_id: self.numeric_id.trim().parse().ok(),
+                    nonces: self.nonces.trim().parse().ok(),
+                    paths: Some(vec![self.path.to_string_lossy().to_string()]),
+                    cpu: self.cpu_cores.trim().parse().ok(),
+                    direct_io: Some(!self.disable_direct_io),
+                    low_priority: Some(self.low_priority),
+                    #[cfg(feature = "opencl")]
+                    zcb: Some(self.zero_copy),
+                    ..Settings::default()
+                })
+            );
+            // no GPU selected has to clear the configured one
+            if cfg!(feature = "opencl") {
+                settings.gpus = gpus.clone();
+            }
+            let saved = Config::load_or_default(&file).and_then(|mut config| {
+                config.set_settings(self.profile.as_deref(), settings.clone());
+                config.save(&file)
+            });
+            match saved {
+                Ok(()) => {
+                    self.settings = settings;
+                }
+                Err(e) => self.logs.push(format!("Warning: settings not saved: {}", e)),
             }
         }
     }
@@ -411,7 +632,7 @@ mod gui_app {
                     self.gpu_rx = Some(rx);
                     self.gpu_thread_spawned = true;
                     std::thread::spawn(move || {
-                        let gpus = crate::ocl::get_gpu_list();
+                        let gpus = anne_hasher::ocl::get_gpu_list();
                         let _ = tx.send(gpus);
                     });
                 }
@@ -447,6 +668,9 @@ mod gui_app {
                             };
                             self.gpu_cores = gpu_cores;
                             self.total_gpu_cores = total_gpu_cores;
+                            if let Some(spec) = self.pending_gpu.take() {
+                                self.select_configured_gpu(&spec);
+                            }
                             self.logs.push(
                                 if self.detected_gpus.is_empty() {
                                     "No GPUs detected. Using CPU only.".to_string()
@@ -468,6 +692,28 @@ mod gui_app {
             }
         }
 
+        /// Selects the detected GPU of a `platform_id:device_id:cores` spec.
+        #[cfg(feature = "opencl")]
+        fn select_configured_gpu(&mut self, spec: &str) {
+            let mut parts = spec.rsplitn(2, ':');
+            let (Some(cores), Some(device)) = (parts.next(), parts.next()) else {
+                return;
+            };
+            let prefix = format!("{}:", device);
+            match self.detected_gpus.iter().position(|gpu| gpu.spec.starts_with(&prefix)) {
+                Some(idx) => {
+                    self.selected_gpu = idx + 1;
+                    self.total_gpu_cores = self.detected_gpus[idx].spec
+                        .rsplit(':')
+                        .next()
+                        .and_then(|cores| cores.parse().ok())
+                        .unwrap_or(0);
+                    self.gpu_cores = cores.to_string();
+                }
+                None => self.logs.push(format!("Configured GPU {} not found.", device)),
+            }
+        }
+
         fn update_nonces_size(&mut self) {
             if let Ok(n) = self.nonces.parse::<u64>() {
                 self.total_nonces = n;
@@ -952,7 +1198,7 @@ mod gui_app {
                     // Find the most recently modified file with this numeric ID
                     for entry in entries.flatten() {
                         if let Some(file_name) = entry.file_name().to_str() {
-                            if file_name.starts_with(&format!("{}_", numeric_id)) {
+                            if parse_plot_file_name(file_name).is_some_and(|(id, _, _)| id == numeric_id) {
                                 let path = entry.path();
                                 if path.is_file() {
                                     if let Ok(metadata) = fs::metadata(&path) {
@@ -984,6 +1230,7 @@ mod gui_app {
                         let path = Path::new(&self.path).join(&file_name);
                         match fs::remove_file(&path) {
                             Ok(_) => {
+                                let _ = fs::remove_file(resume_file(&path));
                                 self.logs.push(
                                     format!("Deleted most recent file (likely interrupted): {}", file_name)
                                 );
@@ -1313,12 +1560,23 @@ mod gui_app {
                 }
             };
 
+            let simd = self.settings.simd.as_deref().unwrap_or("auto");
+            let simd_ext = match select_simd(simd, (cpu_threads as usize).max(1)) {
+                Ok(simd_ext) => simd_ext,
+                Err(e) => {
+                    self.error = Some(e.to_string());
+                    return;
+                }
+            };
+
+            self.save_settings(&gpus);
+
             if self.low_priority {
                 set_low_prio();
             }
 
             let output_path = self.path.to_string_lossy().to_string();
-            let memory = "0B".to_string();
+            let memory = self.settings.mem.clone().unwrap_or_else(|| "0B".to_string());
 
             let direct_io = !self.disable_direct_io;
             let benchmark = self.benchmark;
@@ -1348,11 +1606,17 @@ mod gui_app {
             let stop_flag = Arc::new(AtomicBool::new(false));
             self.stop_flag = Some(stop_flag.clone());
 
-            let hasher = Hasher::new();
-
             // Calculate rounded nonces
             let rounded_nonces = if direct_io {
-                crate::utils::calculate_rounded_nonces(nonces, true, &output_path)
+                match calculate_rounded_nonces(nonces, true, &output_path, &GuiEvents::new(tx.clone())) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        self.error = Some(e.to_string());
+                        self.running = false;
+                        self.stop_flag = None;
+                        return;
+                    }
+                }
             } else {
                 nonces
             };
@@ -1378,36 +1642,11 @@ mod gui_app {
                 let output_path_clone = output_path.clone();
                 let memory_clone = memory.clone();
                 let gpus_clone = gpus.clone();
+                let simd_clone = simd_ext.clone();
                 let stop_flag_clone = stop_flag.clone();
 
                 thread::spawn(move || {
-                    let mut current_start = 0u64;
-                    if let Ok(entries) = std::fs::read_dir(&output_path_clone) {
-                        let mut max_end: u64 = 0;
-                        let prefix = format!("{}_", numeric_id);
-
-                        for entry in entries.flatten() {
-                            if let Some(file_name) = entry.file_name().to_str() {
-                                if file_name.starts_with(&prefix) {
-                                    let parts: Vec<&str> = file_name.split('_').collect();
-                                    if parts.len() >= 3 {
-                                        if
-                                            let (Ok(sn), Ok(cnt)) = (
-                                                parts[1].parse::<u64>(),
-                                                parts[2].parse::<u64>(),
-                                            )
-                                        {
-                                            let end = sn + cnt;
-                                            if end > max_end {
-                                                max_end = end;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        current_start = max_end;
-                    }
+                    let current_start = next_start_nonce(&output_path_clone, numeric_id);
 
                     let _ = tx_clone.send(
                         ProgressUpdate::Log(
@@ -1515,16 +1754,17 @@ mod gui_app {
                             numeric_id,
                             start_nonce: current_start + i * rounded_nonces,
                             nonces: rounded_nonces,
-                            output_path: output_path_clone.clone(),
+                            output_paths: vec![output_path_clone.clone()],
+                            known_paths: Vec::new(),
                             mem: memory_clone.clone(),
+                            buffers: 0,
                             cpu_threads,
                             gpus: gpus_clone.clone(),
                             direct_io,
                             benchmark,
+                            simd: Some(simd_clone.clone()),
                             #[cfg(feature = "opencl")]
                             zcb: zero_copy,
-                            #[cfg(feature = "gui")]
-                            progress_tx: Some(file_tx),
                             stop_flag: Some(stop_flag_clone.clone()),
                         };
 
@@ -1539,10 +1779,16 @@ mod gui_app {
                             )
                         );
 
-                        hasher.run(task);
+                        let result = Hasher::with_events(
+                            Arc::new(GuiEvents::new(file_tx))
+                        ).run(task);
 
                         let _ = progress_scaler.join();
 
+                        if result.is_err() {
+                            break;
+                        }
+
                         if stop_flag_clone.load(Ordering::Relaxed) {
                             let _ = tx_clone.send(
                                 ProgressUpdate::Log(
@@ -1561,22 +1807,22 @@ mod gui_app {
                     numeric_id,
                     start_nonce,
                     nonces: rounded_nonces,
-                    output_path,
+                    output_paths: vec![output_path],
+                    known_paths: Vec::new(),
                     mem: memory,
+                    buffers: 0,
                     cpu_threads,
                     gpus,
                     direct_io,
                     benchmark,
+                    simd: Some(simd_ext),
                     #[cfg(feature = "opencl")]
                     zcb: zero_copy,
-                    #[cfg(feature = "gui")]
-                    progress_tx: Some(tx.clone()),
                     stop_flag: Some(stop_flag.clone()),
                 };
 
                 thread::spawn(move || {
-                    hasher.run(task);
-                    let _ = tx.send(ProgressUpdate::Done);
+                    let _ = Hasher::with_events(Arc::new(GuiEvents::new(tx))).run(task);
                 });
             }
         }
@@ -1584,25 +1830,22 @@ mod gui_app {
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
     #[cfg(feature = "gui")]
-    if args.len() == 1 {
-        if let Err(e) = gui_app::launch() {
+    if std::env::args().len() == 1 {
+        if let Err(e) = gui_app::launch(None, None) {
             eprintln!("Failed to start GUI: {}", e);
             process::exit(1);
         }
         return;
     }
 
-    let mut cmd = Command::new("anne-hasher")
+    let cmd = Command::new("anne-hasher")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .arg_required_else_help(true)
         .arg(
             Arg::new("gui")
-                .short('g')
                 .long("gui")
                 .help("Launch graphical user interface")
                 .action(ArgAction::SetTrue)
@@ -1639,7 +1882,6 @@ fn main() {
                 .value_name("NUMERIC_ID")
                 .help("Your numeric Account ID")
                 .value_parser(clap::value_parser!(u64))
-                .required_unless_present("ocl_devices")
         )
         .arg(
             Arg::new("start_nonce")
@@ -1649,7 +1891,6 @@ fn main() {
                 .help("Starting nonce for hashing")
                 .value_parser(clap::value_parser!(u64))
                 .required_unless_present("start_nonce_auto")
-                .required_unless_present("ocl_devices")
         )
         .arg(
             Arg::new("start_nonce_auto")
@@ -1669,14 +1910,30 @@ fn main() {
                 .value_name("NONCES")
                 .help("How many nonces you want to add")
                 .value_parser(clap::value_parser!(u64))
-                .required_unless_present("ocl_devices")
         )
         .arg(
             Arg::new("path")
                 .short('p')
                 .long("path")
                 .value_name("PATH")
-                .help("Target path for hashfile (optional)")
+                .help("Target path for hashfile (optional), repeat to write one file per path concurrently")
+                .num_args(1..)
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("known_paths")
+                .long("known-path")
+                .value_name("PATH")
+                .help("Further directories with hash files that new files must not overlap (optional)")
+                .num_args(1..)
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("staging")
+                .long("staging")
+                .value_name("PATH")
+                .help("Hash into this fast temp directory and move finished files to --path in the background (optional)")
+                .conflicts_with("benchmark")
         )
         .arg(
             Arg::new("memory")
@@ -1686,6 +1943,13 @@ fn main() {
                 .help("Maximum memory usage (optional)")
                 .default_value("0B")
         )
+        .arg(
+            Arg::new("buffers")
+                .long("buffers")
+                .value_name("COUNT")
+                .help("Number of buffers the memory is split into, default is one per path plus one (optional)")
+                .value_parser(clap::value_parser!(u8).range(1..))
+        )
         .arg(
             Arg::new("cpu")
                 .short('c')
@@ -1702,35 +1966,373 @@ fn main() {
                 .help("GPU(s) you want to use for hashing (optional)")
                 .action(ArgAction::Append)
         )
-        .group(ArgGroup::new("processing").args(["cpu", "gpu"]).multiple(true));
+        .arg(
+            Arg::new("simd")
+                .long("simd")
+                .value_name("EXT")
+                .help("CPU hashing backend: auto, fastest (benchmarks the available ones), avx512f, avx2, avx, sse2, neon or rust")
+                .default_value("auto")
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("TOML file with defaults for these options and named profiles, flags override it (optional)")
+                .value_parser(clap::value_parser!(PathBuf))
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Profile of the config file to use on top of its defaults (optional)")
+                .requires("config")
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("FORMAT")
+                .help("text, or json for one JSON event per line on stdout instead of progress bars")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true)
+        )
+        .group(ArgGroup::new("processing").args(["cpu", "gpu"]).multiple(true))
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("verify")
+                .about("Recomputes nonces and checks them against existing hash files")
+                .arg(
+                    Arg::new("files")
+                        .value_name("FILE")
+                        .help("Hash files to check")
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("sample")
+                        .long("sample")
+                        .value_name("NONCES")
+                        .help("Quick check of NONCES randomly chosen nonces per file instead of all")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                )
+                .arg(
+                    Arg::new("cpu")
+                        .short('c')
+                        .long("cpu")
+                        .value_name("THREADS")
+                        .help("Maximum cpu cores you want to use (optional)")
+                        .value_parser(clap::value_parser!(u8))
+                )
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Benchmarks without writing hash files")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("simd")
+                        .about("Hashes a fixed number of nonces with every CPU backend available")
+                        .arg(
+                            Arg::new("cpu")
+                                .short('c')
+                                .long("cpu")
+                                .value_name("THREADS")
+                                .help("Threads to hash on, all cores by default")
+                                .value_parser(clap::value_parser!(u8))
+                        )
+                        .arg(
+                            Arg::new("nonces")
+                                .short('n')
+                                .long("n")
+                                .value_name("NONCES")
+                                .help("Nonces per thread and backend, a multiple of 16 fills the lanes of every backend")
+                                .default_value("32")
+                                .value_parser(clap::value_parser!(u64).range(1..))
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("inventory")
+                .about("Lists the hash files in directories and reports overlapping nonce ranges")
+                .arg(
+                    Arg::new("dirs")
+                        .value_name("DIR")
+                        .help("Directories to scan")
+                        .required(true)
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("numeric_id")
+                        .long("id")
+                        .value_name("NUMERIC_ID")
+                        .help("Only list hash files of this ID (optional)")
+                        .value_parser(clap::value_parser!(u64))
+                )
+        )
+        .subcommand(
+            Command::new("deadline")
+                .about("Mines existing hash files at a block and prints the best deadline per file")
+                .arg(
+                    Arg::new("files")
+                        .value_name("FILE")
+                        .help("Hash files to mine")
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("gensig")
+                        .long("gensig")
+                        .value_name("HEX")
+                        .help("Generation signature of the block, 64 hex digits")
+                        .required(true)
+                        .value_parser(|s: &str| parse_gensig(s).ok_or("expected 64 hex digits"))
+                )
+                .arg(
+                    Arg::new("height")
+                        .long("height")
+                        .value_name("HEIGHT")
+                        .help("Block height")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u64))
+                )
+                .arg(
+                    Arg::new("base_target")
+                        .long("base-target")
+                        .value_name("BASE_TARGET")
+                        .help("Base target of the block, prints deadlines in seconds (optional)")
+                        .value_parser(clap::value_parser!(u64))
+                )
+        )
+        .subcommand(
+            Command::new("convert")
+                .about("Converts hash files between PoC1 and PoC2, in place or into another directory")
+                .arg(
+                    Arg::new("files")
+                        .value_name("FILE")
+                        .help("Hash files to convert")
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_name("DIR")
+                        .help("Write the converted files to DIR and keep the originals (optional)")
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("FORMAT")
+                        .help("Format to convert to, files already converted to it are refused")
+                        .required(true)
+                        .value_parser(["poc1", "poc2"])
+                )
+                .arg(
+                    Arg::new("memory")
+                        .short('m')
+                        .long("mem")
+                        .value_name("MEMORY")
+                        .help("Buffer size and journal size in place, default is 1GiB (optional)")
+                        .default_value("0B")
+                )
+        )
+        .subcommand(
+            Command::new("reorder")
+                .about("Rewrites legacy {id}_{start}_{nonces}_{stagger} hash files in the optimized layout")
+                .arg(
+                    Arg::new("files")
+                        .value_name("FILE")
+                        .help("Hash files to reorder")
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_name("DIR")
+                        .help("Write the reordered files to DIR instead of next to the originals (optional)")
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("poc1")
+                        .long("poc1")
+                        .help("The files are PoC1, convert them to PoC2 as well")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("memory")
+                        .short('m')
+                        .long("mem")
+                        .value_name("MEMORY")
+                        .help("Buffer size, default is 1GiB (optional)")
+                        .default_value("0B")
+                )
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merges hash files with consecutive nonces into one file without re-hashing")
+                .arg(
+                    Arg::new("files")
+                        .value_name("FILE")
+                        .help("Hash files to merge, in any order")
+                        .required(true)
+                        .num_args(2..)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_name("DIR")
+                        .help("Write the merged file to DIR instead of next to the first file (optional)")
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("delete")
+                        .long("delete")
+                        .help("Delete the merged files once the new file is complete")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("memory")
+                        .short('m')
+                        .long("mem")
+                        .value_name("MEMORY")
+                        .help("Buffer size, default is 1GiB (optional)")
+                        .default_value("0B")
+                )
+        )
+        .subcommand(
+            Command::new("split")
+                .about("Splits a hash file into smaller files without re-hashing")
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .help("Hash file to split")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("nonces")
+                        .short('n')
+                        .long("n")
+                        .value_name("NONCES")
+                        .help("Nonces per new file, the last one gets the rest")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_name("DIR")
+                        .help("Write the new files to DIR instead of next to the original (optional)")
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("delete")
+                        .long("delete")
+                        .help("Delete the original file once all new files are complete")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("memory")
+                        .short('m')
+                        .long("mem")
+                        .value_name("MEMORY")
+                        .help("Buffer size, default is 1GiB (optional)")
+                        .default_value("0B")
+                )
+        )
+        .subcommand(
+            Command::new("daemon")
+                .about("Runs the plotting jobs queued as JSON files in a directory, resuming them after a restart")
+                .arg(
+                    Arg::new("queue")
+                        .value_name("DIR")
+                        .help("Queue directory, every NAME.json is a job and NAME.state records how it went")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    Arg::new("per_disk")
+                        .long("per-disk")
+                        .help("Run jobs on different disks at the same time")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("once")
+                        .long("once")
+                        .help("Exit when no job is left instead of waiting for new ones")
+                        .action(ArgAction::SetTrue)
+                )
+        );
 
     #[cfg(feature = "opencl")]
-    {
-        cmd = cmd
-            .arg(
-                Arg::new("ocl_devices")
-                    .short('o')
-                    .long("opencl")
-                    .help("Display OpenCL platforms and devices")
-                    .action(ArgAction::SetTrue)
-                    .global(true)
-            )
-            .arg(
-                Arg::new("zero_copy")
-                    .short('z')
-                    .long("zcb")
-                    .help("Enables zero copy buffers for shared mem (integrated) gpus")
-                    .action(ArgAction::SetTrue)
-                    .global(true)
-            );
-    }
+    let cmd = cmd
+        .arg(
+            Arg::new("ocl_devices")
+                .short('o')
+                .long("opencl")
+                .help("Display OpenCL platforms and devices")
+                .action(ArgAction::SetTrue)
+                .global(true)
+        )
+        .arg(
+            Arg::new("zero_copy")
+                .short('z')
+                .long("zcb")
+                .help("Enables zero copy buffers for shared mem (integrated) gpus")
+                .action(ArgAction::SetTrue)
+                .global(true)
+        )
+        .mut_arg("numeric_id", |a| a.required_unless_present("ocl_devices"))
+        .mut_arg("start_nonce", |a| a.required_unless_present("ocl_devices"))
+        .mut_arg("nonces", |a| a.required_unless_present("ocl_devices"));
+
+    #[cfg(not(feature = "opencl"))]
+    let cmd = cmd
+        .mut_arg("numeric_id", |a| a.required(true))
+        .mut_arg("nonces", |a| a.required(true));
+
+    #[cfg(feature = "http")]
+    let cmd = cmd
+        .arg(
+            Arg::new("http")
+                .long("http")
+                .value_name("ADDR")
+                .help("Serves status and control JSON on ADDR (e.g. 127.0.0.1:8080), tasks posted to it run after the given one, or on their own without --id/--n")
+        )
+        .mut_arg("numeric_id", |a| a.required(false).required_unless_present("http"))
+        .mut_arg("nonces", |a| a.required(false).required_unless_present("http"))
+        .mut_arg("start_nonce", |a| a.required_unless_present("http"));
+
+    #[cfg(feature = "metrics")]
+    let cmd = cmd.arg(
+        Arg::new("metrics")
+            .long("metrics")
+            .value_name("ADDR")
+            .help("Serves Prometheus metrics on http://ADDR/metrics (e.g. 0.0.0.0:9184)")
+    );
+
+    let cmd = cmd
+        .mut_arg("numeric_id", |a| a.required(false).required_unless_present("config"))
+        .mut_arg("nonces", |a| a.required(false).required_unless_present("config"));
 
     let matches = cmd.get_matches();
+    if matches.get_one::<String>("output").is_some_and(|output| output == "json") {
+        let _ = JSON_OUTPUT.set(Arc::new(JsonEvents::new()));
+    }
+    let config_file = matches.get_one::<PathBuf>("config").cloned();
+    let profile = matches.get_one::<String>("profile").cloned();
 
     if matches.get_flag("gui") {
         #[cfg(feature = "gui")]
         {
-            if let Err(e) = gui_app::launch() {
+            if let Err(e) = gui_app::launch(config_file, profile) {
                 eprintln!("Failed to start GUI: {}", e);
                 process::exit(1);
             }
@@ -1738,7 +2340,14 @@ fn main() {
         }
     }
 
-    if matches.get_flag("low_priority") {
+    // flags override the profile, which overrides the defaults of the file
+    let settings = match &config_file {
+        Some(file) => exit_on_error(Config::load(file).and_then(|config| config.settings(profile.as_deref()))),
+        None => Settings::default(),
+    }
+    .merged(&cli_settings(&matches));
+
+    if settings.low_priority == Some(true) {
         set_low_prio();
     }
 
@@ -1748,142 +2357,593 @@ fn main() {
         return;
     }
 
-    let numeric_id = *matches.get_one::<u64>("numeric_id").expect("numeric_id required");
+    if let Some(("verify", verify_matches)) = matches.subcommand() {
+        verify(verify_matches);
+        exit_with(0);
+    }
 
-    let nonces = *matches.get_one::<u64>("nonces").expect("nonces required");
+    if let Some(("bench", bench_matches)) = matches.subcommand() {
+        if let Some(("simd", simd_matches)) = bench_matches.subcommand() {
+            bench(simd_matches);
+        }
+        exit_with(0);
+    }
 
-    let output_path = matches
-        .get_one::<String>("path")
-        .cloned()
-        .unwrap_or_else(|| {
-            std::env::current_dir().unwrap().into_os_string().into_string().unwrap()
-        });
+    if let Some(("inventory", inventory_matches)) = matches.subcommand() {
+        inventory(inventory_matches);
+        exit_with(0);
+    }
 
-    let mem = matches.get_one::<String>("memory").cloned().unwrap();
+    if let Some(("deadline", deadline_matches)) = matches.subcommand() {
+        deadline(deadline_matches);
+        exit_with(0);
+    }
 
-    let cpu_threads_input = matches.get_one::<u8>("cpu").copied().unwrap_or(0);
+    if let Some(("convert", convert_matches)) = matches.subcommand() {
+        convert(convert_matches);
+        exit_with(0);
+    }
 
-    let gpus: Option<Vec<String>> = matches.get_many::<String>("gpu").map(|v| v.cloned().collect());
+    if let Some(("daemon", daemon_matches)) = matches.subcommand() {
+        let task = DaemonTask {
+            queue_dir: daemon_matches.get_one::<PathBuf>("queue").unwrap().clone(),
+            per_disk: daemon_matches.get_flag("per_disk"),
+            once: daemon_matches.get_flag("once"),
+            stop_flag: Some(install_stop_handler()),
+        };
+        let events = cli_events();
+        let report = exit_on_error(run_daemon(&task, events.clone()));
+        events.on_event(&HasherEvent::Log(report.to_string()));
+        exit_with(if report.stopped { 130 } else { 0 });
+    }
+
+    if let Some(("reorder", reorder_matches)) = matches.subcommand() {
+        reorder(reorder_matches);
+        exit_with(0);
+    }
+
+    if let Some(("merge", merge_matches)) = matches.subcommand() {
+        let task = MergeTask {
+            files: merge_matches.get_many::<PathBuf>("files").unwrap().cloned().collect(),
+            output_dir: merge_matches.get_one::<PathBuf>("out").cloned(),
+            remove_sources: merge_matches.get_flag("delete"),
+            mem: merge_matches.get_one::<String>("memory").unwrap().clone(),
+            direct_io: !merge_matches.get_flag("disable_direct_io"),
+            stop_flag: Some(install_stop_handler()),
+        };
+        exit_after_repack(merge_plot_files(&task, cli_events().as_ref()));
+    }
+
+    if let Some(("split", split_matches)) = matches.subcommand() {
+        let task = SplitTask {
+            file: split_matches.get_one::<PathBuf>("file").unwrap().clone(),
+            nonces: *split_matches.get_one::<u64>("nonces").unwrap(),
+            output_dir: split_matches.get_one::<PathBuf>("out").cloned(),
+            remove_source: split_matches.get_flag("delete"),
+            mem: split_matches.get_one::<String>("memory").unwrap().clone(),
+            direct_io: !split_matches.get_flag("disable_direct_io"),
+            stop_flag: Some(install_stop_handler()),
+        };
+        exit_after_repack(split_plot_file(&task, cli_events().as_ref()));
+    }
+
+    let stop_flag = install_stop_handler();
+    let events = cli_events();
+
+    #[cfg(feature = "metrics")]
+    let (events, _metrics_server) = match matches.get_one::<String>("metrics") {
+        Some(addr) => {
+            let metrics = Metrics::new();
+            let server = exit_on_error(MetricsServer::start(addr, metrics.clone()));
+            if let Some(addr) = server.local_addr() {
+                events.on_event(&HasherEvent::Log(format!("Metrics on http://{}/metrics", addr)));
+            }
+            let events: Arc<dyn HasherEvents> = Arc::new(MetricsEvents::new(metrics, events));
+            (events, Some(server))
+        }
+        None => (events, None),
+    };
+
+    #[cfg(feature = "http")]
+    let api = matches.get_one::<String>("http").map(|addr| start_api(addr, events.as_ref()));
+    #[cfg(feature = "http")]
+    let events: Arc<dyn HasherEvents> = match &api {
+        Some((state, _)) => Arc::new(ApiEvents::new(state.clone(), events)),
+        None => events,
+    };
+    let p = Hasher::with_events(events.clone());
+    let log = |msg: String| events.on_event(&HasherEvent::Log(msg));
+
+    #[cfg(feature = "http")]
+    if let Some((state, _server)) = &api
+        && settings.numeric_id.is_none()
+    {
+        let code = run_queue(&p, state, &stop_flag, events.as_ref());
+        exit_with(code);
+    }
+
+    let numeric_id = settings.numeric_id.unwrap_or_else(|| missing_setting("--id", "numeric_id"));
+
+    let nonces = settings.nonces.unwrap_or_else(|| missing_setting("--n", "nonces"));
+
+    let output_paths: Vec<String> = match &settings.paths {
+        Some(paths) => paths.clone(),
+        None => vec![std::env::current_dir().unwrap().into_os_string().into_string().unwrap()],
+    };
+    let stripes = output_paths.len() as u64;
+
+    // with a staging directory every file is hashed there and moved afterwards
+    let staging = matches.get_one::<String>("staging").cloned();
+    let hash_paths = match &staging {
+        Some(dir) => vec![dir.clone(); output_paths.len()],
+        None => output_paths.clone(),
+    };
+    // staged files end up on the output paths, nonces are rounded for both
+    let sector_paths = match &staging {
+        Some(dir) => [std::slice::from_ref(dir), &output_paths[..]].concat(),
+        None => output_paths.clone(),
+    };
+    // new files must not repeat nonces of any file in these, staged ones included
+    let mut known_paths: Vec<String> = matches
+        .get_many::<String>("known_paths")
+        .map(|paths| paths.cloned().collect())
+        .unwrap_or_default();
+    if staging.is_some() {
+        known_paths.extend(output_paths.iter().cloned());
+    }
+
+    let mem = settings.mem.clone().unwrap_or_else(|| "0B".to_string());
+    let buffers = matches.get_one::<u8>("buffers").copied().unwrap_or(0);
+
+    let cpu_threads_input = settings.cpu.unwrap_or(0);
+
+    let gpus: Option<Vec<String>> = settings.gpus.clone();
 
     let cores = sys_info::cpu_num().unwrap() as u8;
-    let mut cpu_threads = if cpu_threads_input == 0 {
+    let cpu_threads = if
+        cfg!(feature = "opencl") &&
+        settings.gpus.is_some() &&
+        settings.cpu.is_none()
+    {
+        0
+    } else if cpu_threads_input == 0 {
         cores
     } else {
         min(2 * cores, cpu_threads_input)
     };
 
-    #[cfg(feature = "opencl")]
-    if matches.contains_id("gpu") && !matches.contains_id("cpu") {
-        cpu_threads = 0;
+    let simd = settings.simd.as_deref().unwrap_or("auto");
+    let simd_ext = exit_on_error(select_simd(simd, cpu_threads.max(1) as usize));
+    let direct_io = settings.direct_io.unwrap_or(true);
+    if simd == "fastest" {
+        log(format!("Fastest CPU backend: {}", simd_ext.name()));
     }
 
-    let p = Hasher::new();
+    let mut mover = staging.as_ref().map(|_| Mover::new(events.clone()));
 
     if let Some(&auto_count) = matches.get_one::<u64>("start_nonce_auto") {
         if auto_count == 0 {
-            eprintln!("Error: --sna count must be >= 1");
-            process::exit(1);
+            fail(1, "--sna count must be >= 1");
         }
 
-        println!("--sna enabled: hashing {auto_count} sequential file(s)");
+        log(format!("--sna enabled: hashing {auto_count} sequential file(s) per path"));
 
-        let mut current_start = 0u64;
-
-        let rounded_nonces = if !matches.get_flag("disable_direct_io") {
-            calculate_rounded_nonces(nonces, true, &output_path)
+        let rounded_nonces = if direct_io {
+            exit_on_error(rounded_nonces_for_paths(nonces, &sector_paths, events.as_ref()))
         } else {
             nonces
         };
-
-        if let Ok(entries) = std::fs::read_dir(&output_path) {
-            let mut max_end: u64 = 0;
-            let prefix = format!("{}_", numeric_id);
-
-            for entry in entries.flatten() {
-                if let Some(file_name) = entry.file_name().to_str() {
-                    if file_name.starts_with(&prefix) {
-                        let parts: Vec<&str> = file_name.split('_').collect();
-                        if parts.len() >= 3 {
-                            if
-                                let (Ok(sn), Ok(cnt)) = (
-                                    parts[1].parse::<u64>(),
-                                    parts[2].parse::<u64>(),
-                                )
-                            {
-                                let end = sn + cnt;
-                                if end > max_end {
-                                    max_end = end;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            current_start = max_end;
+        if staging.is_some() {
+            exit_on_error(check_dest_space(&output_paths, rounded_nonces * auto_count));
         }
 
-        println!("Starting from nonce {current_start}");
+        // continue after the highest nonce on any of the disks
+        let current_start = Inventory::scan(&[&hash_paths[..], &known_paths[..]].concat())
+            .next_start_nonce(numeric_id);
+
+        log(format!("Starting from nonce {current_start}"));
         if rounded_nonces != nonces {
-            println!("Using rounded nonces per file: {} (original: {})", rounded_nonces, nonces);
+            log(format!("Using rounded nonces per file: {} (original: {})", rounded_nonces, nonces));
         }
 
         for i in 0..auto_count {
-            let this_start = current_start + i * rounded_nonces;
+            let this_start = current_start + i * rounded_nonces * stripes;
 
-            println!(
-                "\n--- Hashing file {} of {auto_count}: start_nonce = {this_start} ---",
-                i + 1
-            );
+            log(format!("\n--- Hashing file {} of {auto_count}: start_nonce = {this_start} ---", i + 1));
 
             let file_task = HasherTask {
                 numeric_id,
                 start_nonce: this_start,
                 nonces: rounded_nonces,
-                output_path: output_path.clone(),
+                output_paths: hash_paths.clone(),
+                known_paths: known_paths.clone(),
                 mem: mem.clone(),
+                buffers,
                 cpu_threads,
                 gpus: gpus.clone(),
-                direct_io: !matches.get_flag("disable_direct_io"),
+                direct_io,
                 benchmark: matches.get_flag("benchmark"),
+                simd: Some(simd_ext.clone()),
                 #[cfg(feature = "opencl")]
-                zcb: matches.get_flag("zero_copy"),
-                #[cfg(feature = "gui")]
-                progress_tx: None,
-                stop_flag: None,
+                zcb: settings.zcb.unwrap_or(false),
+                stop_flag: Some(stop_flag.clone()),
             };
 
-            p.run(file_task);
+            #[cfg(feature = "http")]
+            if let Some((state, _)) = &api {
+                state.start(&file_task);
+            }
+            after_run(p.run(file_task), &mut mover, &output_paths, events.as_ref());
         }
     } else {
         let start_nonce = *matches
             .get_one::<u64>("start_nonce")
             .expect("--sn is required when not using --sna");
 
-        let final_nonces = if !matches.get_flag("disable_direct_io") {
-            calculate_rounded_nonces(nonces, true, &output_path)
+        let final_nonces = if direct_io {
+            exit_on_error(rounded_nonces_for_paths(nonces, &sector_paths, events.as_ref()))
         } else {
             nonces
         };
+        if staging.is_some() {
+            exit_on_error(check_dest_space(&output_paths, final_nonces));
+        }
 
         if final_nonces != nonces {
-            println!("Using rounded nonces: {} (original: {})", final_nonces, nonces);
+            log(format!("Using rounded nonces: {} (original: {})", final_nonces, nonces));
         }
 
-        p.run(HasherTask {
+        let task = HasherTask {
             numeric_id,
             start_nonce,
             nonces: final_nonces,
-            output_path,
+            output_paths: hash_paths,
+            known_paths,
             mem,
+            buffers,
             cpu_threads,
             gpus,
-            direct_io: !matches.get_flag("disable_direct_io"),
+            direct_io,
             benchmark: matches.get_flag("benchmark"),
+            simd: Some(simd_ext),
             #[cfg(feature = "opencl")]
-            zcb: matches.get_flag("zero_copy"),
-            #[cfg(feature = "gui")]
-            progress_tx: None,
-            stop_flag: None,
+            zcb: settings.zcb.unwrap_or(false),
+            stop_flag: Some(stop_flag.clone()),
+        };
+        #[cfg(feature = "http")]
+        if let Some((state, _)) = &api {
+            state.start(&task);
+        }
+        after_run(p.run(task), &mut mover, &output_paths, events.as_ref());
+    }
+
+    #[cfg(feature = "http")]
+    if let Some((state, _server)) = &api {
+        let code = run_queue(&p, state, &stop_flag, events.as_ref());
+        if code != 0 {
+            exit_with(code);
+        }
+    }
+
+    if let Some(mover) = mover {
+        log("Waiting for staged files to be moved...".to_string());
+        exit_on_error(mover.finish());
+    }
+    exit_with(0);
+}
+
+/// The options given on the command line, defaults of clap left out so that
+/// they don't override the config file.
+fn cli_settings(matches: &clap::ArgMatches) -> Settings {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    Settings {
+        numeric_id: matches.get_one::<u64>("numeric_id").copied(),
+        nonces: matches.get_one::<u64>("nonces").copied(),
+        paths: matches.get_many::<String>("path").map(|paths| paths.cloned().collect()),
+        mem: matches.get_one::<String>("memory").filter(|_| given("memory")).cloned(),
+        cpu: matches.get_one::<u8>("cpu").copied(),
+        gpus: matches.get_many::<String>("gpu").map(|gpus| gpus.cloned().collect()),
+        direct_io: given("disable_direct_io").then_some(false),
+        #[cfg(feature = "opencl")]
+        zcb: given("zero_copy").then_some(true),
+        #[cfg(not(feature = "opencl"))]
+        zcb: None,
+        low_priority: given("low_priority").then_some(true),
+        simd: matches.get_one::<String>("simd").filter(|_| given("simd")).cloned(),
+    }
+}
+
+fn missing_setting(flag: &str, key: &str) -> ! {
+    fail(2, format!("{} is required, on the command line or as {} in the config file", flag, key));
+}
+
+/// Exits after a failed or stopped run, otherwise hands the finished files
+/// to the mover when staging.
+fn after_run(
+    result: Result<HashReport, HasherError>,
+    mover: &mut Option<Mover>,
+    output_paths: &[String],
+    events: &dyn HasherEvents
+) {
+    // the console sink has already reported the error
+    let code = match result {
+        Ok(report) if !report.stopped => {
+            if let Some(mover) = mover.as_mut() {
+                for (file, dest) in report.files.into_iter().zip(output_paths) {
+                    exit_on_error(mover.queue(file, PathBuf::from(dest)));
+                }
+            }
+            return;
+        }
+        Ok(_) => 130,
+        Err(_) => 1,
+    };
+    // files that were already handed over still get moved
+    if let Some(mover) = mover.take() {
+        events.on_event(&HasherEvent::Log("Waiting for staged files to be moved...".to_string()));
+        exit_on_error(mover.finish());
+    }
+    exit_with(code);
+}
+
+/// Set right after parsing when the command line asks for `--output json`.
+static JSON_OUTPUT: OnceLock<Arc<JsonEvents>> = OnceLock::new();
+
+/// The sink of every subcommand, JSON lines or the console.
+fn cli_events() -> Arc<dyn HasherEvents> {
+    match JSON_OUTPUT.get() {
+        Some(json) => json.clone(),
+        None => Arc::new(ConsoleEvents::new()),
+    }
+}
+
+/// Reports an error on stderr, or as an error event with `--output json`.
+fn report_error(e: &dyn Display) {
+    match JSON_OUTPUT.get() {
+        Some(json) => json.on_event(&HasherEvent::Error(e.to_string())),
+        None => eprintln!("Error: {}", e),
+    }
+}
+
+/// Exits with `code`, after the summary line with `--output json`.
+fn exit_with(code: i32) -> ! {
+    if let Some(json) = JSON_OUTPUT.get() {
+        json.summary(code);
+    }
+    process::exit(code);
+}
+
+fn fail(code: i32, e: impl Display) -> ! {
+    report_error(&e);
+    exit_with(code);
+}
+
+fn verify(matches: &clap::ArgMatches) {
+    let events = cli_events();
+    let mut all_ok = true;
+    for file in matches.get_many::<PathBuf>("files").unwrap() {
+        let task = VerifyTask {
+            file: file.clone(),
+            sample: matches.get_one::<u64>("sample").copied(),
+            cpu_threads: matches.get_one::<u8>("cpu").copied().unwrap_or(0),
+        };
+        match verify_plot_file(&task, events.as_ref()) {
+            Ok(report) => all_ok &= report.is_ok(),
+            Err(e) => {
+                report_error(&e);
+                all_ok = false;
+            }
+        }
+    }
+    if !all_ok {
+        exit_with(1);
+    }
+}
+
+/// Ctrl-C/SIGTERM let the writer finish its current buffer and save the exact
+/// resume point, a second signal quits right away.
+fn install_stop_handler() -> Arc<AtomicBool> {
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let flag = stop_flag.clone();
+    let result = ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::Relaxed) {
+            let msg = "Forced shutdown, resume info may be behind the data on disk.";
+            match JSON_OUTPUT.get() {
+                Some(json) => json.on_event(&HasherEvent::Error(msg.to_string())),
+                None => eprintln!("\n{}", msg),
+            }
+            exit_with(130);
+        }
+        let msg = "Stopping after the current buffer, press Ctrl-C again to force quit...";
+        match JSON_OUTPUT.get() {
+            Some(json) => json.on_event(&HasherEvent::Warning(msg.to_string())),
+            None => eprintln!("\n{}", msg),
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: couldn't install signal handler: {}", e);
+    }
+    stop_flag
+}
+
+fn bench(matches: &clap::ArgMatches) {
+    let threads = match matches.get_one::<u8>("cpu") {
+        Some(&threads) if threads > 0 => threads as usize,
+        _ => sys_info::cpu_num().unwrap() as usize,
+    };
+    let nonces = *matches.get_one::<u64>("nonces").unwrap();
+    let events = cli_events();
+    let log = |msg: String| events.on_event(&HasherEvent::Log(msg));
+    log(format!("Hashing {} nonces on each of {} thread(s) per backend", nonces, threads));
+
+    let mut fastest = None;
+    for simd_ext in supported_simd() {
+        let speed = bench_simd(&simd_ext, threads, nonces);
+        log(format!("{:>8}: {:>10.0} nonces/m", simd_ext.name(), speed));
+        if fastest.as_ref().is_none_or(|(_, best)| speed > *best) {
+            fastest = Some((simd_ext, speed));
+        }
+    }
+    if let Some((simd_ext, _)) = fastest {
+        log(format!("Fastest: {}, use it with --simd {}", simd_ext.name(), simd_ext.name()));
+    }
+}
+
+fn inventory(matches: &clap::ArgMatches) {
+    let dirs: Vec<&String> = matches.get_many::<String>("dirs").unwrap().collect();
+    let mut inventory = Inventory::scan(&dirs);
+    if let Some(numeric_id) = matches.get_one::<u64>("numeric_id") {
+        inventory.plots.retain(|id, _| id == numeric_id);
+    }
+    cli_events().on_event(&HasherEvent::Log(inventory.to_string()));
+    if !inventory.overlaps().is_empty() {
+        exit_with(1);
+    }
+}
+
+fn deadline(matches: &clap::ArgMatches) {
+    let events = cli_events();
+    let mut failed = false;
+    for file in matches.get_many::<PathBuf>("files").unwrap() {
+        let task = DeadlineTask {
+            file: file.clone(),
+            gensig: *matches.get_one::<[u8; 32]>("gensig").unwrap(),
+            height: *matches.get_one::<u64>("height").unwrap(),
+            base_target: matches.get_one::<u64>("base_target").copied(),
+        };
+        if let Err(e) = find_best_deadline(&task, events.as_ref()) {
+            report_error(&e);
+            failed = true;
+        }
+    }
+    if failed {
+        exit_with(1);
+    }
+}
+
+fn convert(matches: &clap::ArgMatches) {
+    let events = cli_events();
+    let stop_flag = install_stop_handler();
+    for file in matches.get_many::<PathBuf>("files").unwrap() {
+        let task = ConvertTask {
+            file: file.clone(),
+            output_dir: matches.get_one::<PathBuf>("out").cloned(),
+            mem: matches.get_one::<String>("memory").unwrap().clone(),
+            direct_io: !matches.get_flag("disable_direct_io"),
+            poc1: matches.get_one::<String>("to").unwrap() == "poc1",
+            stop_flag: Some(stop_flag.clone()),
+        };
+        match convert_plot_file(&task, events.as_ref()) {
+            Ok(report) if report.stopped => exit_with(130),
+            Ok(_) => {}
+            Err(e) => fail(1, e),
+        }
+    }
+}
+
+fn reorder(matches: &clap::ArgMatches) {
+    let events = cli_events();
+    let stop_flag = install_stop_handler();
+    for file in matches.get_many::<PathBuf>("files").unwrap() {
+        let task = ReorderTask {
+            file: file.clone(),
+            output_dir: matches.get_one::<PathBuf>("out").cloned(),
+            poc1: matches.get_flag("poc1"),
+            mem: matches.get_one::<String>("memory").unwrap().clone(),
+            direct_io: !matches.get_flag("disable_direct_io"),
+            stop_flag: Some(stop_flag.clone()),
+        };
+        match reorder_plot_file(&task, events.as_ref()) {
+            Ok(report) if report.stopped => exit_with(130),
+            Ok(_) => {}
+            Err(e) => fail(1, e),
+        }
+    }
+}
+
+fn exit_after_repack(result: Result<RepackReport, HasherError>) -> ! {
+    match exit_on_error(result) {
+        report if report.stopped => exit_with(130),
+        _ => exit_with(0),
+    }
+}
+
+#[cfg(feature = "http")]
+fn start_api(addr: &str, events: &dyn HasherEvents) -> (Arc<ApiState>, ApiServer) {
+    let state = ApiState::new();
+    let server = exit_on_error(ApiServer::start(addr, state.clone()));
+    if let Some(addr) = server.local_addr() {
+        events.on_event(&HasherEvent::Log(format!("HTTP API listening on http://{}", addr)));
+    }
+    (state, server)
+}
+
+/// Runs the tasks posted to the HTTP API one after another until Ctrl-C.
+/// `POST /stop` only stops the running task, a stopped or failed task is
+/// reported and the next one runs. Returns the exit code, 130 when Ctrl-C
+/// stopped a running task.
+#[cfg(feature = "http")]
+fn run_queue(hasher: &Hasher, state: &ApiState, stop_flag: &Arc<AtomicBool>, events: &dyn HasherEvents) -> i32 {
+    events.on_event(
+        &HasherEvent::Log("Waiting for tasks from the HTTP API, press Ctrl-C to quit...".to_string())
+    );
+    while !stop_flag.load(Ordering::Relaxed) {
+        let Some(spec) = state.next_task(Duration::from_secs(1)) else {
+            continue;
+        };
+        // a flag of its own, so POST /stop ends just this task
+        let task_flag = Arc::new(AtomicBool::new(false));
+        let task = match spec.to_task(task_flag.clone(), events) {
+            Ok(task) => task,
+            Err(e) => {
+                report_error(&e);
+                state.fail(e.to_string());
+                continue;
+            }
+        };
+        state.start(&task);
+        let done = AtomicBool::new(false);
+        let result = thread::scope(|scope| {
+            // pass Ctrl-C on to the task
+            scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    if stop_flag.load(Ordering::Relaxed) {
+                        task_flag.store(true, Ordering::Relaxed);
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+            });
+            let result = hasher.run(task);
+            done.store(true, Ordering::Relaxed);
+            result
         });
+        if let Ok(report) = result
+            && report.stopped
+            && stop_flag.load(Ordering::Relaxed)
+        {
+            return 130;
+        }
     }
+    0
+}
+
+/// Nonces per file rounded so that direct i/o works on every path.
+fn rounded_nonces_for_paths(
+    nonces: u64,
+    output_paths: &[String],
+    events: &dyn HasherEvents
+) -> Result<u64, HasherError> {
+    output_paths
+        .iter()
+        .try_fold(nonces, |nonces, path| calculate_rounded_nonces(nonces, true, path, events))
+}
+
+fn exit_on_error<T>(result: Result<T, HasherError>) -> T {
+    result.unwrap_or_else(|e| {
+        report_error(&e);
+        if JSON_OUTPUT.get().is_none() {
+            eprintln!("Shutting down...");
+        }
+        exit_with(1)
+    })
 }
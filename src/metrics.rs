@@ -0,0 +1,257 @@
+//! Prometheus metrics of a running hasher.
+//!
+//! [`MetricsEvents`] counts the events of the running [`Hasher`](crate::Hasher)
+//! into [`Metrics`], which [`MetricsServer`] serves in the Prometheus text
+//! format on `GET /metrics`. Counters add up over all runs of the process.
+
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents, Phase};
+use crate::hasher::NONCE_SIZE;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(Default)]
+struct Counters {
+    nonces_hashed: u64,
+    /// Nonces and nonces per minute by device, see [`HasherEvent::DeviceHashed`].
+    device_nonces: BTreeMap<u8, u64>,
+    device_speed: BTreeMap<u8, f64>,
+    bytes_written: u64,
+    /// Nonces on disk the last `Written` event of the run reported.
+    written: u64,
+    hasher_stall: Duration,
+    writer_stall: Duration,
+    checkpoints: u64,
+    write_errors: u64,
+    running: bool,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Metrics> {
+        Arc::new(Metrics::default())
+    }
+
+    fn on_event(&self, event: &HasherEvent) {
+        let mut counters = self.counters.lock().unwrap();
+        match event {
+            HasherEvent::Phase(Phase::Hashing { resumed_from, .. })
+            | HasherEvent::Phase(Phase::Converting { resumed_from, .. })
+            | HasherEvent::Phase(Phase::Repacking { resumed_from, .. }) => {
+                counters.running = true;
+                counters.written = *resumed_from;
+                counters.device_speed.clear();
+            }
+            HasherEvent::DeviceHashed { device, nonces } => {
+                counters.nonces_hashed += nonces;
+                *counters.device_nonces.entry(*device).or_default() += nonces;
+            }
+            HasherEvent::DeviceHashSpeed { device, speed } => {
+                counters.device_speed.insert(*device, *speed);
+            }
+            // progress within a buffer is estimated, so only count what's new
+            HasherEvent::Written(nonces) if *nonces > counters.written => {
+                counters.bytes_written += (nonces - counters.written) * NONCE_SIZE;
+                counters.written = *nonces;
+            }
+            HasherEvent::HasherStall(wait) => counters.hasher_stall += *wait,
+            HasherEvent::WriterStall(wait) => counters.writer_stall += *wait,
+            HasherEvent::Checkpoint { .. } => counters.checkpoints += 1,
+            HasherEvent::WriteError { .. } => counters.write_errors += 1,
+            HasherEvent::Error(_)
+            | HasherEvent::Finished(_)
+            | HasherEvent::Converted(_)
+            | HasherEvent::Repacked(_) => {
+                counters.running = false;
+                counters.device_speed.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            let _ = writeln!(out, "# HELP anne_hasher_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE anne_hasher_{} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "anne_hasher_{}{} {}", name, labels, value);
+            }
+        };
+        let device = |device: u8| match device {
+            0 => "{device=\"cpu\"}".to_string(),
+            n => format!("{{device=\"gpu{}\"}}", n - 1),
+        };
+
+        metric(
+            "nonces_hashed_total",
+            "counter",
+            "Nonces hashed by all devices.",
+            vec![(String::new(), counters.nonces_hashed.to_string())],
+        );
+        metric(
+            "device_nonces_hashed_total",
+            "counter",
+            "Nonces hashed by the CPU thread pool and each GPU.",
+            counters.device_nonces.iter().map(|(d, n)| (device(*d), n.to_string())).collect(),
+        );
+        metric(
+            "device_hash_rate",
+            "gauge",
+            "Nonces per minute of the CPU thread pool and each GPU in the current run.",
+            counters.device_speed.iter().map(|(d, s)| (device(*d), format!("{:.1}", s))).collect(),
+        );
+        metric(
+            "bytes_written_total",
+            "counter",
+            "Bytes of plot files written.",
+            vec![(String::new(), counters.bytes_written.to_string())],
+        );
+        metric(
+            "buffer_wait_seconds_total",
+            "counter",
+            "Time hashing waited for empty buffers and the writers waited for full ones.",
+            vec![
+                ("{stage=\"hashing\"}".to_string(), format!("{:.3}", counters.hasher_stall.as_secs_f64())),
+                ("{stage=\"writing\"}".to_string(), format!("{:.3}", counters.writer_stall.as_secs_f64())),
+            ],
+        );
+        metric(
+            "resume_checkpoints_total",
+            "counter",
+            "Resume info updates after written buffers.",
+            vec![(String::new(), counters.checkpoints.to_string())],
+        );
+        metric(
+            "write_errors_total",
+            "counter",
+            "Failed writes of plot files or their resume info.",
+            vec![(String::new(), counters.write_errors.to_string())],
+        );
+        metric(
+            "running",
+            "gauge",
+            "Whether a run is hashing or copying right now.",
+            vec![(String::new(), u8::from(counters.running).to_string())],
+        );
+        out
+    }
+}
+
+/// Counts events into [`Metrics`] and passes every event on to `inner`.
+pub struct MetricsEvents {
+    metrics: Arc<Metrics>,
+    inner: Arc<dyn HasherEvents>,
+}
+
+impl MetricsEvents {
+    pub fn new(metrics: Arc<Metrics>, inner: Arc<dyn HasherEvents>) -> MetricsEvents {
+        MetricsEvents { metrics, inner }
+    }
+}
+
+impl HasherEvents for MetricsEvents {
+    fn on_event(&self, event: &HasherEvent) {
+        self.metrics.on_event(event);
+        self.inner.on_event(event);
+    }
+}
+
+/// Serves `/metrics` on its own thread until it is dropped.
+pub struct MetricsServer {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Listens on `addr`, port 0 picks a free one, see [`MetricsServer::local_addr`].
+    pub fn start(addr: &str, metrics: Arc<Metrics>) -> Result<MetricsServer, HasherError> {
+        let server = Server::http(addr).map_err(|e| HasherError::MetricsServer {
+            addr: addr.to_string(),
+            reason: e.to_string(),
+        })?;
+        let server = Arc::new(server);
+        let incoming = server.clone();
+        let thread = thread::spawn(move || {
+            for request in incoming.incoming_requests() {
+                handle(request, &metrics);
+            }
+        });
+        Ok(MetricsServer { server, thread: Some(thread) })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn handle(request: Request, metrics: &Metrics) {
+    let path = request.url().split('?').next().unwrap_or_default();
+    let (code, body) = match (request.method(), path) {
+        (Method::Get, "/metrics") => (200, metrics.render()),
+        (_, "/metrics") => (405, "method not allowed\n".to_string()),
+        _ => (404, "not found\n".to_string()),
+    };
+    let header = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+    let _ = request.respond(Response::from_string(body).with_status_code(code).with_header(header));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        for event in [
+            HasherEvent::Phase(Phase::Hashing { nonces: 32, resumed_from: 8 }),
+            HasherEvent::DeviceHashed { device: 0, nonces: 8 },
+            HasherEvent::DeviceHashed { device: 2, nonces: 16 },
+            HasherEvent::DeviceHashSpeed { device: 2, speed: 1200.0 },
+            HasherEvent::Written(12),
+            HasherEvent::Written(10),
+            HasherEvent::Written(16),
+            HasherEvent::WriterStall(Duration::from_millis(1500)),
+            HasherEvent::Checkpoint { file: PathBuf::from("plot"), nonces: 16 },
+            HasherEvent::WriteError { file: PathBuf::from("plot"), error: String::new() },
+        ] {
+            metrics.on_event(&event);
+        }
+
+        let text = metrics.render();
+        for line in [
+            "anne_hasher_nonces_hashed_total 24",
+            "anne_hasher_device_nonces_hashed_total{device=\"cpu\"} 8",
+            "anne_hasher_device_nonces_hashed_total{device=\"gpu1\"} 16",
+            "anne_hasher_device_hash_rate{device=\"gpu1\"} 1200.0",
+            &format!("anne_hasher_bytes_written_total {}", 8 * NONCE_SIZE),
+            "anne_hasher_buffer_wait_seconds_total{stage=\"writing\"} 1.500",
+            "anne_hasher_resume_checkpoints_total 1",
+            "anne_hasher_write_errors_total 1",
+            "anne_hasher_running 1",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents};
+use crate::hasher::NONCE_SIZE;
+use crate::resume::{mark_complete, resume_file};
+use crate::utils::{free_disk_space, open_r};
+use crossbeam_channel::{bounded, Sender};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+// large sequential writes keep SMR drives out of their slow path
+const MOVE_CHUNK: usize = 64 * 1024 * 1024;
+
+/// Moves finished plot files out of a staging directory in the background,
+/// one at a time, while the next file is being hashed.
+pub struct Mover {
+    tx: Option<Sender<(PathBuf, PathBuf)>>,
+    thread: Option<JoinHandle<Result<u64, HasherError>>>,
+}
+
+impl Mover {
+    pub fn new(events: Arc<dyn HasherEvents>) -> Mover {
+        // one file waiting besides the one being moved, so a slow disk holds
+        // the hasher back before the staging directory runs full
+        let (tx, rx) = bounded::<(PathBuf, PathBuf)>(1);
+        let thread = thread::spawn(move || {
+            let mut moved = 0;
+            for (file, dest_dir) in rx {
+                move_plot_file(&file, &dest_dir, events.as_ref())?;
+                moved += 1;
+            }
+            Ok(moved)
+        });
+        Mover {
+            tx: Some(tx),
+            thread: Some(thread),
+        }
+    }
+
+    /// Queues `file` to be moved into `dest_dir`, blocks while another file is
+    /// already waiting. Fails with the mover's error if an earlier move failed.
+    pub fn queue(&mut self, file: PathBuf, dest_dir: PathBuf) -> Result<(), HasherError> {
+        let sent = self.tx.as_ref().is_some_and(|tx| tx.send((file, dest_dir)).is_ok());
+        if sent {
+            return Ok(());
+        }
+        // the thread only hangs up after a failed move
+        self.join().and(Err(HasherError::ThreadPanicked("mover")))
+    }
+
+    /// Waits until every queued file has been moved and returns how many were.
+    pub fn finish(mut self) -> Result<u64, HasherError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<u64, HasherError> {
+        self.tx = None;
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| HasherError::ThreadPanicked("mover"))?,
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for Mover {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+/// Checks up front that every directory in `dest_dirs` has room for the
+/// files that will be moved there, `nonces` for each time it's listed.
+pub fn check_dest_space(dest_dirs: &[String], nonces: u64) -> Result<(), HasherError> {
+    for dir in dest_dirs {
+        let required = dest_dirs.iter().filter(|other| *other == dir).count() as u64 * nonces * NONCE_SIZE;
+        let available = free_disk_space(dir)?;
+        if available < required {
+            return Err(HasherError::InsufficientDiskSpace { required, available });
+        }
+    }
+    Ok(())
+}
+
+/// Moves a completely written plot file into `dest_dir` and returns its new
+/// path.
+///
+/// Within a filesystem the file is put into place by [`place_file`], which
+/// never replaces a file that appeared at the destination in the meantime.
+/// Across filesystems the file is copied to `{name}.moving` next to its
+/// destination, synced, checked for size and put into place the same way, so
+/// the destination never holds a partial plot file. The destination is marked
+/// complete before the staged file and its resume info are removed.
+pub fn move_plot_file(file: &Path, dest_dir: &Path, events: &dyn HasherEvents) -> Result<PathBuf, HasherError> {
+    let name = file
+        .file_name()
+        .ok_or_else(|| HasherError::InvalidPlotFileName(file.to_path_buf()))?;
+    let dest = dest_dir.join(name);
+    let move_error = |source| HasherError::MovePlotFile {
+        file: file.to_path_buf(),
+        dest: dest.clone(),
+        source,
+    };
+
+    events.on_event(&HasherEvent::Log(format!("Moving {} to {}", file.display(), dest.display())));
+    let start = Instant::now();
+
+    let size = file.metadata().map_err(move_error)?.len();
+    let copied = match place_file(file, &dest) {
+        Ok(()) => false,
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => true,
+        Err(e) => return Err(move_error(e)),
+    };
+    if copied {
+        // spares copying a file that can't be linked into place anyway
+        if dest.exists() {
+            return Err(move_error(Error::new(ErrorKind::AlreadyExists, "destination already exists")));
+        }
+        let available = free_disk_space(&dest_dir.to_string_lossy())?;
+        if available < size {
+            return Err(HasherError::InsufficientDiskSpace { required: size, available });
+        }
+
+        let mut tmp_name = dest.as_os_str().to_owned();
+        tmp_name.push(".moving");
+        let tmp = PathBuf::from(tmp_name);
+        copy_synced(file, &tmp).map_err(move_error)?;
+
+        let copied_size = tmp.metadata().map_err(move_error)?.len();
+        if copied_size != size {
+            let _ = fs::remove_file(&tmp);
+            return Err(move_error(Error::other(format!(
+                "copied {} of {} bytes",
+                copied_size, size
+            ))));
+        }
+        let placed = place_file(&tmp, &dest);
+        let _ = fs::remove_file(&tmp);
+        placed.map_err(move_error)?;
+    }
+
+    mark_complete(&dest).map_err(|source| HasherError::WriteResumeInfo { file: dest.clone(), source })?;
+    // a rename has taken the staged file along already
+    match fs::remove_file(file) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(move_error(e)),
+        _ => {}
+    }
+    let _ = fs::remove_file(resume_file(file));
+
+    if copied {
+        let elapsed = start.elapsed().as_secs_f64();
+        events.on_event(&HasherEvent::Log(format!(
+            "Moved {} in {:.0}s, {:.2} MiB/s",
+            dest.display(),
+            elapsed,
+            size as f64 / 1024.0 / 1024.0 / elapsed.max(0.001)
+        )));
+    } else {
+        events.on_event(&HasherEvent::Log(format!("Moved {}", dest.display())));
+    }
+    events.on_event(&HasherEvent::Moved(dest.clone()));
+    Ok(dest)
+}
+
+/// Puts `from` at `to` without ever replacing a file there, `from` is left
+/// behind if it could be linked. A hard link does that atomically. FAT and
+/// exFAT have no hard links, there `to` is claimed by an exclusive create
+/// and `from` is renamed over that empty file.
+fn place_file(from: &Path, to: &Path) -> Result<(), Error> {
+    match fs::hard_link(from, to) {
+        Err(e) if matches!(e.kind(), ErrorKind::Unsupported | ErrorKind::PermissionDenied) => {
+            OpenOptions::new().write(true).create_new(true).open(to)?;
+            let renamed = fs::rename(from, to);
+            if renamed.is_err() {
+                let _ = fs::remove_file(to);
+            }
+            renamed
+        }
+        result => result,
+    }
+}
+
+fn copy_synced(from: &Path, to: &Path) -> Result<(), Error> {
+    let mut source = open_r(from)?;
+    let mut target = File::create(to)?;
+    let mut buffer = vec![0u8; MOVE_CHUNK];
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        target.write_all(&buffer[..read])?;
+    }
+    target.sync_all()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::NoEvents;
+    use crate::resume::{write_resume_info, ResumeInfo};
+
+    #[test]
+    fn test_move_plot_file() {
+        let dir = std::env::temp_dir().join(format!("anne-hasher-mover-{}", std::process::id()));
+        let (staging, dest_dir) = (dir.join("staging"), dir.join("dest"));
+        fs::create_dir_all(&staging).unwrap();
+        fs::create_dir_all(&dest_dir).unwrap();
+
+        let file = staging.join("42_0_2");
+        fs::write(&file, [7u8; 1024]).unwrap();
+        write_resume_info(&file, 2).unwrap();
+
+        let mut mover = Mover::new(Arc::new(NoEvents));
+        mover.queue(file.clone(), dest_dir.clone()).unwrap();
+        assert_eq!(mover.finish().unwrap(), 1);
+
+        let dest = dest_dir.join("42_0_2");
+        assert_eq!(fs::read(&dest).unwrap(), vec![7u8; 1024]);
+        assert!(ResumeInfo::read(&dest).unwrap().complete);
+        assert!(!file.exists() && !resume_file(&file).exists());
+
+        // an existing file at the destination is never replaced
+        fs::write(&file, [0u8; 1024]).unwrap();
+        assert!(move_plot_file(&file, &dest_dir, &NoEvents).is_err());
+        assert_eq!(fs::read(&dest).unwrap(), vec![7u8; 1024]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,382 @@
+//! Shabal-256 on several messages at once, one per SIMD lane, and the nonce
+//! generation built on it. The backends only provide the lane-wise integer
+//! operations in [`ShabalVector`], the hashing itself is shared and mirrors
+//! [`shabal256_fast`](crate::shabal256::shabal256_fast) and
+//! [`noncegen_rust`](crate::poc_hashing::noncegen_rust).
+//!
+//! Everything here is `#[inline(always)]`, so it is compiled with the target
+//! features of the backend entry point it ends up in. [`Lanes`] is the
+//! backend for targets without one of their own.
+
+use crate::poc_hashing::noncegen_rust;
+use crate::shabal256::{A_INIT, B_INIT, C_INIT};
+
+const HASH_SIZE: usize = 32;
+const HASH_CAP: usize = 4096;
+const NUM_SCOOPS: usize = 4096;
+const SCOOP_SIZE: usize = 64;
+const NONCE_SIZE: usize = NUM_SCOOPS * SCOOP_SIZE;
+
+// sizes in 32 bit words, the unit of a lane
+const HASH_WORDS: usize = HASH_SIZE / 4;
+const BLOCK_WORDS: usize = 16;
+const NONCE_WORDS: usize = NONCE_SIZE / 4;
+
+/// `LANES` 32 bit integers that are operated on lane by lane.
+///
+/// # Safety
+///
+/// The methods may only be called where the instructions of the backend are
+/// available.
+pub(crate) unsafe trait ShabalVector: Copy {
+    const LANES: usize;
+
+    unsafe fn splat(x: u32) -> Self;
+    /// Loads the first `LANES` words of `words`.
+    unsafe fn load(words: &[u32]) -> Self;
+    /// Stores into the first `LANES` words of `words`.
+    unsafe fn store(self, words: &mut [u32]);
+    unsafe fn add(self, other: Self) -> Self;
+    unsafe fn sub(self, other: Self) -> Self;
+    unsafe fn xor(self, other: Self) -> Self;
+    /// `self & !other`
+    unsafe fn and_not(self, other: Self) -> Self;
+    unsafe fn not(self) -> Self;
+    /// Rotates left by `L` bits, `R` is `32 - L`.
+    unsafe fn rotl<const L: i32, const R: i32>(self) -> Self;
+
+    /// One step of the permutation on the words it reads and updates.
+    #[inline(always)]
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn perm_step(a0: &mut Self, a1: Self, b0: &mut Self, b1: Self, b2: Self, b3: Self, c: Self, m: Self) {
+        unsafe {
+            let t = a1.rotl::<15, 17>();
+            // t * 5 and the result * 3
+            let t = t.add(t).add(t.add(t)).add(t);
+            let u = a0.xor(t).xor(c);
+            let u = u.add(u).add(u);
+            *a0 = u.xor(b1).xor(b2.and_not(b3)).xor(m);
+            *b0 = b0.rotl::<1, 31>().xor(*a0).not();
+        }
+    }
+}
+
+/// Plain arrays for targets without a SIMD backend, the lane-wise loops are
+/// simple enough for LLVM to vectorize with whatever the target has.
+#[derive(Clone, Copy)]
+pub(crate) struct Lanes<const N: usize>([u32; N]);
+
+impl<const N: usize> Lanes<N> {
+    #[inline(always)]
+    fn zip(self, other: Self, f: impl Fn(u32, u32) -> u32) -> Self {
+        Lanes(std::array::from_fn(|i| f(self.0[i], other.0[i])))
+    }
+}
+
+unsafe impl<const N: usize> ShabalVector for Lanes<N> {
+    const LANES: usize = N;
+
+    #[inline(always)]
+    unsafe fn splat(x: u32) -> Self {
+        Lanes([x; N])
+    }
+    #[inline(always)]
+    unsafe fn load(words: &[u32]) -> Self {
+        Lanes(words[..N].try_into().unwrap())
+    }
+    #[inline(always)]
+    unsafe fn store(self, words: &mut [u32]) {
+        words[..N].copy_from_slice(&self.0);
+    }
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        self.zip(other, u32::wrapping_add)
+    }
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        self.zip(other, u32::wrapping_sub)
+    }
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        self.zip(other, |a, b| a ^ b)
+    }
+    #[inline(always)]
+    unsafe fn and_not(self, other: Self) -> Self {
+        self.zip(other, |a, b| a & !b)
+    }
+    #[inline(always)]
+    unsafe fn not(self) -> Self {
+        Lanes(self.0.map(|x| !x))
+    }
+    #[inline(always)]
+    unsafe fn rotl<const L: i32, const R: i32>(self) -> Self {
+        Lanes(self.0.map(|x| x.rotate_left(L as u32)))
+    }
+
+    // a single loop over the lanes vectorizes far better than one per operation
+    #[inline(always)]
+    unsafe fn perm_step(a0: &mut Self, a1: Self, b0: &mut Self, b1: Self, b2: Self, b3: Self, c: Self, m: Self) {
+        for i in 0..N {
+            let t = a1.0[i].rotate_left(15).wrapping_mul(5);
+            let u = (a0.0[i] ^ t ^ c.0[i]).wrapping_mul(3);
+            a0.0[i] = u ^ b1.0[i] ^ (b2.0[i] & !b3.0[i]) ^ m.0[i];
+            b0.0[i] = !(b0.0[i].rotate_left(1) ^ a0.0[i]);
+        }
+    }
+}
+
+/// [`noncegen_lanes`] on plain arrays of `N` lanes, for every target.
+pub(crate) fn noncegen_portable<const N: usize>(
+    cache: &mut [u8],
+    cache_offset: usize,
+    numeric_id: u64,
+    local_startnonce: u64,
+    local_nonces: u64,
+) {
+    // no instructions beyond the target's baseline are involved
+    unsafe { noncegen_lanes::<Lanes<N>>(cache, cache_offset, numeric_id, local_startnonce, local_nonces) }
+}
+
+struct State<V> {
+    a: [V; 12],
+    b: [V; 16],
+    c: [V; 16],
+    w_low: u32,
+    w_high: u32,
+}
+
+impl<V: ShabalVector> State<V> {
+    #[inline(always)]
+    unsafe fn new() -> State<V> {
+        unsafe {
+            State {
+                a: A_INIT.map(|x| V::splat(x)),
+                b: B_INIT.map(|x| V::splat(x)),
+                c: C_INIT.map(|x| V::splat(x)),
+                w_low: 1,
+                w_high: 0,
+            }
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn xor_w(&mut self) {
+        unsafe {
+            self.a[0] = self.a[0].xor(V::splat(self.w_low));
+            self.a[1] = self.a[1].xor(V::splat(self.w_high));
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn apply_p(&mut self, m: &[V]) {
+        unsafe {
+            for b in self.b.iter_mut() {
+                *b = b.rotl::<17, 15>();
+            }
+            self.perm(m);
+            let (a, c) = (&mut self.a, &self.c);
+            for (i, a) in a.iter_mut().enumerate() {
+                *a = a.add(c[(i + 11) % 16]).add(c[(i + 15) % 16]).add(c[(i + 3) % 16]);
+            }
+        }
+    }
+
+    #[inline(always)]
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn perm_elt(&mut self, xa0: usize, xa1: usize, xb0: usize, xb1: usize, xb2: usize, xb3: usize, xc: usize, xm: V) {
+        let (a, b) = (&mut self.a, &mut self.b);
+        let (a1, b1, b2, b3) = (a[xa1], b[xb1], b[xb2], b[xb3]);
+        unsafe { V::perm_step(&mut a[xa0], a1, &mut b[xb0], b1, b2, b3, self.c[xc], xm) }
+    }
+
+    #[inline(always)]
+    unsafe fn perm(&mut self, m: &[V]) {
+        unsafe {
+            self.perm_elt(0, 11, 0, 13, 9, 6, 8, m[0]);
+            self.perm_elt(1, 0, 1, 14, 10, 7, 7, m[1]);
+            self.perm_elt(2, 1, 2, 15, 11, 8, 6, m[2]);
+            self.perm_elt(3, 2, 3, 0, 12, 9, 5, m[3]);
+            self.perm_elt(4, 3, 4, 1, 13, 10, 4, m[4]);
+            self.perm_elt(5, 4, 5, 2, 14, 11, 3, m[5]);
+            self.perm_elt(6, 5, 6, 3, 15, 12, 2, m[6]);
+            self.perm_elt(7, 6, 7, 4, 0, 13, 1, m[7]);
+            self.perm_elt(8, 7, 8, 5, 1, 14, 0, m[8]);
+            self.perm_elt(9, 8, 9, 6, 2, 15, 15, m[9]);
+            self.perm_elt(10, 9, 10, 7, 3, 0, 14, m[10]);
+            self.perm_elt(11, 10, 11, 8, 4, 1, 13, m[11]);
+            self.perm_elt(0, 11, 12, 9, 5, 2, 12, m[12]);
+            self.perm_elt(1, 0, 13, 10, 6, 3, 11, m[13]);
+            self.perm_elt(2, 1, 14, 11, 7, 4, 10, m[14]);
+            self.perm_elt(3, 2, 15, 12, 8, 5, 9, m[15]);
+            self.perm_elt(4, 3, 0, 13, 9, 6, 8, m[0]);
+            self.perm_elt(5, 4, 1, 14, 10, 7, 7, m[1]);
+            self.perm_elt(6, 5, 2, 15, 11, 8, 6, m[2]);
+            self.perm_elt(7, 6, 3, 0, 12, 9, 5, m[3]);
+            self.perm_elt(8, 7, 4, 1, 13, 10, 4, m[4]);
+            self.perm_elt(9, 8, 5, 2, 14, 11, 3, m[5]);
+            self.perm_elt(10, 9, 6, 3, 15, 12, 2, m[6]);
+            self.perm_elt(11, 10, 7, 4, 0, 13, 1, m[7]);
+            self.perm_elt(0, 11, 8, 5, 1, 14, 0, m[8]);
+            self.perm_elt(1, 0, 9, 6, 2, 15, 15, m[9]);
+            self.perm_elt(2, 1, 10, 7, 3, 0, 14, m[10]);
+            self.perm_elt(3, 2, 11, 8, 4, 1, 13, m[11]);
+            self.perm_elt(4, 3, 12, 9, 5, 2, 12, m[12]);
+            self.perm_elt(5, 4, 13, 10, 6, 3, 11, m[13]);
+            self.perm_elt(6, 5, 14, 11, 7, 4, 10, m[14]);
+            self.perm_elt(7, 6, 15, 12, 8, 5, 9, m[15]);
+            self.perm_elt(8, 7, 0, 13, 9, 6, 8, m[0]);
+            self.perm_elt(9, 8, 1, 14, 10, 7, 7, m[1]);
+            self.perm_elt(10, 9, 2, 15, 11, 8, 6, m[2]);
+            self.perm_elt(11, 10, 3, 0, 12, 9, 5, m[3]);
+            self.perm_elt(0, 11, 4, 1, 13, 10, 4, m[4]);
+            self.perm_elt(1, 0, 5, 2, 14, 11, 3, m[5]);
+            self.perm_elt(2, 1, 6, 3, 15, 12, 2, m[6]);
+            self.perm_elt(3, 2, 7, 4, 0, 13, 1, m[7]);
+            self.perm_elt(4, 3, 8, 5, 1, 14, 0, m[8]);
+            self.perm_elt(5, 4, 9, 6, 2, 15, 15, m[9]);
+            self.perm_elt(6, 5, 10, 7, 3, 0, 14, m[10]);
+            self.perm_elt(7, 6, 11, 8, 4, 1, 13, m[11]);
+            self.perm_elt(8, 7, 12, 9, 5, 2, 12, m[12]);
+            self.perm_elt(9, 8, 13, 10, 6, 3, 11, m[13]);
+            self.perm_elt(10, 9, 14, 11, 7, 4, 10, m[14]);
+            self.perm_elt(11, 10, 15, 12, 8, 5, 9, m[15]);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn block(&mut self, m: &[V]) {
+        unsafe {
+            for (b, m) in self.b.iter_mut().zip(m) {
+                *b = b.add(*m);
+            }
+            self.xor_w();
+            self.apply_p(m);
+            for (c, m) in self.c.iter_mut().zip(m) {
+                *c = c.sub(*m);
+            }
+            std::mem::swap(&mut self.b, &mut self.c);
+            self.w_low = self.w_low.wrapping_add(1);
+            if self.w_low == 0 {
+                self.w_high = self.w_high.wrapping_add(1);
+            }
+        }
+    }
+}
+
+/// Hashes the whole 64 byte blocks of `data` and then `term`, which holds the
+/// rest of each message and its padding.
+#[inline(always)]
+unsafe fn shabal256_lanes<V: ShabalVector>(data: &[V], term: &[V; BLOCK_WORDS]) -> [V; HASH_WORDS] {
+    unsafe {
+        let mut state = State::<V>::new();
+        for block in data.chunks_exact(BLOCK_WORDS) {
+            state.block(block);
+        }
+        for (b, m) in state.b.iter_mut().zip(term) {
+            *b = b.add(*m);
+        }
+        state.xor_w();
+        state.apply_p(term);
+        for _ in 0..3 {
+            std::mem::swap(&mut state.b, &mut state.c);
+            state.xor_w();
+            state.apply_p(term);
+        }
+        state.b[8..16].try_into().unwrap()
+    }
+}
+
+/// Generates `LANES` nonces at a time like [`noncegen_rust`], which handles
+/// the nonces that are left over.
+#[inline(always)]
+pub(crate) unsafe fn noncegen_lanes<V: ShabalVector>(
+    cache: &mut [u8],
+    cache_offset: usize,
+    numeric_id: u64,
+    local_startnonce: u64,
+    local_nonces: u64,
+) {
+    unsafe {
+        let lanes = V::LANES;
+        let be_words = |x: u64| {
+            let bytes = x.to_be_bytes();
+            [
+                u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                u32::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            ]
+        };
+        let [id_high, id_low] = be_words(numeric_id).map(|x| V::splat(x));
+        let zero = V::splat(0);
+        let padding = V::splat(0x80);
+
+        // seed and padding, after the first hash or on its own
+        let mut t1 = [zero; BLOCK_WORDS];
+        t1[0] = id_high;
+        t1[1] = id_low;
+        t1[4] = padding;
+        let mut t2 = [zero; BLOCK_WORDS];
+        t2[8] = id_high;
+        t2[9] = id_low;
+        t2[12] = padding;
+        let mut t3 = [zero; BLOCK_WORDS];
+        t3[0] = padding;
+
+        let cache_size = cache.len() / NONCE_SIZE;
+        // up to 4 MiB, too much for the stack
+        #[allow(clippy::useless_vec)]
+        let mut buffer = vec![zero; NONCE_WORDS];
+        let mut words = [0u32; 64];
+        let mut n = 0;
+        while n + lanes as u64 <= local_nonces {
+            let (mut high, mut low) = ([0u32; 64], [0u32; 64]);
+            for lane in 0..lanes {
+                [high[lane], low[lane]] = be_words(local_startnonce + n + lane as u64);
+            }
+            t1[2] = V::load(&high);
+            t1[3] = V::load(&low);
+            t2[10] = t1[2];
+            t2[11] = t1[3];
+
+            // hash backwards from the end of the nonce, the first hash of nothing
+            // but the seed, then of up to HASH_CAP bytes behind it, last of it all
+            let mut i = NONCE_SIZE;
+            let final_hash = loop {
+                let (data, term) = if i == 0 {
+                    (&buffer[..], &t1)
+                } else if i > NONCE_SIZE - HASH_CAP {
+                    // the half block left over is the first hash, which t2 starts with
+                    (&buffer[i / 4..], if i.is_multiple_of(64) { &t1 } else { &t2 })
+                } else {
+                    (&buffer[i / 4..(i + HASH_CAP) / 4], &t3)
+                };
+                let hash = shabal256_lanes(data, term);
+                if i == 0 {
+                    break hash;
+                }
+                buffer[i / 4 - HASH_WORDS..i / 4].copy_from_slice(&hash);
+                if i == NONCE_SIZE {
+                    t2[..HASH_WORDS].copy_from_slice(&hash);
+                }
+                i -= HASH_SIZE;
+            };
+
+            // first half of scoop i goes to scoop i, the second one to scoop 4095 - i
+            for (w, word) in buffer.iter().enumerate() {
+                word.xor(final_hash[w % HASH_WORDS]).store(&mut words);
+                let byte = w * 4;
+                let (scoop, half) = (byte / SCOOP_SIZE, byte % SCOOP_SIZE / HASH_SIZE);
+                let scoop = if half == 0 { scoop } else { NUM_SCOOPS - 1 - scoop };
+                let row = scoop * cache_size * SCOOP_SIZE + byte % SCOOP_SIZE;
+                for (lane, word) in words[..lanes].iter().enumerate() {
+                    let offset = row + (n as usize + lane + cache_offset) * SCOOP_SIZE;
+                    cache[offset..offset + 4].copy_from_slice(&word.to_ne_bytes());
+                }
+            }
+            n += lanes as u64;
+        }
+
+        if n < local_nonces {
+            noncegen_rust(cache, cache_offset + n as usize, numeric_id, local_startnonce + n, local_nonces - n);
+        }
+    }
+}
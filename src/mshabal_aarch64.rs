@@ -0,0 +1,57 @@
+//! NEON backend of [`mshabal`](crate::mshabal).
+
+use crate::mshabal::{noncegen_lanes, ShabalVector};
+use std::arch::aarch64::*;
+
+#[derive(Clone, Copy)]
+struct Neon(uint32x4_t);
+
+unsafe impl ShabalVector for Neon {
+    const LANES: usize = 4;
+
+    #[inline(always)]
+    unsafe fn splat(x: u32) -> Self {
+        unsafe { Neon(vdupq_n_u32(x)) }
+    }
+    #[inline(always)]
+    unsafe fn load(words: &[u32]) -> Self {
+        assert!(words.len() >= Self::LANES);
+        unsafe { Neon(vld1q_u32(words.as_ptr())) }
+    }
+    #[inline(always)]
+    unsafe fn store(self, words: &mut [u32]) {
+        assert!(words.len() >= Self::LANES);
+        unsafe { vst1q_u32(words.as_mut_ptr(), self.0) }
+    }
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        unsafe { Neon(vaddq_u32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        unsafe { Neon(vsubq_u32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        unsafe { Neon(veorq_u32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn and_not(self, other: Self) -> Self {
+        unsafe { Neon(vbicq_u32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn not(self) -> Self {
+        unsafe { Neon(vmvnq_u32(self.0)) }
+    }
+    #[inline(always)]
+    unsafe fn rotl<const L: i32, const R: i32>(self) -> Self {
+        // shift right and insert below the bits shifted left
+        unsafe { Neon(vsriq_n_u32::<R>(vshlq_n_u32::<L>(self.0), self.0)) }
+    }
+}
+
+/// Same arguments as [`noncegen_rust`](crate::poc_hashing::noncegen_rust).
+#[target_feature(enable = "neon")]
+pub(crate) unsafe fn noncegen_neon(cache: &mut [u8], offset: usize, numeric_id: u64, start_nonce: u64, nonces: u64) {
+    unsafe { noncegen_lanes::<Neon>(cache, offset, numeric_id, start_nonce, nonces) }
+}
@@ -0,0 +1,165 @@
+//! SSE2, AVX, AVX2 and AVX512F backends of [`mshabal`](crate::mshabal).
+
+use crate::mshabal::{noncegen_lanes, ShabalVector};
+use std::arch::x86_64::*;
+
+#[derive(Clone, Copy)]
+struct Sse2(__m128i);
+
+unsafe impl ShabalVector for Sse2 {
+    const LANES: usize = 4;
+
+    #[inline(always)]
+    unsafe fn splat(x: u32) -> Self {
+        unsafe { Sse2(_mm_set1_epi32(x as i32)) }
+    }
+    #[inline(always)]
+    unsafe fn load(words: &[u32]) -> Self {
+        assert!(words.len() >= Self::LANES);
+        unsafe { Sse2(_mm_loadu_si128(words.as_ptr().cast())) }
+    }
+    #[inline(always)]
+    unsafe fn store(self, words: &mut [u32]) {
+        assert!(words.len() >= Self::LANES);
+        unsafe { _mm_storeu_si128(words.as_mut_ptr().cast(), self.0) }
+    }
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        unsafe { Sse2(_mm_add_epi32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        unsafe { Sse2(_mm_sub_epi32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        unsafe { Sse2(_mm_xor_si128(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn and_not(self, other: Self) -> Self {
+        unsafe { Sse2(_mm_andnot_si128(other.0, self.0)) }
+    }
+    #[inline(always)]
+    unsafe fn not(self) -> Self {
+        unsafe { Sse2(_mm_xor_si128(self.0, _mm_set1_epi32(-1))) }
+    }
+    #[inline(always)]
+    unsafe fn rotl<const L: i32, const R: i32>(self) -> Self {
+        unsafe { Sse2(_mm_or_si128(_mm_slli_epi32::<L>(self.0), _mm_srli_epi32::<R>(self.0))) }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Avx2(__m256i);
+
+unsafe impl ShabalVector for Avx2 {
+    const LANES: usize = 8;
+
+    #[inline(always)]
+    unsafe fn splat(x: u32) -> Self {
+        unsafe { Avx2(_mm256_set1_epi32(x as i32)) }
+    }
+    #[inline(always)]
+    unsafe fn load(words: &[u32]) -> Self {
+        assert!(words.len() >= Self::LANES);
+        unsafe { Avx2(_mm256_loadu_si256(words.as_ptr().cast())) }
+    }
+    #[inline(always)]
+    unsafe fn store(self, words: &mut [u32]) {
+        assert!(words.len() >= Self::LANES);
+        unsafe { _mm256_storeu_si256(words.as_mut_ptr().cast(), self.0) }
+    }
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        unsafe { Avx2(_mm256_add_epi32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        unsafe { Avx2(_mm256_sub_epi32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        unsafe { Avx2(_mm256_xor_si256(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn and_not(self, other: Self) -> Self {
+        unsafe { Avx2(_mm256_andnot_si256(other.0, self.0)) }
+    }
+    #[inline(always)]
+    unsafe fn not(self) -> Self {
+        unsafe { Avx2(_mm256_xor_si256(self.0, _mm256_set1_epi32(-1))) }
+    }
+    #[inline(always)]
+    unsafe fn rotl<const L: i32, const R: i32>(self) -> Self {
+        unsafe { Avx2(_mm256_or_si256(_mm256_slli_epi32::<L>(self.0), _mm256_srli_epi32::<R>(self.0))) }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Avx512f(__m512i);
+
+unsafe impl ShabalVector for Avx512f {
+    const LANES: usize = 16;
+
+    #[inline(always)]
+    unsafe fn splat(x: u32) -> Self {
+        unsafe { Avx512f(_mm512_set1_epi32(x as i32)) }
+    }
+    #[inline(always)]
+    unsafe fn load(words: &[u32]) -> Self {
+        assert!(words.len() >= Self::LANES);
+        unsafe { Avx512f(_mm512_loadu_si512(words.as_ptr().cast())) }
+    }
+    #[inline(always)]
+    unsafe fn store(self, words: &mut [u32]) {
+        assert!(words.len() >= Self::LANES);
+        unsafe { _mm512_storeu_si512(words.as_mut_ptr().cast(), self.0) }
+    }
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        unsafe { Avx512f(_mm512_add_epi32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn sub(self, other: Self) -> Self {
+        unsafe { Avx512f(_mm512_sub_epi32(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        unsafe { Avx512f(_mm512_xor_si512(self.0, other.0)) }
+    }
+    #[inline(always)]
+    unsafe fn and_not(self, other: Self) -> Self {
+        unsafe { Avx512f(_mm512_andnot_si512(other.0, self.0)) }
+    }
+    #[inline(always)]
+    unsafe fn not(self) -> Self {
+        unsafe { Avx512f(_mm512_xor_si512(self.0, _mm512_set1_epi32(-1))) }
+    }
+    #[inline(always)]
+    unsafe fn rotl<const L: i32, const R: i32>(self) -> Self {
+        // a native rotate
+        unsafe { Avx512f(_mm512_rol_epi32::<L>(self.0)) }
+    }
+}
+
+/// Same arguments as [`noncegen_rust`](crate::poc_hashing::noncegen_rust).
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe fn noncegen_sse2(cache: &mut [u8], offset: usize, numeric_id: u64, start_nonce: u64, nonces: u64) {
+    unsafe { noncegen_lanes::<Sse2>(cache, offset, numeric_id, start_nonce, nonces) }
+}
+
+/// The SSE2 backend with VEX encoded instructions.
+#[target_feature(enable = "avx")]
+pub(crate) unsafe fn noncegen_avx(cache: &mut [u8], offset: usize, numeric_id: u64, start_nonce: u64, nonces: u64) {
+    unsafe { noncegen_lanes::<Sse2>(cache, offset, numeric_id, start_nonce, nonces) }
+}
+
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe fn noncegen_avx2(cache: &mut [u8], offset: usize, numeric_id: u64, start_nonce: u64, nonces: u64) {
+    unsafe { noncegen_lanes::<Avx2>(cache, offset, numeric_id, start_nonce, nonces) }
+}
+
+#[target_feature(enable = "avx512f")]
+pub(crate) unsafe fn noncegen_avx512f(cache: &mut [u8], offset: usize, numeric_id: u64, start_nonce: u64, nonces: u64) {
+    unsafe { noncegen_lanes::<Avx512f>(cache, offset, numeric_id, start_nonce, nonces) }
+}
@@ -7,6 +7,43 @@ const SCOOP_SIZE: usize = 64;
 const NONCE_SIZE: usize = NUM_SCOOPS * SCOOP_SIZE;
 const MESSAGE_SIZE: usize = 16;
 
+/// Scoop order of a nonce. PoC1 is the order the nonce is generated in, PoC2
+/// swaps the second half of scoop `i` with the one of scoop `4095 - i`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PocVersion {
+    Poc1,
+    Poc2,
+}
+
+/// Generates a single nonce, scoop `i` at `i * SCOOP_SIZE` in PoC2 order as
+/// plot files store it.
+pub fn generate_nonce(numeric_id: u64, nonce: u64) -> Box<[u8; NONCE_SIZE]> {
+    let mut buffer: Box<[u8; NONCE_SIZE]> = vec![0u8; NONCE_SIZE].into_boxed_slice().try_into().unwrap();
+    hash_nonce(numeric_id, nonce, &mut buffer);
+    for i in 0..NUM_SCOOPS / 2 {
+        let (low, high) = buffer.split_at_mut((NUM_SCOOPS - 1 - i) * SCOOP_SIZE);
+        low[i * SCOOP_SIZE + HASH_SIZE..(i + 1) * SCOOP_SIZE].swap_with_slice(&mut high[HASH_SIZE..SCOOP_SIZE]);
+    }
+    buffer
+}
+
+/// Generates scoop `scoop` of a single nonce. The whole nonce has to be
+/// hashed for it, which is as costly as [`generate_nonce`].
+pub fn generate_scoop(numeric_id: u64, nonce: u64, scoop: u64, poc_version: PocVersion) -> [u8; SCOOP_SIZE] {
+    let scoop = scoop as usize;
+    assert!(scoop < NUM_SCOOPS, "scoop {} out of range", scoop);
+    let mut buffer: Box<[u8; NONCE_SIZE]> = vec![0u8; NONCE_SIZE].into_boxed_slice().try_into().unwrap();
+    hash_nonce(numeric_id, nonce, &mut buffer);
+
+    let mut data = [0u8; SCOOP_SIZE];
+    data.copy_from_slice(&buffer[scoop * SCOOP_SIZE..(scoop + 1) * SCOOP_SIZE]);
+    if poc_version == PocVersion::Poc2 {
+        let mirror = (NUM_SCOOPS - 1 - scoop) * SCOOP_SIZE;
+        data[HASH_SIZE..].copy_from_slice(&buffer[mirror + HASH_SIZE..mirror + SCOOP_SIZE]);
+    }
+    data
+}
+
 pub fn noncegen_rust(
     cache: &mut [u8],
     cache_offset: usize,
@@ -14,74 +51,132 @@ pub fn noncegen_rust(
     local_startnonce: u64,
     local_nonces: u64,
 ) {
-    let numeric_id: [u32; 2] = unsafe { std::mem::transmute(numeric_id.to_be()) };
-
     let mut buffer = [0u8; NONCE_SIZE];
-    let mut final_buffer = [0u8; HASH_SIZE];
+
+    for n in 0..local_nonces {
+        hash_nonce(numeric_id, local_startnonce + n, &mut buffer);
+
+        let cache_size = cache.len() / NONCE_SIZE;
+        for i in 0..NUM_SCOOPS {
+            let offset = i * cache_size * SCOOP_SIZE + (n as usize + cache_offset) * SCOOP_SIZE;
+            cache[offset..offset + HASH_SIZE]
+                .clone_from_slice(&buffer[i * SCOOP_SIZE..i * SCOOP_SIZE + HASH_SIZE]);
+            let mirror_offset = (4095 - i) * cache_size * SCOOP_SIZE
+                + (n as usize + cache_offset) * SCOOP_SIZE
+                + HASH_SIZE;
+            cache[mirror_offset..mirror_offset + HASH_SIZE].clone_from_slice(
+                &buffer[i * SCOOP_SIZE + HASH_SIZE..i * SCOOP_SIZE + 2 * HASH_SIZE],
+            );
+        }
+    }
+}
+
+/// Hashes one nonce into `buffer` in PoC1 order.
+fn hash_nonce(numeric_id: u64, nonce: u64, buffer: &mut [u8; NONCE_SIZE]) {
+    let numeric_id: [u32; 2] = unsafe { std::mem::transmute(numeric_id.to_be()) };
+    let nonce: [u32; 2] = unsafe { std::mem::transmute(nonce.to_be()) };
 
     let mut t1 = [0u32; MESSAGE_SIZE];
     t1[0..2].clone_from_slice(&numeric_id);
+    t1[2..4].clone_from_slice(&nonce);
     t1[4] = 0x80;
 
     let mut t2 = [0u32; MESSAGE_SIZE];
     t2[8..10].clone_from_slice(&numeric_id);
+    t2[10..12].clone_from_slice(&nonce);
     t2[12] = 0x80;
 
     let mut t3 = [0u32; MESSAGE_SIZE];
     t3[0] = 0x80;
 
-    for n in 0..local_nonces {
+    let hash = shabal256_fast(&[], &t1);
 
-        let nonce: [u32; 2] = unsafe { std::mem::transmute((local_startnonce + n).to_be()) };
+    buffer[NONCE_SIZE - HASH_SIZE..NONCE_SIZE].clone_from_slice(&hash);
+    let hash = unsafe { std::mem::transmute::<[u8; 32], [u32; 8]>(hash) };
 
-        t1[2..4].clone_from_slice(&nonce);
-        t2[10..12].clone_from_slice(&nonce);
+    t2[0..8].clone_from_slice(&hash);
 
-        let hash = shabal256_fast(&[], &t1);
+    for i in (NONCE_SIZE - HASH_CAP + HASH_SIZE..=NONCE_SIZE - HASH_SIZE)
+        .rev()
+        .step_by(HASH_SIZE)
+    {
 
-        buffer[NONCE_SIZE - HASH_SIZE..NONCE_SIZE].clone_from_slice(&hash);
-        let hash = unsafe { std::mem::transmute::<[u8; 32], [u32; 8]>(hash) };
+        if i % 64 == 0 {
+
+            let hash = &shabal256_fast(&buffer[i..NONCE_SIZE], &t1);
+            buffer[i - HASH_SIZE..i].clone_from_slice(hash);
+        } else {
 
-        t2[0..8].clone_from_slice(&hash);
+            let hash = &shabal256_fast(&buffer[i..NONCE_SIZE], &t2);
+            buffer[i - HASH_SIZE..i].clone_from_slice(hash);
+        }
+    }
 
-        for i in (NONCE_SIZE - HASH_CAP + HASH_SIZE..=NONCE_SIZE - HASH_SIZE)
-            .rev()
-            .step_by(HASH_SIZE)
-        {
+    for i in (HASH_SIZE..=NONCE_SIZE - HASH_CAP).rev().step_by(HASH_SIZE) {
+        let hash = &shabal256_fast(&buffer[i..i + HASH_CAP], &t3);
+        buffer[i - HASH_SIZE..i].clone_from_slice(hash);
+    }
 
-            if i % 64 == 0 {
+    let final_buffer = shabal256_fast(&buffer[0..NONCE_SIZE], &t1);
 
-                let hash = &shabal256_fast(&buffer[i..NONCE_SIZE], &t1);
-                buffer[i - HASH_SIZE..i].clone_from_slice(hash);
-            } else {
+    for i in 0..NONCE_SIZE {
+        buffer[i] ^= final_buffer[i % HASH_SIZE];
+    }
+}
 
-                let hash = &shabal256_fast(&buffer[i..NONCE_SIZE], &t2);
-                buffer[i - HASH_SIZE..i].clone_from_slice(hash);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::NoEvents;
+    use crate::hasher::{Hasher, HasherTask};
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_generate_scoop() {
+        let numeric_id = 7900104405094198526;
+        let (start_nonce, nonces) = (1337, 16);
+        let dir = std::env::temp_dir().join(format!("anne-hasher-generate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let task = HasherTask {
+            numeric_id,
+            start_nonce,
+            nonces,
+            output_paths: vec![dir.to_str().unwrap().to_string()],
+            known_paths: Vec::new(),
+            mem: "8MiB".to_string(),
+            buffers: 0,
+            cpu_threads: 1,
+            gpus: None,
+            direct_io: false,
+            benchmark: false,
+            simd: None,
+            #[cfg(feature = "opencl")]
+            zcb: false,
+            stop_flag: None,
+        };
+        let report = Hasher::with_events(Arc::new(NoEvents)).run(task).unwrap();
+        let plot = fs::read(&report.files[0]).unwrap();
+        let stored = |n: u64, scoop: usize| {
+            let offset = (scoop * nonces as usize + n as usize) * SCOOP_SIZE;
+            &plot[offset..offset + SCOOP_SIZE]
+        };
+
+        for n in [0, 5, nonces - 1] {
+            let nonce = generate_nonce(numeric_id, start_nonce + n);
+            for scoop in [0, 1, 2047, 2048, 4095] {
+                assert_eq!(&nonce[scoop * SCOOP_SIZE..(scoop + 1) * SCOOP_SIZE], stored(n, scoop));
             }
         }
 
-        for i in (HASH_SIZE..=NONCE_SIZE - HASH_CAP).rev().step_by(HASH_SIZE) {
-            let hash = &shabal256_fast(&buffer[i..i + HASH_CAP], &t3);
-            buffer[i - HASH_SIZE..i].clone_from_slice(hash);
-        }
-
-        final_buffer.clone_from_slice(&shabal256_fast(&buffer[0..NONCE_SIZE], &t1));
+        let (n, scoop) = (3, 42);
+        let poc2 = generate_scoop(numeric_id, start_nonce + n, scoop as u64, PocVersion::Poc2);
+        assert_eq!(&poc2[..], stored(n, scoop));
+        let poc1 = generate_scoop(numeric_id, start_nonce + n, scoop as u64, PocVersion::Poc1);
+        assert_eq!(poc1[..HASH_SIZE], poc2[..HASH_SIZE]);
+        assert_eq!(&poc1[HASH_SIZE..], &stored(n, NUM_SCOOPS - 1 - scoop)[HASH_SIZE..]);
 
-        for i in 0..NONCE_SIZE {
-            buffer[i] ^= final_buffer[i % HASH_SIZE];
-        }
-
-        let cache_size = cache.len() / NONCE_SIZE;
-        for i in 0..NUM_SCOOPS {
-            let offset = i * cache_size * SCOOP_SIZE + (n as usize + cache_offset) * SCOOP_SIZE;
-            cache[offset..offset + HASH_SIZE]
-                .clone_from_slice(&buffer[i * SCOOP_SIZE..i * SCOOP_SIZE + HASH_SIZE]);
-            let mirror_offset = (4095 - i) * cache_size * SCOOP_SIZE
-                + (n as usize + cache_offset) * SCOOP_SIZE
-                + HASH_SIZE;
-            cache[mirror_offset..mirror_offset + HASH_SIZE].clone_from_slice(
-                &buffer[i * SCOOP_SIZE + HASH_SIZE..i * SCOOP_SIZE + 2 * HASH_SIZE],
-            );
-        }
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,239 @@
+use crate::buffer::PageAlignedByteBuffer;
+use crate::convert::{
+    buffer_size, checkpoint_output, finish_output, nonces_per_sector, open_output, parent_dir, prepare_output,
+    swap_scoop_halves, write_chunk, ConvertReport,
+};
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents, Phase};
+use crate::hasher::{NONCE_SIZE, NUM_SCOOPS, SCOOP_SIZE};
+use crate::utils::{open_r, parse_stagger_file_name, plot_file_name};
+use std::cmp::{max, min};
+use std::fs::File;
+use std::io::{Error, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+pub struct ReorderTask {
+    /// A legacy `{id}_{start_nonce}_{nonces}_{stagger}` plot file.
+    pub file: PathBuf,
+    /// Directory the reordered file is written to, `None` writes it next to
+    /// the original.
+    pub output_dir: Option<PathBuf>,
+    /// The file is PoC1, its scoop halves get swapped into PoC2 as well.
+    pub poc1: bool,
+    /// Buffer size like `--mem`, `0B` uses 1 GiB.
+    pub mem: String,
+    pub direct_io: bool,
+    /// Stops after the current chunk, running the same task again resumes.
+    pub stop_flag: Option<Arc<AtomicBool>>,
+}
+
+/// Rewrites a plot file that stores its nonces in groups of `stagger` into
+/// the layout the writer produces, as `{id}_{start_nonce}_{nonces}`.
+///
+/// Within a group the file is scoop-major already, so whole groups are read
+/// sequentially into a staging buffer when a chunk of whole groups fits into
+/// the memory limit beside it, and are read scoop by scoop otherwise. The new file is checkpointed
+/// in its resume info like the writer does, the original is left untouched.
+pub fn reorder_plot_file(task: &ReorderTask, events: &dyn HasherEvents) -> Result<ConvertReport, HasherError> {
+    let start = Instant::now();
+    let file = task.file.as_path();
+    let (numeric_id, start_nonce, nonces, stagger) = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_stagger_file_name)
+        .ok_or_else(|| HasherError::InvalidStaggerFileName(file.to_path_buf()))?;
+    let read_error = |source| HasherError::ReadPlotFile { file: file.to_path_buf(), source };
+
+    let size = file.metadata().map_err(read_error)?.len();
+    if size != nonces * NONCE_SIZE {
+        return Err(HasherError::PlotFileSize {
+            file: file.to_path_buf(),
+            expected: nonces * NONCE_SIZE,
+            actual: size,
+        });
+    }
+
+    let name = plot_file_name(numeric_id, start_nonce, nonces);
+    let output = match &task.output_dir {
+        Some(dir) => dir.join(name),
+        None => file.with_file_name(name),
+    };
+    let mem = buffer_size(&task.mem)?;
+    let nonces_per_sector = nonces_per_sector(&parent_dir(&output), task.direct_io, events)?;
+    let direct_io = task.direct_io && nonces.is_multiple_of(nonces_per_sector);
+
+    let group_size = stagger * NONCE_SIZE;
+    let (staged, chunk_nonces) = chunk_layout(stagger, nonces_per_sector, mem);
+
+    let resumed_from = prepare_output(&output, size, direct_io, events)?;
+    events.on_event(&HasherEvent::Phase(Phase::Converting { nonces, resumed_from }));
+    let write_error = |source| HasherError::WritePlotFile { file: output.clone(), source };
+    let should_stop = || task.stop_flag.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed));
+
+    let mut progress = resumed_from;
+    let mut stopped = false;
+    if progress < nonces {
+        let buffer = PageAlignedByteBuffer::new((min(chunk_nonces, nonces) * NONCE_SIZE) as usize);
+        let data = buffer.get_buffer();
+        let mut data = data.lock().unwrap();
+        let staging_buffer = staged.then(|| PageAlignedByteBuffer::new(group_size as usize));
+        let staging = staging_buffer.as_ref().map(|buffer| buffer.get_buffer());
+        let mut staging = staging.as_ref().map(|data| data.lock().unwrap());
+
+        let mut source = open_r(file).map_err(read_error)?;
+        let mut target = open_output(&output, direct_io).map_err(write_error)?;
+
+        while progress < nonces {
+            if should_stop() {
+                stopped = true;
+                break;
+            }
+            let count = min(chunk_nonces, nonces - progress);
+            let chunk = &mut data[..(count * NONCE_SIZE) as usize];
+            read_staggered(&mut source, stagger, progress, count, chunk, staging.as_deref_mut()).map_err(read_error)?;
+            if task.poc1 {
+                swap_scoop_halves(chunk, count);
+            }
+            write_chunk(&mut target, nonces, progress, count, chunk).map_err(write_error)?;
+            target.sync_data().map_err(write_error)?;
+            progress += count;
+
+            checkpoint_output(&output, progress, nonces)?;
+            events.on_event(&HasherEvent::Written(progress));
+            events.on_event(&HasherEvent::WriteSpeed(
+                ((progress - resumed_from) * NONCE_SIZE) as f64 / 1024.0 / 1024.0 / start.elapsed().as_secs_f64(),
+            ));
+        }
+    }
+    if !stopped {
+        finish_output(&output, false)?;
+    }
+
+    let report = ConvertReport {
+        file: file.to_path_buf(),
+        output,
+        nonces,
+        resumed_from,
+        converted_nonces: progress - resumed_from,
+        elapsed: start.elapsed(),
+        stopped,
+    };
+    events.on_event(&HasherEvent::Converted(report.clone()));
+    Ok(report)
+}
+
+/// Reads `count` nonces from `offset` on out of a file with groups of
+/// `stagger` nonces into `chunk` in file layout. With a `staging` buffer
+/// groups that are read completely take a single read.
+fn read_staggered(
+    plot: &mut File,
+    stagger: u64,
+    offset: u64,
+    count: u64,
+    chunk: &mut [u8],
+    mut staging: Option<&mut Vec<u8>>,
+) -> Result<(), Error> {
+    let mut nonce = offset;
+    while nonce < offset + count {
+        let in_group = nonce % stagger;
+        let n = min(stagger - in_group, offset + count - nonce);
+        let group_addr = nonce / stagger * stagger * NONCE_SIZE;
+        let dest = ((nonce - offset) * SCOOP_SIZE) as usize;
+        let row_len = (n * SCOOP_SIZE) as usize;
+
+        if let Some(staging) = staging.as_deref_mut().filter(|_| n == stagger) {
+            plot.seek(SeekFrom::Start(group_addr))?;
+            plot.read_exact(staging)?;
+            for (scoop, row) in staging.chunks_exact(row_len).enumerate() {
+                let row_addr = scoop * (count * SCOOP_SIZE) as usize + dest;
+                chunk[row_addr..row_addr + row_len].copy_from_slice(row);
+            }
+        } else {
+            for scoop in 0..NUM_SCOOPS {
+                plot.seek(SeekFrom::Start(group_addr + (scoop * stagger + in_group) * SCOOP_SIZE))?;
+                let row_addr = (scoop * count * SCOOP_SIZE) as usize + dest;
+                plot.read_exact(&mut chunk[row_addr..row_addr + row_len])?;
+            }
+        }
+        nonce += n;
+    }
+    Ok(())
+}
+
+/// Whether whole groups are staged and the nonces per chunk. Staged chunks
+/// hold whole groups and whole sectors, if a single such step doesn't fit
+/// beside the staging buffer the groups are read scoop by scoop instead.
+fn chunk_layout(stagger: u64, nonces_per_sector: u64, mem: u64) -> (bool, u64) {
+    let group_size = stagger * NONCE_SIZE;
+    let step = stagger * nonces_per_sector / gcd(stagger, nonces_per_sector);
+    if group_size + step * NONCE_SIZE <= mem {
+        (true, (mem - group_size) / NONCE_SIZE / step * step)
+    } else {
+        (false, max(mem / NONCE_SIZE / nonces_per_sector, 1) * nonces_per_sector)
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_hasher::{init_simd, noncegen};
+    use crate::events::NoEvents;
+    use crate::resume::ResumeInfo;
+    use std::fs;
+
+    #[test]
+    fn test_reorder_plot_file() {
+        let numeric_id = 7900104405094198526;
+        let (start_nonce, nonces) = (1337, 6);
+        let dir = std::env::temp_dir().join(format!("anne-hasher-reorder-{}", std::process::id()));
+        let (poc1_dir, poc2_dir) = (dir.join("poc1"), dir.join("poc2"));
+        fs::create_dir_all(&poc1_dir).unwrap();
+        fs::create_dir_all(&poc2_dir).unwrap();
+
+        let simd = init_simd();
+        let mut expected = vec![0u8; (nonces * NONCE_SIZE) as usize];
+        noncegen(&simd, &mut expected, 0, numeric_id, start_nonce, nonces);
+
+        // a group has the layout of a file of `stagger` nonces
+        let mut staggered = vec![0u8; (nonces * NONCE_SIZE) as usize];
+        for (i, group) in staggered.chunks_exact_mut(2 * NONCE_SIZE as usize).enumerate() {
+            noncegen(&simd, group, 0, numeric_id, start_nonce + 2 * i as u64, 2);
+            swap_scoop_halves(group, 2);
+        }
+        let poc1 = poc1_dir.join(format!("{}_{}_{}_2", numeric_id, start_nonce, nonces));
+        fs::write(&poc1, &staggered).unwrap();
+        // a single group, larger than the memory limit
+        let poc2 = poc2_dir.join(format!("{}_{}_{}_{}", numeric_id, start_nonce, nonces, nonces));
+        fs::write(&poc2, &expected).unwrap();
+
+        for (file, poc1, mem) in [(poc1, true, 4 * NONCE_SIZE), (poc2, false, 4 * NONCE_SIZE)] {
+            let task = ReorderTask {
+                file,
+                output_dir: None,
+                poc1,
+                mem: mem.to_string(),
+                direct_io: false,
+                stop_flag: None,
+            };
+            let report = reorder_plot_file(&task, &NoEvents).unwrap();
+            assert_eq!(report.converted_nonces, nonces);
+            assert_eq!(fs::read(&report.output).unwrap(), expected);
+            assert!(ResumeInfo::read(&report.output).unwrap().complete);
+        }
+
+        // chunks stay within the memory limit when sectors and groups don't line up
+        assert_eq!(chunk_layout(2, 64, 66 * NONCE_SIZE), (true, 64));
+        assert_eq!(chunk_layout(3, 64, 6 * NONCE_SIZE), (false, 64));
+        assert_eq!(chunk_layout(3, 64, 194 * NONCE_SIZE), (false, 192));
+        assert_eq!(chunk_layout(3, 64, 195 * NONCE_SIZE), (true, 192));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
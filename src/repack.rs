@@ -0,0 +1,406 @@
+use crate::buffer::PageAlignedByteBuffer;
+use crate::convert::{
+    buffer_size, check_plot_file, checkpoint_output, finish_output, nonces_per_sector, open_output, parent_dir,
+    prepare_output, read_chunk, write_chunk,
+};
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents, Phase};
+use crate::hasher::NONCE_SIZE;
+use crate::resume::resume_file;
+use crate::utils::{open_r, plot_file_name};
+use std::cmp::{max, min};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub struct MergeTask {
+    /// Plot files of one numeric ID whose nonces follow each other, in any order.
+    pub files: Vec<PathBuf>,
+    /// Directory the merged file is written to, `None` uses the one of the
+    /// first file.
+    pub output_dir: Option<PathBuf>,
+    /// Delete the merged files once the new file is complete.
+    pub remove_sources: bool,
+    /// Buffer size like `--mem`, `0B` uses 1 GiB.
+    pub mem: String,
+    pub direct_io: bool,
+    /// Stops after the current chunk, running the same task again resumes.
+    pub stop_flag: Option<Arc<AtomicBool>>,
+}
+
+pub struct SplitTask {
+    pub file: PathBuf,
+    /// Nonces per new file, the last one gets the rest.
+    pub nonces: u64,
+    /// Directory the new files are written to, `None` uses the one of `file`.
+    pub output_dir: Option<PathBuf>,
+    /// Delete `file` once all new files are complete.
+    pub remove_source: bool,
+    /// Buffer size like `--mem`, `0B` uses 1 GiB.
+    pub mem: String,
+    pub direct_io: bool,
+    /// Stops after the current chunk, running the same task again resumes.
+    pub stop_flag: Option<Arc<AtomicBool>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RepackReport {
+    pub files: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+    pub nonces: u64,
+    /// Nonces an earlier, interrupted run had copied already.
+    pub resumed_from: u64,
+    pub copied_nonces: u64,
+    pub elapsed: Duration,
+    pub stopped: bool,
+    /// Whether the original files have been deleted.
+    pub removed: bool,
+}
+
+impl fmt::Display for RepackReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.stopped {
+            return write!(
+                f,
+                "Stopped after {} of {} nonces, run again to resume",
+                self.resumed_from + self.copied_nonces,
+                self.nonces
+            );
+        }
+        write!(
+            f,
+            "Copied {} nonces in {:.0}s, {:.2} MiB/s, {} file(s) into {} file(s):",
+            self.copied_nonces,
+            self.elapsed.as_secs_f64(),
+            (self.copied_nonces * NONCE_SIZE) as f64 / 1024.0 / 1024.0 / self.elapsed.as_secs_f64().max(0.001),
+            self.files.len(),
+            self.outputs.len()
+        )?;
+        for output in &self.outputs {
+            write!(f, "\n  {}", output.display())?;
+        }
+        if self.removed {
+            write!(f, "\nThe original file(s) have been deleted.")?;
+        }
+        Ok(())
+    }
+}
+
+/// A run of nonces copied from `file`, which holds `nonces`, into an output.
+struct Segment {
+    file: PathBuf,
+    nonces: u64,
+    from: u64,
+    to: u64,
+    count: u64,
+}
+
+/// One new plot file and where its nonces come from.
+struct Output {
+    file: PathBuf,
+    nonces: u64,
+    segments: Vec<Segment>,
+}
+
+/// Merges plot files with consecutive nonces into one
+/// `{id}_{start_nonce}_{nonces}` file, scoop by scoop without hashing.
+pub fn merge_plot_files(task: &MergeTask, events: &dyn HasherEvents) -> Result<RepackReport, HasherError> {
+    if task.files.len() < 2 {
+        return Err(HasherError::InvalidRepack("merging needs at least two files".to_string()));
+    }
+    let mut files = task
+        .files
+        .iter()
+        .map(|file| check_plot_file(file).map(|params| (file, params)))
+        .collect::<Result<Vec<_>, _>>()?;
+    files.sort_by_key(|&(_, (_, start_nonce, _, _))| start_nonce);
+
+    let (first, (numeric_id, start_nonce, _, poc1)) = files[0];
+    let mut segments = Vec::with_capacity(files.len());
+    let mut nonces = 0;
+    for &(file, (id, start, count, format)) in &files {
+        if id != numeric_id {
+            return Err(HasherError::InvalidRepack(format!(
+                "'{}' belongs to numeric ID {}, not {}",
+                file.display(),
+                id,
+                numeric_id
+            )));
+        }
+        if format != poc1 {
+            return Err(HasherError::InvalidRepack(format!(
+                "'{}' is {}, '{}' isn't",
+                first.display(),
+                if poc1 { "PoC1" } else { "PoC2" },
+                file.display()
+            )));
+        }
+        if start != start_nonce + nonces {
+            return Err(HasherError::InvalidRepack(format!(
+                "'{}' starts at nonce {}, expected {}",
+                file.display(),
+                start,
+                start_nonce + nonces
+            )));
+        }
+        segments.push(Segment { file: file.clone(), nonces: count, from: 0, to: nonces, count });
+        nonces += count;
+    }
+
+    let dir = task.output_dir.clone().unwrap_or_else(|| PathBuf::from(parent_dir(first)));
+    let output = Output {
+        file: dir.join(plot_file_name(numeric_id, start_nonce, nonces)),
+        nonces,
+        segments,
+    };
+    let sources = files.iter().map(|(file, _)| file.to_path_buf()).collect();
+    repack(
+        sources,
+        vec![output],
+        poc1,
+        task.remove_sources,
+        &task.mem,
+        task.direct_io,
+        task.stop_flag.as_ref(),
+        events,
+    )
+}
+
+/// Splits a plot file into `{id}_{start_nonce}_{nonces}` files of
+/// `task.nonces` nonces each, scoop by scoop without hashing.
+pub fn split_plot_file(task: &SplitTask, events: &dyn HasherEvents) -> Result<RepackReport, HasherError> {
+    let file = task.file.as_path();
+    let (numeric_id, start_nonce, nonces, poc1) = check_plot_file(file)?;
+    if task.nonces == 0 || task.nonces >= nonces {
+        return Err(HasherError::InvalidRepack(format!(
+            "{} nonces don't split into files of {}",
+            nonces, task.nonces
+        )));
+    }
+
+    let dir = task.output_dir.clone().unwrap_or_else(|| PathBuf::from(parent_dir(file)));
+    let mut outputs = Vec::new();
+    let mut offset = 0;
+    while offset < nonces {
+        let count = min(task.nonces, nonces - offset);
+        outputs.push(Output {
+            file: dir.join(plot_file_name(numeric_id, start_nonce + offset, count)),
+            nonces: count,
+            segments: vec![Segment { file: file.to_path_buf(), nonces, from: offset, to: 0, count }],
+        });
+        offset += count;
+    }
+    repack(
+        vec![file.to_path_buf()],
+        outputs,
+        poc1,
+        task.remove_source,
+        &task.mem,
+        task.direct_io,
+        task.stop_flag.as_ref(),
+        events,
+    )
+}
+
+/// Writes `outputs` chunk by chunk like the writer does, synced and
+/// checkpointed in their resume info. All outputs are preallocated first, so
+/// a lack of space shows before anything is copied. `poc1` is the format of
+/// the sources, the outputs are marked the same.
+#[allow(clippy::too_many_arguments)]
+fn repack(
+    sources: Vec<PathBuf>,
+    outputs: Vec<Output>,
+    poc1: bool,
+    remove_sources: bool,
+    mem: &str,
+    direct_io: bool,
+    stop_flag: Option<&Arc<AtomicBool>>,
+    events: &dyn HasherEvents,
+) -> Result<RepackReport, HasherError> {
+    let start = Instant::now();
+    for output in &outputs {
+        // resuming an output that is one of the sources would destroy it
+        let existing = fs::canonicalize(&output.file).ok();
+        if sources.iter().any(|source| existing.is_some() && fs::canonicalize(source).ok() == existing) {
+            return Err(HasherError::InvalidRepack(format!(
+                "'{}' would be overwritten",
+                output.file.display()
+            )));
+        }
+    }
+
+    let mem = buffer_size(mem)?;
+    let mut nonces_per_sector = 1;
+    for output in &outputs {
+        nonces_per_sector = max(nonces_per_sector, self::nonces_per_sector(&parent_dir(&output.file), direct_io, events)?);
+    }
+    // every chunk of every output has to start and end on a sector
+    let direct_io = direct_io
+        && outputs.iter().all(|output| {
+            output.nonces.is_multiple_of(nonces_per_sector)
+                && output.segments.iter().all(|segment| {
+                    segment.to.is_multiple_of(nonces_per_sector) && segment.count.is_multiple_of(nonces_per_sector)
+                })
+        });
+    let chunk_nonces = max(mem / NONCE_SIZE / nonces_per_sector, 1) * nonces_per_sector;
+
+    let mut progress = Vec::with_capacity(outputs.len());
+    for output in &outputs {
+        progress.push(prepare_output(&output.file, output.nonces * NONCE_SIZE, direct_io, events)?);
+    }
+    let nonces = outputs.iter().map(|output| output.nonces).sum();
+    let resumed_from = progress.iter().sum();
+    events.on_event(&HasherEvent::Phase(Phase::Repacking { nonces, resumed_from }));
+
+    let should_stop = || stop_flag.is_some_and(|flag| flag.load(Ordering::Relaxed));
+    let max_chunk = outputs.iter().map(|output| min(chunk_nonces, output.nonces)).max().unwrap_or(1);
+    let buffer = PageAlignedByteBuffer::new((max_chunk * NONCE_SIZE) as usize);
+    let data = buffer.get_buffer();
+    let mut data = data.lock().unwrap();
+    let mut copied = resumed_from;
+    let mut stopped = false;
+
+    'outputs: for (output, mut done) in outputs.iter().zip(progress) {
+        let write_error = |source| HasherError::WritePlotFile { file: output.file.clone(), source };
+        let mut target = None;
+        for segment in &output.segments {
+            let read_error = |source| HasherError::ReadPlotFile { file: segment.file.clone(), source };
+            let end = segment.to + segment.count;
+            if done >= end {
+                continue;
+            }
+            let mut source = open_r(&segment.file).map_err(read_error)?;
+            while done < end {
+                if should_stop() {
+                    stopped = true;
+                    break 'outputs;
+                }
+                let target = match target.as_mut() {
+                    Some(target) => target,
+                    None => target.insert(open_output(&output.file, direct_io).map_err(write_error)?),
+                };
+                let count = min(chunk_nonces, end - done);
+                let chunk = &mut data[..(count * NONCE_SIZE) as usize];
+                let offset = segment.from + done - segment.to;
+                read_chunk(&mut source, segment.nonces, offset, count, chunk).map_err(read_error)?;
+                write_chunk(target, output.nonces, done, count, chunk).map_err(write_error)?;
+                target.sync_data().map_err(write_error)?;
+                done += count;
+                copied += count;
+
+                checkpoint_output(&output.file, done, output.nonces)?;
+                events.on_event(&HasherEvent::Written(copied));
+                events.on_event(&HasherEvent::WriteSpeed(
+                    ((copied - resumed_from) * NONCE_SIZE) as f64 / 1024.0 / 1024.0 / start.elapsed().as_secs_f64(),
+                ));
+            }
+        }
+        finish_output(&output.file, poc1)?;
+    }
+
+    let removed = remove_sources && !stopped;
+    if removed {
+        for source in &sources {
+            fs::remove_file(source)
+                .map_err(|e| HasherError::InvalidRepack(format!("couldn't delete '{}': {}", source.display(), e)))?;
+            let _ = fs::remove_file(resume_file(source));
+        }
+    }
+
+    let report = RepackReport {
+        files: sources,
+        outputs: outputs.into_iter().map(|output| output.file).collect(),
+        nonces,
+        resumed_from,
+        copied_nonces: copied - resumed_from,
+        elapsed: start.elapsed(),
+        stopped,
+        removed,
+    };
+    events.on_event(&HasherEvent::Repacked(report.clone()));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu_hasher::{init_simd, noncegen};
+    use crate::events::NoEvents;
+    use crate::resume::{write_legacy_marker, ResumeInfo};
+
+    #[test]
+    fn test_merge_and_split() {
+        let numeric_id = 7900104405094198526;
+        let (start_nonce, nonces) = (1337, 5);
+        let dir = std::env::temp_dir().join(format!("anne-hasher-repack-{}", std::process::id()));
+        let (split_dir, merge_dir) = (dir.join("split"), dir.join("merge"));
+        fs::create_dir_all(&split_dir).unwrap();
+        fs::create_dir_all(&merge_dir).unwrap();
+
+        let mut plot = vec![0u8; (nonces * NONCE_SIZE) as usize];
+        noncegen(&init_simd(), &mut plot, 0, numeric_id, start_nonce, nonces);
+        let file = dir.join(plot_file_name(numeric_id, start_nonce, nonces));
+        fs::write(&file, &plot).unwrap();
+        // a file of the old hasher, the marker bytes must not end up in a piece
+        write_legacy_marker(&file, nonces as u32).unwrap();
+
+        let split = SplitTask {
+            file: file.clone(),
+            nonces: 2,
+            output_dir: Some(split_dir.clone()),
+            remove_source: false,
+            mem: NONCE_SIZE.to_string(),
+            direct_io: false,
+            stop_flag: None,
+        };
+        let report = split_plot_file(&split, &NoEvents).unwrap();
+        assert_eq!(report.copied_nonces, nonces);
+        assert_eq!(report.outputs.len(), 3);
+        for (i, piece) in report.outputs.iter().enumerate() {
+            let count = min(2, nonces - 2 * i as u64);
+            assert_eq!(piece, &split_dir.join(plot_file_name(numeric_id, start_nonce + 2 * i as u64, count)));
+            let mut expected = vec![0u8; (count * NONCE_SIZE) as usize];
+            noncegen(&init_simd(), &mut expected, 0, numeric_id, start_nonce + 2 * i as u64, count);
+            assert_eq!(fs::read(piece).unwrap(), expected);
+            assert!(ResumeInfo::read(piece).unwrap().complete);
+        }
+
+        // the pieces in any order merge back into the original file
+        let mut pieces = report.outputs.clone();
+        pieces.reverse();
+        let merge = MergeTask {
+            files: pieces.clone(),
+            output_dir: Some(merge_dir.clone()),
+            remove_sources: true,
+            mem: (2 * NONCE_SIZE).to_string(),
+            direct_io: false,
+            stop_flag: None,
+        };
+        let report = merge_plot_files(&merge, &NoEvents).unwrap();
+        assert_eq!(report.outputs, vec![merge_dir.join(plot_file_name(numeric_id, start_nonce, nonces))]);
+        assert_eq!(fs::read(&report.outputs[0]).unwrap(), plot);
+        assert!(pieces.iter().all(|piece| !piece.exists()));
+
+        // files have to follow each other without gaps or overlaps
+        let merge = MergeTask { files: vec![file.clone(), report.outputs[0].clone()], ..merge };
+        assert!(merge_plot_files(&merge, &NoEvents).is_err());
+
+        // pieces keep the format of a converted file, mixed formats don't merge
+        let info = ResumeInfo::new(numeric_id, start_nonce, nonces, 0).completed();
+        ResumeInfo { poc1: true, ..info }.write(&file).unwrap();
+        let report = split_plot_file(&split, &NoEvents).unwrap();
+        assert!(report.outputs.iter().all(|piece| ResumeInfo::read(piece).unwrap().poc1));
+        crate::resume::mark_complete(&report.outputs[1]).unwrap();
+        let merge = MergeTask { files: report.outputs.clone(), ..merge };
+        assert!(matches!(merge_plot_files(&merge, &NoEvents), Err(HasherError::InvalidRepack(_))));
+
+        // unreadable resume info isn't taken for a complete file
+        fs::write(resume_file(&file), b"garbage").unwrap();
+        assert!(matches!(split_plot_file(&split, &NoEvents), Err(HasherError::ReadResumeInfo { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
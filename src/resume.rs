@@ -0,0 +1,317 @@
+use crate::cpu_hasher::{init_simd, noncegen};
+use crate::hasher::NONCE_SIZE;
+use crate::utils::{open, open_r, parse_plot_file_name};
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: [u8; 8] = *b"ANNERSM\0";
+const LEGACY_MARKER: [u8; 4] = [0xAF, 0xFE, 0xAF, 0xFE];
+pub(crate) const RECORD_SIZE: usize = 48;
+const FLAG_COMPLETE: u32 = 1;
+const FLAG_POC1: u32 = 2;
+const FLAG_CONVERTED: u32 = 4;
+
+/// Format version written by this build. Version 0 is the legacy in-file
+/// marker, which is only ever read.
+pub const RESUME_INFO_VERSION: u32 = 1;
+
+/// How far a plot file has been written, kept next to it in `{file}.resume`.
+///
+/// Layout, little endian: magic `ANNERSM\0`, version u32, flags u32,
+/// numeric id u64, start nonce u64, nonce count u64, progress u64. The flags
+/// mark a file that has been completely written and synced, one that a
+/// conversion left in PoC1 layout and one that a conversion writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumeInfo {
+    pub version: u32,
+    pub numeric_id: u64,
+    pub start_nonce: u64,
+    pub nonces: u64,
+    /// Nonces from the start of the file that are on disk.
+    pub progress: u64,
+    /// Set once the last nonce has been written and the file synced.
+    pub complete: bool,
+    /// Set by a conversion to PoC1, everything else is written as PoC2.
+    pub poc1: bool,
+    /// Set on files written or rewritten by a conversion.
+    pub converted: bool,
+}
+
+impl ResumeInfo {
+    pub fn new(numeric_id: u64, start_nonce: u64, nonces: u64, progress: u64) -> ResumeInfo {
+        ResumeInfo {
+            version: RESUME_INFO_VERSION,
+            numeric_id,
+            start_nonce,
+            nonces,
+            progress,
+            complete: false,
+            poc1: false,
+            converted: false,
+        }
+    }
+
+    /// The same info, marked as completely written.
+    pub fn completed(self) -> ResumeInfo {
+        ResumeInfo {
+            progress: self.nonces,
+            complete: true,
+            ..self
+        }
+    }
+
+    /// Resume info for `file` at `progress`, task parameters taken from its name.
+    pub fn for_plot_file(file: &Path, progress: u64) -> Result<ResumeInfo, Error> {
+        let (numeric_id, start_nonce, nonces) = plot_file_params(file)?;
+        Ok(ResumeInfo::new(numeric_id, start_nonce, nonces, progress))
+    }
+
+    /// Whether this was read from the legacy marker in the last 8 bytes of the
+    /// plot file, which overwrote plot data.
+    pub fn is_legacy(&self) -> bool {
+        self.version == 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&MAGIC);
+        bytes[8..12].copy_from_slice(&self.version.to_le_bytes());
+        let flags = if self.complete { FLAG_COMPLETE } else { 0 }
+            | if self.poc1 { FLAG_POC1 } else { 0 }
+            | if self.converted { FLAG_CONVERTED } else { 0 };
+        bytes[12..16].copy_from_slice(&flags.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.numeric_id.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.start_nonce.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.nonces.to_le_bytes());
+        bytes[40..48].copy_from_slice(&self.progress.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ResumeInfo, Error> {
+        if bytes.len() < RECORD_SIZE || bytes[0..8] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a resume info file"));
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version == 0 || version > RESUME_INFO_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported resume info version {}", version),
+            ));
+        }
+        let flags = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+        let info = ResumeInfo {
+            version,
+            numeric_id: u64_at(16),
+            start_nonce: u64_at(24),
+            nonces: u64_at(32),
+            progress: u64_at(40),
+            complete: flags & FLAG_COMPLETE != 0,
+            poc1: flags & FLAG_POC1 != 0,
+            converted: flags & FLAG_CONVERTED != 0,
+        };
+        if info.progress > info.nonces {
+            return Err(Error::new(ErrorKind::InvalidData, "resume progress exceeds nonce count"));
+        }
+        if info.complete && info.progress != info.nonces {
+            return Err(Error::new(ErrorKind::InvalidData, "complete resume info with missing nonces"));
+        }
+        Ok(info)
+    }
+
+    /// Reads the resume info of a plot file, falling back to the legacy
+    /// in-file marker if there is no sidecar.
+    pub fn read(file: &Path) -> Result<ResumeInfo, Error> {
+        let (numeric_id, start_nonce, nonces) = plot_file_params(file)?;
+        let info = match fs::read(resume_file(file)) {
+            Ok(bytes) => ResumeInfo::from_bytes(&bytes)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let progress = read_legacy_marker(file)?;
+                let legacy = ResumeInfo::new(numeric_id, start_nonce, nonces, progress);
+                ResumeInfo { version: 0, ..legacy }
+            }
+            Err(e) => return Err(e),
+        };
+        if (info.numeric_id, info.start_nonce, info.nonces) != (numeric_id, start_nonce, nonces) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "resume info belongs to a different plot file",
+            ));
+        }
+        Ok(info)
+    }
+
+    /// Like [`ResumeInfo::read`], but `None` for a file with neither a sidecar
+    /// nor the legacy marker, which wasn't written by us.
+    pub fn read_optional(file: &Path) -> Result<Option<ResumeInfo>, Error> {
+        match ResumeInfo::read(file) {
+            Ok(info) => Ok(Some(info)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replaces the sidecar of `file` atomically, a crash leaves either the
+    /// old or the new record behind but never a torn one.
+    pub fn write(&self, file: &Path) -> Result<(), Error> {
+        write_atomically(&resume_file(file), &[&self.to_bytes()])
+    }
+}
+
+/// Replaces `path` with `parts` through a synced temporary file and a rename.
+pub(crate) fn write_atomically(path: &Path, parts: &[&[u8]]) -> Result<(), Error> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp = PathBuf::from(tmp_name);
+
+    let mut f = File::create(&tmp)?;
+    for part in parts {
+        f.write_all(part)?;
+    }
+    f.sync_all()?;
+    drop(f);
+    fs::rename(&tmp, path)?;
+
+    // persist the rename as well, not supported for directories on windows
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Path of the sidecar holding the resume info of `file`.
+pub fn resume_file(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".resume");
+    PathBuf::from(name)
+}
+
+/// Nonces from the start of `file` that are on disk.
+///
+/// Files that still carry the legacy in-file marker are migrated to a sidecar
+/// first, see [`migrate_legacy_marker`].
+pub fn read_resume_info(file: &Path) -> Result<u64, Error> {
+    read_migrated(file).map(|info| info.progress)
+}
+
+/// Resume info of `file`, a legacy marker is migrated to a sidecar first.
+pub(crate) fn read_migrated(file: &Path) -> Result<ResumeInfo, Error> {
+    let info = ResumeInfo::read(file)?;
+    if info.is_legacy() {
+        return migrate_legacy_marker(file, info);
+    }
+    Ok(info)
+}
+
+pub fn write_resume_info(file: &Path, nonces_written: u64) -> Result<(), Error> {
+    ResumeInfo::for_plot_file(file, nonces_written)?.write(file)
+}
+
+/// Records that every nonce of `file` has been written and synced.
+pub fn mark_complete(file: &Path) -> Result<(), Error> {
+    ResumeInfo::for_plot_file(file, 0)?.completed().write(file)
+}
+
+/// Moves legacy resume info into a sidecar.
+///
+/// The legacy marker overwrote the last 8 bytes of scoop 4095 of the last
+/// nonce. If that nonce has been written those bytes are regenerated,
+/// otherwise the writer will overwrite them anyway.
+fn migrate_legacy_marker(file: &Path, legacy: ResumeInfo) -> Result<ResumeInfo, Error> {
+    let info = ResumeInfo::new(legacy.numeric_id, legacy.start_nonce, legacy.nonces, legacy.progress);
+    if legacy.progress < legacy.nonces {
+        info.write(file)?;
+        return Ok(info);
+    }
+
+    // a cache of a single nonce has the layout of a single nonce file, so it
+    // ends with the same bytes as the plot file
+    let mut cache = vec![0u8; NONCE_SIZE as usize];
+    noncegen(&init_simd(), &mut cache, 0, legacy.numeric_id, legacy.start_nonce + legacy.nonces - 1, 1);
+    let mut plot = open(file)?;
+    plot.seek(SeekFrom::End(-8))?;
+    plot.write_all(&cache[cache.len() - 8..])?;
+    plot.sync_all()?;
+
+    let info = info.completed();
+    info.write(file)?;
+    Ok(info)
+}
+
+fn plot_file_params(file: &Path) -> Result<(u64, u64, u64), Error> {
+    file.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_plot_file_name)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "not a plot file name"))
+}
+
+fn read_legacy_marker(file: &Path) -> Result<u64, Error> {
+    let mut file = open_r(file)?;
+    file.seek(SeekFrom::End(-8))?;
+
+    let mut progress = [0u8; 4];
+    let mut double_monkey = [0u8; 4];
+
+    file.read_exact(&mut progress)?;
+    file.read_exact(&mut double_monkey)?;
+
+    if double_monkey == LEGACY_MARKER {
+        Ok(u64::from(u32::from_le_bytes(progress)))
+    } else {
+        Err(Error::new(ErrorKind::NotFound, "End marker not found"))
+    }
+}
+
+/// Writes the legacy in-file marker, only needed to produce old-style files.
+#[cfg(test)]
+pub(crate) fn write_legacy_marker(file: &Path, nonces_written: u32) -> Result<(), Error> {
+    let mut file = open(file)?;
+    file.seek(SeekFrom::End(-8))?;
+    file.write_all(&nonces_written.to_le_bytes())?;
+    file.write_all(&LEGACY_MARKER)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resume_info() {
+        let dir = std::env::temp_dir().join(format!("anne-hasher-resume-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("42_1000_8589934592");
+        fs::write(&file, [0u8; 64]).unwrap();
+
+        // old files only carry the 32 bit in-file marker
+        write_legacy_marker(&file, 1234).unwrap();
+        let info = ResumeInfo::read(&file).unwrap();
+        assert!(info.is_legacy());
+        assert_eq!(info.progress, 1234);
+
+        // the sidecar takes precedence and holds progress beyond 2^32
+        write_resume_info(&file, 5_000_000_000).unwrap();
+        let info = ResumeInfo::read(&file).unwrap();
+        assert_eq!(info, ResumeInfo::new(42, 1000, 8589934592, 5_000_000_000));
+
+        // a sidecar copied next to another file is rejected
+        let other = dir.join("42_0_8589934592");
+        fs::copy(resume_file(&file), resume_file(&other)).unwrap();
+        assert!(read_resume_info(&other).is_err());
+
+        // completed legacy files get their trampled plot bytes back
+        let (numeric_id, start_nonce, nonces) = (42, 7, 2);
+        let plot = dir.join(crate::utils::plot_file_name(numeric_id, start_nonce, nonces));
+        let mut expected = vec![0u8; (nonces * NONCE_SIZE) as usize];
+        noncegen(&init_simd(), &mut expected, 0, numeric_id, start_nonce, nonces);
+        fs::write(&plot, &expected).unwrap();
+        write_legacy_marker(&plot, nonces as u32).unwrap();
+        assert_eq!(read_resume_info(&plot).unwrap(), nonces);
+        assert!(fs::read(&plot).unwrap() == expected);
+        let info = ResumeInfo::read(&plot).unwrap();
+        assert!(!info.is_legacy() && info.complete);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use crate::cpu_hasher::{SimdExtension, hash_cpu, CpuTask, SafePointer};
 use crate::buffer::PageAlignedByteBuffer;
+use crate::events::{HasherEvent, HasherEvents};
 #[cfg(feature = "opencl")]
 use crate::gpu_hasher::{create_gpu_hasher_thread, GpuTask};
 #[cfg(feature = "opencl")]
@@ -14,45 +15,48 @@ use std::sync::Arc;
 #[cfg(feature = "opencl")]
 use std::thread;
 use std::sync::atomic::{Ordering};
+use std::time::Duration;
 
 const CPU_TASK_SIZE: u64 = 64;
 
+/// One plot file of a run and the writer its buffers go to.
+pub struct Stripe {
+    pub start_nonce: u64,
+    pub nonces_hashed: u64,
+    pub tx_buffers_to_writer: Sender<PageAlignedByteBuffer>,
+}
+
+/// Hashes buffers for the stripes in turn, so every writer gets work while
+/// the others are busy. All stripes have `task.nonces` nonces.
+///
+/// The thread returns how long it waited for empty buffers.
 pub fn create_scheduler_thread(
     task: Arc<HasherTask>,
     thread_pool: rayon::ThreadPool,
-    mut nonces_hashed: u64,
-    pb: Option<indicatif::ProgressBar>,
+    mut stripes: Vec<Stripe>,
+    events: Arc<dyn HasherEvents>,
     rx_empty_buffers: Receiver<PageAlignedByteBuffer>,
-    tx_buffers_to_writer: Sender<PageAlignedByteBuffer>,
     simd_ext: SimdExtension,
-) -> impl FnOnce() {
+) -> impl FnOnce() -> Duration {
     move || {
-        #[cfg(feature = "gui")]
-        use crate::hasher::ProgressUpdate;
-        
-        #[cfg(feature = "gui")]
+        let mut stall = Duration::ZERO;
         let start_time = std::time::Instant::now();
-        #[cfg(feature = "gui")]
         let mut last_speed_update_time = start_time;
-        #[cfg(feature = "gui")]
         let mut total_nonces_processed = 0u64;
+        let log = |msg: &str| events.on_event(&HasherEvent::Log(msg.to_string()));
 
         // Get stop flag reference
         let stop_flag = task.stop_flag.clone();
         
         // Helper function to check stop
         let should_stop = || {
-            stop_flag.as_ref().map_or(false, |flag| flag.load(Ordering::Relaxed))
+            stop_flag.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
         };
 
         // Check at the very beginning
         if should_stop() {
-            #[cfg(feature = "gui")]
-            if let Some(tx) = &task.progress_tx {
-                let _ = tx.send(ProgressUpdate::Log("Scheduler: Stop requested before starting".to_string()));
-            }
-            println!("Scheduler: Stop requested before starting");
-            return;
+            log("Scheduler: Stop requested before starting");
+            return stall;
         }
 
         let (tx, rx) = channel();
@@ -86,19 +90,25 @@ pub fn create_scheduler_thread(
             }));
         }
 
+        // nonces per device since the start, device 0 is the CPU pool
+        #[cfg(feature = "opencl")]
+        let mut device_nonces = vec![0u64; gpus.len() + 1];
+        #[cfg(not(feature = "opencl"))]
+        let mut device_nonces = [0u64; 1];
+
         // Simple buffer timing for logging purposes only
         let mut buffer_count: u32 = 0;
         let mut last_buffer_time = std::time::Instant::now();
         let mut avg_time_per_buffer = std::time::Duration::from_millis(0);
 
-        while nonces_hashed < task.nonces && !should_stop() {
+        let mut total_hashed: u64 = stripes.iter().map(|s| s.nonces_hashed).sum();
+        let total_nonces = task.nonces * stripes.len() as u64;
+        let mut next_stripe = 0;
+
+        while total_hashed < total_nonces && !should_stop() {
             // Check stop flag more frequently during long operations
-            if buffer_count % 10 == 0 && should_stop() {
-                #[cfg(feature = "gui")]
-                if let Some(tx) = &task.progress_tx {
-                    let _ = tx.send(ProgressUpdate::Log("Scheduler: Stop requested during processing".to_string()));
-                }
-                println!("Scheduler: Stop requested during processing");
+            if buffer_count.is_multiple_of(10) && should_stop() {
+                log("Scheduler: Stop requested during processing");
                 break;
             }
             
@@ -121,27 +131,23 @@ pub fn create_scheduler_thread(
             }
             
             // Log buffer rate occasionally
-            #[cfg(feature = "gui")]
-            if let Some(tx_progress) = &task.progress_tx {
-                if buffer_count % 200 == 0 {
-                    let buffer_rate = 1000.0 / avg_time_per_buffer.as_millis() as f64;
-                    let _ = tx_progress.send(ProgressUpdate::Log(
-                        format!("Buffer rate: {:.1}/sec, Avg time: {:?}", buffer_rate, avg_time_per_buffer)
-                    ));
-                }
+            if buffer_count.is_multiple_of(200) {
+                let buffer_rate = 1000.0 / avg_time_per_buffer.as_millis() as f64;
+                log(&format!("Buffer rate: {:.1}/sec, Avg time: {:?}", buffer_rate, avg_time_per_buffer));
             }
 
             // Receive buffer with timeout to check stop flag
-            let buffer = match rx_empty_buffers.recv_timeout(std::time::Duration::from_millis(100)) {
+            let wait_start = std::time::Instant::now();
+            let received = rx_empty_buffers.recv_timeout(Duration::from_millis(100));
+            let waited = wait_start.elapsed();
+            stall += waited;
+            events.on_event(&HasherEvent::HasherStall(waited));
+            let buffer = match received {
                 Ok(buf) => buf,
                 Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
                     // Check stop flag during timeout
                     if should_stop() {
-                        #[cfg(feature = "gui")]
-                        if let Some(tx) = &task.progress_tx {
-                            let _ = tx.send(ProgressUpdate::Log("Scheduler: Stop requested while waiting for buffer".to_string()));
-                        }
-                        println!("Scheduler: Stop requested while waiting for buffer");
+                        log("Scheduler: Stop requested while waiting for buffer");
                         break;
                     }
                     continue;
@@ -152,6 +158,16 @@ pub fn create_scheduler_thread(
                 }
             };
 
+            // round robin over the stripes that still need nonces
+            while stripes[next_stripe].nonces_hashed == task.nonces {
+                next_stripe = (next_stripe + 1) % stripes.len();
+            }
+            let current = next_stripe;
+            next_stripe = (next_stripe + 1) % stripes.len();
+            let stripe = &mut stripes[current];
+            let start_nonce = stripe.start_nonce;
+            let nonces_hashed = stripe.nonces_hashed;
+
             let mut_bs = &buffer.get_buffer();
             let mut bs = mut_bs.lock().unwrap();
             let buffer_size = (*bs).len() as u64;
@@ -174,7 +190,7 @@ pub fn create_scheduler_thread(
                             cache_size: buffer_size / NONCE_SIZE,
                             chunk_offset: requested,
                             numeric_id: task.numeric_id,
-                            local_startnonce: task.start_nonce + nonces_hashed + requested,
+                            local_startnonce: start_nonce + nonces_hashed + requested,
                             local_nonces: task_size,
                         }));
                 }
@@ -193,7 +209,7 @@ pub fn create_scheduler_thread(
                             cache_size: (buffer_size / NONCE_SIZE) as usize,
                             chunk_offset: requested as usize,
                             numeric_id: task.numeric_id,
-                            local_startnonce: task.start_nonce + nonces_hashed + requested,
+                            local_startnonce: start_nonce + nonces_hashed + requested,
                             local_nonces: task_size,
                         },
                         simd_ext.clone(),
@@ -203,23 +219,23 @@ pub fn create_scheduler_thread(
                 requested += task_size;
             }
 
+            // after a stop no new work is handed out, but the tasks that are
+            // running still write into the buffer, so it may only leave once
+            // all of them have reported back
+            let mut stopping = false;
             let rx = &rx;
             for msg in rx {
-                // Check stop flag during message processing
-                if should_stop() {
-                    #[cfg(feature = "gui")]
-                    if let Some(tx) = &task.progress_tx {
-                        let _ = tx.send(ProgressUpdate::Log("Scheduler: Stop requested during hashing".to_string()));
-                    }
-                    println!("Scheduler: Stop requested during hashing");
-                    break;
+                if !stopping && should_stop() {
+                    log("Scheduler: Stop requested during hashing");
+                    stopping = true;
                 }
-                
+                let remaining = if stopping { 0 } else { nonces_to_hash - requested };
+
                 match msg.1 {
                     1 => {
                         let task_size = match msg.0 {
                             0 => {
-                                let task_size = min(CPU_TASK_SIZE, nonces_to_hash - requested);
+                                let task_size = min(CPU_TASK_SIZE, remaining);
                                 if task_size > 0 {
                                     let task = hash_cpu(
                                         tx.clone(),
@@ -230,7 +246,7 @@ pub fn create_scheduler_thread(
                                             cache_size: (buffer_size / NONCE_SIZE) as usize,
                                             chunk_offset: requested as usize,
                                             numeric_id: task.numeric_id,
-                                            local_startnonce: task.start_nonce
+                                            local_startnonce: start_nonce
                                                 + nonces_hashed
                                                 + requested,
                                             local_nonces: task_size,
@@ -245,8 +261,7 @@ pub fn create_scheduler_thread(
                                 #[cfg(feature = "opencl")]
                                 let gpu = gpus[(msg.0 - 1) as usize].lock().unwrap();
                                 #[cfg(feature = "opencl")]
-                                let task_size =
-                                    min(gpu.worksize as u64, nonces_to_hash - requested);
+                                let task_size = min(gpu.worksize as u64, remaining);
 
                                 #[cfg(feature = "opencl")]
                                 let task_size = if task_size < gpu.worksize as u64
@@ -271,7 +286,7 @@ pub fn create_scheduler_thread(
                                         cache_size: buffer_size / NONCE_SIZE,
                                         chunk_offset: requested,
                                         numeric_id: task.numeric_id,
-                                        local_startnonce: task.start_nonce
+                                        local_startnonce: start_nonce
                                             + nonces_hashed
                                             + requested,
                                         local_nonces: task_size,
@@ -284,73 +299,47 @@ pub fn create_scheduler_thread(
                     }
                     0 => {
                         processed += msg.2;
-                        if let Some(pb) = &pb {
-                            pb.inc(msg.2 * NONCE_SIZE);
-                        }
-                        
-
-                        #[cfg(feature = "gui")]
-                        if let Some(tx) = &task.progress_tx {
-                            total_nonces_processed += msg.2;
-                            
+                        total_nonces_processed += msg.2;
+                        device_nonces[msg.0 as usize] += msg.2;
+                        events.on_event(&HasherEvent::Hashed(total_hashed + processed));
+                        events.on_event(&HasherEvent::DeviceHashed { device: msg.0, nonces: msg.2 });
 
-                            let current_nonces = nonces_hashed + processed;
-                            let progress_pct = current_nonces as f32 / task.nonces as f32;
-                            let _ = tx.send(ProgressUpdate::Progress(progress_pct));
-                            
-
-                            let now = std::time::Instant::now();
-                            if now.duration_since(last_speed_update_time).as_secs() >= 1 {
-                                let elapsed = now.duration_since(start_time).as_secs_f64();
-                                if elapsed > 0.0 {
-                                    let speed = total_nonces_processed as f64 * 60.0 / elapsed;
-                                    let _ = tx.send(ProgressUpdate::Speed(speed));
+                        let now = std::time::Instant::now();
+                        if now.duration_since(last_speed_update_time).as_secs() >= 1 {
+                            let elapsed = now.duration_since(start_time).as_secs_f64();
+                            if elapsed > 0.0 {
+                                let speed = total_nonces_processed as f64 * 60.0 / elapsed;
+                                events.on_event(&HasherEvent::HashSpeed(speed));
+                                for (device, nonces) in device_nonces.iter().enumerate() {
+                                    events.on_event(&HasherEvent::DeviceHashSpeed {
+                                        device: device as u8,
+                                        speed: *nonces as f64 * 60.0 / elapsed,
+                                    });
                                 }
-                                last_speed_update_time = now;
                             }
+                            last_speed_update_time = now;
                         }
                     }
                     _ => {}
                 }
-                if processed == nonces_to_hash {
+                if processed == nonces_to_hash || (stopping && processed == requested) {
                     break;
                 }
             }
 
-            // Check again before sending buffer to writer
-            if should_stop() {
-                #[cfg(feature = "gui")]
-                if let Some(tx) = &task.progress_tx {
-                    let _ = tx.send(ProgressUpdate::Log("Scheduler: Stop requested before sending buffer to writer".to_string()));
-                }
-                println!("Scheduler: Stop requested before sending buffer to writer");
-                // Return buffer to pool
-                let _ = tx_buffers_to_writer.send(buffer);
+            // an unfinished buffer is never written, the resume info would
+            // claim nonces that weren't hashed
+            if processed < nonces_to_hash {
+                log("Scheduler: Dropping the unfinished buffer");
                 break;
             }
 
-            nonces_hashed += nonces_to_hash;
-            
-
-            #[cfg(feature = "gui")]
-            if let Some(tx) = &task.progress_tx {
-                let progress_pct = nonces_hashed as f32 / task.nonces as f32;
-                let _ = tx.send(ProgressUpdate::Progress(progress_pct));
-            }
-
-            let _ = tx_buffers_to_writer.send(buffer);
-
-            if task.nonces == nonces_hashed {
-                if let Some(pb) = &pb {
-                    pb.finish_with_message("Hasher done.");
-                }
-                 #[cfg(feature = "gui")]
-                if let Some(tx) = &task.progress_tx {
-                    // Send final progress update
-                    let _ = tx.send(ProgressUpdate::Progress(1.0));
-                }
+            stripe.nonces_hashed += nonces_to_hash;
+            total_hashed += nonces_to_hash;
 
+            let _ = stripe.tx_buffers_to_writer.send(buffer);
 
+            if total_hashed == total_nonces {
                 #[cfg(feature = "opencl")]
                 for gpu in &gpu_channels {
                     let _ = gpu.0.send(None);
@@ -376,14 +365,9 @@ pub fn create_scheduler_thread(
             let _ = thread.join();
         }
         
-        #[cfg(feature = "gui")]
-        if should_stop() && let Some(tx) = &task.progress_tx {
-            let _ = tx.send(ProgressUpdate::Log("Scheduler: Exiting due to stop request".to_string()));
-        }
-        
-        // Print to console when exiting due to stop (even in non-GUI mode)
         if should_stop() {
-            println!("Scheduler: Exiting due to stop request");
+            log("Scheduler: Exiting due to stop request");
         }
+        stall
     }
 }
\ No newline at end of file
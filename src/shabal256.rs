@@ -1,16 +1,14 @@
-use std::slice::from_raw_parts;
-
-const A_INIT: [u32; 12] = [
+pub(crate) const A_INIT: [u32; 12] = [
     0x52F84552, 0xE54B7999, 0x2D8EE3EC, 0xB9645191, 0xE0078B86, 0xBB7C44C9, 0xD2B5C1CA, 0xB0D2EB8C,
     0x14CE5A45, 0x22AF50DC, 0xEFFDBC6B, 0xEB21B74A,
 ];
 
-const B_INIT: [u32; 16] = [
+pub(crate) const B_INIT: [u32; 16] = [
     0xB555C6EE, 0x3E710596, 0xA72A652F, 0x9301515F, 0xDA28C1FA, 0x696FD868, 0x9CB6BF72, 0x0AFE4002,
     0xA6E03615, 0x5138C1D4, 0xBE216306, 0xB38B8890, 0x3EA8B96B, 0x3299ACE4, 0x30924DD4, 0x55CB34A5,
 ];
 
-const C_INIT: [u32; 16] = [
+pub(crate) const C_INIT: [u32; 16] = [
     0xB405F031, 0xC4233EBA, 0xB3733979, 0xC0DD9D55, 0xC51C28AE, 0xA327B8E1, 0x56C56167, 0xED614433,
     0x88B59D60, 0x60E2CEBA, 0x758B4B8B, 0x83E82A7F, 0xBC968828, 0xE6E00BF7, 0xBA839E55, 0x9B491C60,
 ];
@@ -21,20 +19,19 @@ pub fn shabal256_fast(data: &[u8], term: &[u32; 16]) -> [u8; 32] {
     let mut c = C_INIT;
     let mut w_high = 0u32;
     let mut w_low = 1u32;
-    let mut num = data.len() >> 6;
-    let mut ptr = 0;
-    let data_ptr = data.as_ptr() as *const u32;
-    let data = unsafe { from_raw_parts(data_ptr, data.len() / 4) };
+    let mut block = [0u32; 16];
 
-    while num > 0 {
-        input_block_add(&mut b, &data[ptr..]);
+    for chunk in data.chunks_exact(64) {
+        // data isn't necessarily 4 byte aligned, so copy the block out
+        for (word, bytes) in block.iter_mut().zip(chunk.chunks_exact(4)) {
+            *word = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        input_block_add(&mut b, &block);
         xor_w(&mut a, w_low, w_high);
-        apply_p(&mut a, &mut b, &c, &data[ptr..]);
-        input_block_sub(&mut c, &data[ptr..]);
+        apply_p(&mut a, &mut b, &c, &block);
+        input_block_sub(&mut c, &block);
         swap_bc(&mut b, &mut c);
         incr_w(&mut w_low, &mut w_high);
-        ptr = ptr.wrapping_add(16);
-        num = num.wrapping_sub(1);
     }
     input_block_add(&mut b, term);
     xor_w(&mut a, w_low, w_high);
@@ -47,6 +44,20 @@ pub fn shabal256_fast(data: &[u8], term: &[u32; 16]) -> [u8; 32] {
     unsafe { *(b[8..16].as_ptr() as *const [u8; 32]) }
 }
 
+/// Shabal-256 of an arbitrary message, padding included.
+pub fn shabal256(data: &[u8]) -> [u8; 32] {
+    let full = data.len() / 64 * 64;
+    let mut last = [0u8; 64];
+    last[..data.len() - full].copy_from_slice(&data[full..]);
+    last[data.len() - full] = 0x80;
+
+    let mut term = [0u32; 16];
+    for (word, bytes) in term.iter_mut().zip(last.chunks_exact(4)) {
+        *word = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    shabal256_fast(&data[..full], &term)
+}
+
 #[inline(always)]
 fn input_block_add(b: &mut [u32; 16], data: &[u32]) {
     for (element, data) in b.iter_mut().zip(data.iter()) {
@@ -124,6 +135,7 @@ fn apply_p(a: &mut [u32; 12], b: &mut [u32; 16], c: &[u32; 16], data: &[u32]) {
 }
 
 #[inline(always)]
+#[allow(clippy::too_many_arguments)]
 fn perm_elt(
     a: &mut [u32; 12],
     b: &mut [u32; 16],
@@ -259,5 +271,11 @@ mod test {
             )
         };
         assert_eq!(hash_b, TEST_B_RESULT);
+        // the same messages padded by shabal256
+        assert_eq!(super::shabal256(&[0u8; 64]), TEST_A_RESULT);
+        assert_eq!(
+            super::shabal256(b"abcdefghijklmnopqrstuvwxyz-0123456789-ABCDEFGHIJKLMNOPQRSTUVWXYZ-0123456789-abcdefghijklmnopqrstuvwxyz"),
+            TEST_B_RESULT
+        );
     }
 }
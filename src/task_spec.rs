@@ -0,0 +1,123 @@
+use crate::cpu_hasher::{select_simd, SimdExtension};
+use crate::error::HasherError;
+use crate::events::HasherEvents;
+use crate::hasher::HasherTask;
+use crate::inventory::Inventory;
+use crate::utils::calculate_rounded_nonces;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// A plotting task as the HTTP API and the daemon queue take it, the JSON
+/// form of a [`HasherTask`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskSpec {
+    pub numeric_id: u64,
+    /// `None` continues after the last nonce in `paths` and `known_paths`.
+    #[serde(default)]
+    pub start_nonce: Option<u64>,
+    /// Nonces per plot file, rounded down to whole sectors with direct I/O.
+    pub nonces: u64,
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub known_paths: Vec<String>,
+    #[serde(default = "default_mem")]
+    pub mem: String,
+    #[serde(default)]
+    pub buffers: u8,
+    /// 0 uses all cores, or none when `gpus` are given.
+    #[serde(default)]
+    pub cpu_threads: u8,
+    #[serde(default)]
+    pub gpus: Option<Vec<String>>,
+    #[serde(default = "default_direct_io")]
+    pub direct_io: bool,
+    #[serde(default)]
+    pub zcb: bool,
+    /// A `--simd` value, `None` is `auto`.
+    #[serde(default)]
+    pub simd: Option<String>,
+}
+
+fn default_mem() -> String {
+    "0B".to_string()
+}
+
+fn default_direct_io() -> bool {
+    true
+}
+
+impl TaskSpec {
+    pub fn from_task(task: &HasherTask) -> TaskSpec {
+        TaskSpec {
+            numeric_id: task.numeric_id,
+            start_nonce: Some(task.start_nonce),
+            nonces: task.nonces,
+            paths: task.output_paths.clone(),
+            known_paths: task.known_paths.clone(),
+            mem: task.mem.clone(),
+            buffers: task.buffers,
+            cpu_threads: task.cpu_threads,
+            gpus: task.gpus.clone(),
+            direct_io: task.direct_io,
+            #[cfg(feature = "opencl")]
+            zcb: task.zcb,
+            #[cfg(not(feature = "opencl"))]
+            zcb: false,
+            simd: task.simd.as_ref().map(|simd_ext| simd_ext.name().to_string()),
+        }
+    }
+
+    /// Checks what can be checked before the task runs.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.nonces == 0 {
+            return Err("nonces must be at least 1".to_string());
+        }
+        if self.paths.is_empty() {
+            return Err("paths must not be empty".to_string());
+        }
+        if let Some(simd) = self.simd.as_deref().filter(|simd| !matches!(*simd, "auto" | "fastest")) {
+            simd.parse::<SimdExtension>().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// The task to run, with the start nonce and thread count resolved like
+    /// the command line does.
+    pub fn to_task(&self, stop_flag: Arc<AtomicBool>, events: &dyn HasherEvents) -> Result<HasherTask, HasherError> {
+        let cpu_threads = match self.cpu_threads {
+            0 if self.gpus.is_some() => 0,
+            0 => sys_info::cpu_num().unwrap_or(1) as u8,
+            threads => threads,
+        };
+        let simd = match self.simd.as_deref() {
+            Some(simd) => Some(select_simd(simd, cpu_threads.max(1) as usize)?),
+            None => None,
+        };
+        let nonces = self
+            .paths
+            .iter()
+            .try_fold(self.nonces, |nonces, path| calculate_rounded_nonces(nonces, self.direct_io, path, events))?;
+        let start_nonce = match self.start_nonce {
+            Some(start_nonce) => start_nonce,
+            None => Inventory::scan(&[&self.paths[..], &self.known_paths[..]].concat()).next_start_nonce(self.numeric_id),
+        };
+        Ok(HasherTask {
+            numeric_id: self.numeric_id,
+            start_nonce,
+            nonces,
+            output_paths: self.paths.clone(),
+            known_paths: self.known_paths.clone(),
+            mem: self.mem.clone(),
+            buffers: self.buffers,
+            cpu_threads,
+            gpus: self.gpus.clone(),
+            direct_io: self.direct_io,
+            benchmark: false,
+            simd,
+            #[cfg(feature = "opencl")]
+            zcb: self.zcb,
+            stop_flag: Some(stop_flag),
+        })
+    }
+}
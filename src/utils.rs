@@ -1,13 +1,14 @@
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::path::Path;
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents};
 
 cfg_if::cfg_if! {
     if #[cfg(unix)] {
         #[cfg(target_os = "linux")]
         extern crate thread_priority;
         use std::process::Command;
-        use std::process;
         use std::os::unix::fs::OpenOptionsExt;
         use fs2::FileExt;
         #[cfg(target_os = "linux")]
@@ -34,6 +35,7 @@ cfg_if::cfg_if! {
             OpenOptions::new()
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(path)
         }
 
@@ -44,16 +46,16 @@ cfg_if::cfg_if! {
         }
         
 
-        fn get_device_id_unix(path: &str) -> String {
+        fn get_device_id_unix(path: &str) -> Result<String, HasherError> {
     let path_obj = Path::new(path);
     let parent = path_obj.parent()
         .unwrap_or_else(|| Path::new("/"));
     
     if !parent.exists() {
-        panic!("Parent directory does not exist: {:?}. Please create it first.", parent);
+        return Err(HasherError::PathNotFound(parent.display().to_string()));
     }
     
-    let actual_path = parent.to_str().unwrap();
+    let actual_path = parent.to_str().unwrap_or("/");
     
     // Try using stat command which is more portable
     let output = Command::new("stat")
@@ -62,12 +64,12 @@ cfg_if::cfg_if! {
         .arg(actual_path)
         .output();
     
-    if let Ok(output) = output {
-        if output.status.success() {
-            let device = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !device.is_empty() && device != "0" {
-                return device;
-            }
+    if let Ok(output) = output
+        && output.status.success()
+    {
+        let device = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !device.is_empty() && device != "0" {
+            return Ok(device);
         }
     }
     
@@ -75,9 +77,12 @@ cfg_if::cfg_if! {
     let output = Command::new("df")
         .arg(actual_path)
         .output()
-        .expect("failed to execute 'df'");
+        .map_err(|e| HasherError::SectorSize {
+            path: path.to_string(),
+            reason: format!("failed to execute 'df': {}", e),
+        })?;
     
-    let source = String::from_utf8(output.stdout).expect("not utf8");
+    let source = String::from_utf8_lossy(&output.stdout);
     let lines: Vec<&str> = source.trim().split('\n').collect();
     
     if lines.len() >= 2 {
@@ -86,22 +91,28 @@ cfg_if::cfg_if! {
         if !parts.is_empty() {
             let device = parts[0].trim();
             if !device.is_empty() {
-                return device.to_string();
+                return Ok(device.to_string());
             }
         }
     }
     
-    panic!("Could not determine device for path: {} (parent: {})", path, actual_path);
+    Err(HasherError::SectorSize {
+        path: path.to_string(),
+        reason: format!("could not determine device (parent: {})", actual_path),
+    })
 }
 
-        fn get_sector_size_macos(path: &str) -> u64 {
-            let source = get_device_id_unix(path);
+        fn get_sector_size_macos(path: &str) -> Result<u64, HasherError> {
+            let source = get_device_id_unix(path)?;
             let output = Command::new("diskutil")
                 .arg("info")
                 .arg(&source)
                 .output()
-                .expect("failed to execute 'diskutil info'");
-            let source = String::from_utf8(output.stdout).expect("not utf8");
+                .map_err(|e| HasherError::SectorSize {
+                    path: path.to_string(),
+                    reason: format!("failed to execute 'diskutil info': {}", e),
+                })?;
+            let source = String::from_utf8_lossy(&output.stdout);
             let mut sector_size: u64 = 0;
             for line in source.split('\n') {
                 if line.trim().starts_with("Device Block Size") {
@@ -117,13 +128,16 @@ cfg_if::cfg_if! {
                 }
             }
             if sector_size == 0 {
-                panic!("Abort: Unable to determine disk physical sector size from diskutil info")
+                return Err(HasherError::SectorSize {
+                    path: path.to_string(),
+                    reason: "unable to determine disk physical sector size from diskutil info".to_string(),
+                });
             }
-            sector_size
+            Ok(sector_size)
         }
 
-        fn get_sector_size_unix(path: &str) -> u64 {
-            let source = get_device_id_unix(path);
+        fn get_sector_size_unix(path: &str, events: &dyn HasherEvents) -> Result<u64, HasherError> {
+            let source = get_device_id_unix(path)?;
             
 
             let output = match Command::new("lsblk")
@@ -136,109 +150,115 @@ cfg_if::cfg_if! {
                     Ok(output) => output,
                     Err(_) => {
 
-                        return get_sector_size_fallback(&source);
+                        return Ok(get_sector_size_fallback(&source, events));
                     }
                 };
 
-            let sector_size_str = String::from_utf8(output.stdout).expect("not utf8");
+            let sector_size_str = String::from_utf8_lossy(&output.stdout);
             let sector_size = sector_size_str.trim();
             
             if sector_size.is_empty() {
-                return get_sector_size_fallback(&source);
+                return Ok(get_sector_size_fallback(&source, events));
             }
             
-            sector_size.parse::<u64>().unwrap_or_else(|_| {
-                println!("Warning: Failed to parse sector size '{}', defaulting to 512", sector_size);
+            Ok(sector_size.parse::<u64>().unwrap_or_else(|_| {
+                events.on_event(&HasherEvent::Warning(format!(
+                    "Failed to parse sector size '{}', defaulting to 4096",
+                    sector_size
+                )));
                 4096
-            })
+            }))
         }
 
-        fn get_sector_size_fallback(device: &str) -> u64 {
+        fn get_sector_size_fallback(device: &str, events: &dyn HasherEvents) -> u64 {
             match Command::new("blockdev")
                 .arg("--getpbsz")
                 .arg(device)
                 .output() {
                     Ok(output) => {
-                        let size_str = String::from_utf8(output.stdout).expect("not utf8");
+                        let size_str = String::from_utf8_lossy(&output.stdout);
                         size_str.trim().parse::<u64>().unwrap_or(4096)
                     }
                     Err(_) => {
-                        println!("Warning: Could not determine sector size, defaulting to 4096");
+                        events.on_event(&HasherEvent::Warning(
+                            "Could not determine sector size, defaulting to 4096".to_string(),
+                        ));
                         4096
                     }
                 }
         }
 
-        pub fn get_sector_size(path: &str) -> u64 {
+        /// Physical sector size of the disk of `path`, fallbacks to 4096 are
+        /// reported as warnings.
+        pub fn get_sector_size(path: &str, events: &dyn HasherEvents) -> Result<u64, HasherError> {
             if cfg!(target_os = "macos") {
                 get_sector_size_macos(path)
             } else {
-                get_sector_size_unix(path)
+                get_sector_size_unix(path, events)
             }
         }
 
-        pub fn preallocate(file: &Path, size_in_bytes: u64, use_direct_io: bool) {
+        pub fn preallocate(
+            file: &Path,
+            size_in_bytes: u64,
+            use_direct_io: bool,
+            events: &dyn HasherEvents,
+        ) -> Result<(), HasherError> {
             if use_direct_io {
 
-                preallocate_direct_io(file, size_in_bytes)
+                preallocate_direct_io(file, size_in_bytes, events)
             } else {
                 preallocate_normal(file, size_in_bytes)
             }
         }
 
-        fn preallocate_normal(file: &Path, size_in_bytes: u64) {
-            let file = open(&file).unwrap();
-            match file.allocate(size_in_bytes) {
-                Err(errno) => {
-                    eprintln!("\n\nError: couldn't preallocate space for file. {}\n\
-                            Probable causes are:\n \
-                            * fallocate() is only supported on ext4 filesystems.\n \
-                            * Insufficient space.\n", errno);
-                    process::exit(1);
-                }
-                Ok(_) => (),
-            }
+        fn preallocate_normal(file: &Path, size_in_bytes: u64) -> Result<(), HasherError> {
+            let to_error = |source| HasherError::Preallocate { file: file.to_path_buf(), source };
+            open(file).map_err(to_error)?.allocate(size_in_bytes).map_err(to_error)
         }
 
-        fn preallocate_direct_io(file: &Path, size_in_bytes: u64) {
+        fn preallocate_direct_io(file: &Path, size_in_bytes: u64, events: &dyn HasherEvents) -> Result<(), HasherError> {
 
-            let sector_size = get_sector_size(file.to_str().unwrap_or("/"));
-            let aligned_size = ((size_in_bytes + sector_size - 1) / sector_size) * sector_size;
+            let sector_size = get_sector_size(file.to_str().unwrap_or("/"), events)?;
+            let aligned_size = size_in_bytes.div_ceil(sector_size) * sector_size;
             
 
-            let file_result = open_using_direct_io(&file);
+            let file_result = open_using_direct_io(file);
             
             match file_result {
-                Ok(file) => {
+                Ok(handle) => {
 
                     use std::os::unix::io::AsRawFd;
                     use libc::{ftruncate, c_int};
                     
-                    let fd = file.as_raw_fd();
+                    let fd = handle.as_raw_fd();
                     
                     unsafe {
                         if ftruncate(fd as c_int, aligned_size as i64) != 0 {
-                            let err = io::Error::last_os_error();
-                            println!("\n\nError: couldn't allocate space for O_DIRECT file. {}\n\
-                                      Probable causes are:\n \
-                                      * O_DIRECT requires aligned sizes\n \
-                                      * Filesystem doesn't support O_DIRECT properly\n \
-                                      * Try running without direct I/O flag\n", err);
-                            process::exit(1);
+                            return Err(HasherError::Preallocate {
+                                file: file.to_path_buf(),
+                                source: io::Error::last_os_error(),
+                            });
                         }
                     }
+                    Ok(())
                 }
                 Err(_e) => {
 
-                    eprintln!("\nWarning: DIRECT I/O is not available for your drive. Using normal I/O. Preallocation will be slow.");
-                    preallocate_normal(file, size_in_bytes);
+                    events.on_event(&HasherEvent::Warning(
+                        "DIRECT I/O is not available for your drive. Using normal I/O. Preallocation will be slow."
+                            .to_string(),
+                    ));
+                    preallocate_normal(file, size_in_bytes)
                 }
             }
         }
 
-        pub fn free_disk_space(path: &str) -> u64 {
+        pub fn free_disk_space(path: &str) -> Result<u64, HasherError> {
 
-            fs2::available_space(Path::new(&path)).unwrap().saturating_sub(2097152)
+            fs2::available_space(Path::new(&path))
+                .map(|space| space.saturating_sub(2097152))
+                .map_err(|source| HasherError::DiskSpace { path: path.to_string(), source })
         }
 
     } else {
@@ -284,18 +304,24 @@ cfg_if::cfg_if! {
                 .open(path)
         }
 
-        pub fn preallocate(file: &Path, size_in_bytes: u64, use_direct_io: bool) {
+        pub fn preallocate(
+            file: &Path,
+            size_in_bytes: u64,
+            use_direct_io: bool,
+            events: &dyn HasherEvents,
+        ) -> Result<(), HasherError> {
             let mut result = true;
             result &= obtain_priviledge();
 
+            let to_error = |source| HasherError::Preallocate { file: file.to_path_buf(), source };
             let file = if use_direct_io {
                 open_using_direct_io(&file)
             } else {
                 open(&file)
             };
-            let file = file.unwrap();
+            let file = file.map_err(to_error)?;
 
-            file.set_len(size_in_bytes).unwrap();
+            file.set_len(size_in_bytes).map_err(to_error)?;
 
             if result {
                 let handle = file.as_raw_handle();
@@ -306,9 +332,11 @@ cfg_if::cfg_if! {
             }
 
             if !result {
-                println!("FAILED, administrative rights missing");
-                print!("Slow file pre-allocation...");
+                events.on_event(&HasherEvent::Warning(
+                    "Administrative rights missing, slow file pre-allocation...".to_string(),
+                ));
             }
+            Ok(())
         }
 
         pub fn obtain_priviledge() -> bool {
@@ -351,9 +379,9 @@ cfg_if::cfg_if! {
             result
         }
 
-        pub fn get_sector_size(path: &str) -> u64 {
-            let path_encoded = Path::new(path);
-            let parent_path_encoded = CString::new(path_encoded.to_str().unwrap()).unwrap();
+        pub fn get_sector_size(path: &str, _events: &dyn HasherEvents) -> Result<u64, HasherError> {
+            let to_error = |reason: String| HasherError::SectorSize { path: path.to_string(), reason };
+            let parent_path_encoded = CString::new(path).map_err(|e| to_error(e.to_string()))?;
             let mut sectors_per_cluster  = 0u32;
             let mut bytes_per_sector  = 0u32;
             let mut number_of_free_cluster  = 0u32;
@@ -367,9 +395,9 @@ cfg_if::cfg_if! {
                     &mut total_number_of_cluster
                 )
             } == 0  {
-                panic!("get sector size, filename={}",path);
+                return Err(to_error(io::Error::last_os_error().to_string()));
             };
-            u64::from(bytes_per_sector)
+            Ok(u64::from(bytes_per_sector))
         }
 
         pub fn set_thread_ideal_processor(id: usize){
@@ -386,29 +414,81 @@ cfg_if::cfg_if! {
                 SetPriorityClass(GetCurrentProcess(),BELOW_NORMAL_PRIORITY_CLASS);
             }
         }
-        pub fn free_disk_space(path: &str) -> u64 {
-            fs2::available_space(Path::new(&path)).unwrap()
+        pub fn free_disk_space(path: &str) -> Result<u64, HasherError> {
+            fs2::available_space(Path::new(&path))
+                .map_err(|source| HasherError::DiskSpace { path: path.to_string(), source })
         }
     }
 
 }
 
-pub fn calculate_rounded_nonces(nonces: u64, direct_io: bool, output_path: &str) -> u64 {
+pub fn calculate_rounded_nonces(
+    nonces: u64,
+    direct_io: bool,
+    output_path: &str,
+    events: &dyn HasherEvents,
+) -> Result<u64, HasherError> {
     use crate::hasher::SCOOP_SIZE;
     
     if !direct_io {
-        return nonces;
+        return Ok(nonces);
     }
     
-    let sector_size = get_sector_size(output_path);
+    let sector_size = get_sector_size(output_path, events)?;
     let nonces_per_sector = sector_size / SCOOP_SIZE;
     
-    if nonces % nonces_per_sector == 0 {
-        nonces
+    if nonces.is_multiple_of(nonces_per_sector) {
+        Ok(nonces)
     } else {
         let rounded = nonces / nonces_per_sector;
-        rounded * nonces_per_sector
+        Ok(rounded * nonces_per_sector)
+    }
+}
+
+/// Name of the plot file holding `nonces` nonces starting at `start_nonce`.
+pub fn plot_file_name(numeric_id: u64, start_nonce: u64, nonces: u64) -> String {
+    format!("{}_{}_{}", numeric_id, start_nonce, nonces)
+}
+
+/// Splits a `{id}_{start_nonce}_{nonces}` plot file name into its parts.
+pub fn parse_plot_file_name(file_name: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = file_name.split('_');
+    let numeric_id = parts.next()?.parse().ok()?;
+    let start_nonce = parts.next()?.parse().ok()?;
+    let nonces = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((numeric_id, start_nonce, nonces))
+}
+
+/// Splits a legacy `{id}_{start_nonce}_{nonces}_{stagger}` plot file name
+/// into its parts. The nonces of such files are stored in groups of `stagger`.
+pub fn parse_stagger_file_name(file_name: &str) -> Option<(u64, u64, u64, u64)> {
+    let (plot_name, stagger) = file_name.rsplit_once('_')?;
+    let (numeric_id, start_nonce, nonces) = parse_plot_file_name(plot_name)?;
+    let stagger: u64 = stagger.parse().ok()?;
+    if stagger == 0 || !nonces.is_multiple_of(stagger) {
+        return None;
+    }
+    Some((numeric_id, start_nonce, nonces, stagger))
+}
+
+/// First nonce following all plot files of `numeric_id` found in `path`,
+/// 0 if there are none.
+pub fn next_start_nonce(path: &str, numeric_id: u64) -> u64 {
+    let mut max_end = 0u64;
+    if let Ok(entries) = std::fs::read_dir(path) {
+        for entry in entries.flatten() {
+            if let Some((id, start_nonce, nonces)) =
+                entry.file_name().to_str().and_then(parse_plot_file_name)
+                && id == numeric_id
+            {
+                max_end = max_end.max(start_nonce + nonces);
+            }
+        }
     }
+    max_end
 }
 
 // pub fn timestamp() -> String {
@@ -0,0 +1,287 @@
+use crate::convert::swap_scoop_halves;
+use crate::cpu_hasher::{init_simd, noncegen, SimdExtension};
+use crate::error::HasherError;
+use crate::events::{HasherEvent, HasherEvents, Phase};
+use crate::hasher::{NONCE_SIZE, NUM_SCOOPS, SCOOP_SIZE};
+use crate::utils::{open_r, parse_plot_file_name};
+use crate::resume::ResumeInfo;
+use rayon::prelude::*;
+use std::cmp::min;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// nonces regenerated per job in a full check, 64 MiB of cache and a 16 KiB read buffer
+const VERIFY_BATCH: u64 = 256;
+
+pub struct VerifyTask {
+    pub file: PathBuf,
+    /// Check this many randomly chosen nonces instead of every nonce.
+    pub sample: Option<u64>,
+    /// Worker threads, 0 uses all cores.
+    pub cpu_threads: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub file: PathBuf,
+    pub numeric_id: u64,
+    pub start_nonce: u64,
+    pub nonces: u64,
+    /// Nonces on disk according to the resume info, only these are checked.
+    pub written_nonces: u64,
+    pub checked_nonces: u64,
+    /// Absolute nonce ranges with at least one mismatching scoop.
+    pub bad_ranges: Vec<Range<u64>>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.bad_ranges.is_empty()
+    }
+
+    pub fn bad_nonces(&self) -> u64 {
+        self.bad_ranges.iter().map(|r| r.end - r.start).sum()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} - checked {} of {} written nonces ({} in file)",
+            self.file.display(),
+            if self.is_ok() { "OK" } else { "BAD" },
+            self.checked_nonces,
+            self.written_nonces,
+            self.nonces
+        )?;
+        if !self.is_ok() {
+            write!(f, ", {} bad nonce(s):", self.bad_nonces())?;
+            for range in &self.bad_ranges {
+                if range.end - range.start == 1 {
+                    write!(f, "\n  {}", range.start)?;
+                } else {
+                    write!(f, "\n  {}..{}", range.start, range.end - 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Regenerates the nonces of a plot file and compares them scoop by scoop
+/// with what is on disk.
+///
+/// Only the nonces covered by the resume info are checked, so partially
+/// written files can be verified as well.
+pub fn verify_plot_file(task: &VerifyTask, events: &dyn HasherEvents) -> Result<VerifyReport, HasherError> {
+    let file = task.file.as_path();
+    let (numeric_id, start_nonce, nonces) = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_plot_file_name)
+        .ok_or_else(|| HasherError::InvalidPlotFileName(file.to_path_buf()))?;
+
+    let size = file
+        .metadata()
+        .map_err(|source| HasherError::ReadPlotFile { file: file.to_path_buf(), source })?
+        .len();
+    if size != nonces * NONCE_SIZE {
+        return Err(HasherError::PlotFileSize {
+            file: file.to_path_buf(),
+            expected: nonces * NONCE_SIZE,
+            actual: size,
+        });
+    }
+
+    // files without resume info weren't written by us, treat them as complete
+    let (written_nonces, has_marker, poc1) = match ResumeInfo::read_optional(file) {
+        Ok(Some(info)) => (info.progress, info.is_legacy(), info.poc1),
+        Ok(None) => (nonces, false, false),
+        Err(source) => return Err(HasherError::ReadResumeInfo { file: file.to_path_buf(), source }),
+    };
+
+    let batches: Vec<(u64, u64)> = match task.sample {
+        Some(sample) if sample < written_nonces => {
+            let mut picked = BTreeSet::new();
+            while (picked.len() as u64) < sample {
+                picked.insert(fastrand::u64(0..written_nonces));
+            }
+            picked.into_iter().map(|offset| (offset, 1)).collect()
+        }
+        _ => (0..written_nonces)
+            .step_by(VERIFY_BATCH as usize)
+            .map(|offset| (offset, min(VERIFY_BATCH, written_nonces - offset)))
+            .collect(),
+    };
+    let to_check = batches.iter().map(|b| b.1).sum();
+
+    events.on_event(&HasherEvent::Phase(Phase::Verifying { nonces: to_check }));
+
+    let simd_ext = init_simd();
+    let checked = AtomicU64::new(0);
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(task.cpu_threads as usize)
+        .build()
+        .unwrap();
+    let results: Vec<Result<Vec<u64>, HasherError>> = thread_pool.install(|| {
+        batches
+            .par_iter()
+            .map(|&(offset, count)| {
+                let bad = check_nonces(
+                    file,
+                    &simd_ext,
+                    (numeric_id, start_nonce, nonces),
+                    offset,
+                    count,
+                    has_marker,
+                    poc1,
+                )?;
+                let done = checked.fetch_add(count, Ordering::Relaxed) + count;
+                events.on_event(&HasherEvent::Hashed(done));
+                Ok(bad)
+            })
+            .collect()
+    });
+
+    let mut bad_ranges: Vec<Range<u64>> = Vec::new();
+    for result in results {
+        for offset in result? {
+            let nonce = start_nonce + offset;
+            match bad_ranges.last_mut() {
+                Some(range) if range.end == nonce => range.end += 1,
+                _ => bad_ranges.push(nonce..nonce + 1),
+            }
+        }
+    }
+
+    let report = VerifyReport {
+        file: file.to_path_buf(),
+        numeric_id,
+        start_nonce,
+        nonces,
+        written_nonces,
+        checked_nonces: to_check,
+        bad_ranges,
+    };
+    events.on_event(&HasherEvent::Verified(report.clone()));
+    Ok(report)
+}
+
+/// Returns the offsets of the nonces in `offset..offset + count` that don't
+/// match their regenerated counterpart, swapped into PoC1 for `poc1` files.
+fn check_nonces(
+    file: &Path,
+    simd_ext: &SimdExtension,
+    (numeric_id, start_nonce, nonces): (u64, u64, u64),
+    offset: u64,
+    count: u64,
+    has_marker: bool,
+    poc1: bool,
+) -> Result<Vec<u64>, HasherError> {
+    let read_error = |source| HasherError::ReadPlotFile { file: file.to_path_buf(), source };
+
+    let mut expected = vec![0u8; (count * NONCE_SIZE) as usize];
+    noncegen(simd_ext, &mut expected, 0, numeric_id, start_nonce + offset, count);
+    if poc1 {
+        swap_scoop_halves(&mut expected, count);
+    }
+
+    let mut plot = open_r(file).map_err(read_error)?;
+    let mut actual = vec![0u8; (count * SCOOP_SIZE) as usize];
+    let mut bad = vec![false; count as usize];
+
+    for scoop in 0..NUM_SCOOPS {
+        plot.seek(SeekFrom::Start(scoop * nonces * SCOOP_SIZE + offset * SCOOP_SIZE))
+            .map_err(read_error)?;
+        plot.read_exact(&mut actual).map_err(read_error)?;
+
+        let row = (scoop * count * SCOOP_SIZE) as usize;
+        let expected = &expected[row..row + actual.len()];
+        for (n, (a, e)) in actual
+            .chunks_exact(SCOOP_SIZE as usize)
+            .zip(expected.chunks_exact(SCOOP_SIZE as usize))
+            .enumerate()
+        {
+            // the legacy resume marker occupies the last 8 bytes of the file
+            let len = if has_marker && scoop == NUM_SCOOPS - 1 && offset + n as u64 == nonces - 1 {
+                SCOOP_SIZE as usize - 8
+            } else {
+                SCOOP_SIZE as usize
+            };
+            if a[..len] != e[..len] {
+                bad[n] = true;
+            }
+        }
+    }
+
+    Ok(bad
+        .iter()
+        .enumerate()
+        .filter(|(_, bad)| **bad)
+        .map(|(n, _)| offset + n as u64)
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::NoEvents;
+    use crate::utils::plot_file_name;
+    use crate::resume::write_resume_info;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn test_verify_detects_corrupt_nonce() {
+        let numeric_id = 7900104405094198526;
+        let (start_nonce, nonces) = (1337, 8);
+        let dir = std::env::temp_dir().join(format!("anne-hasher-verify-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(plot_file_name(numeric_id, start_nonce, nonces));
+
+        // a cache holding every nonce of a file has the file's layout
+        let mut plot = vec![0u8; (nonces * NONCE_SIZE) as usize];
+        noncegen(&init_simd(), &mut plot, 0, numeric_id, start_nonce, nonces);
+        fs::write(&file, &plot).unwrap();
+        write_resume_info(&file, nonces).unwrap();
+
+        let task = VerifyTask { file: file.clone(), sample: None, cpu_threads: 1 };
+        let report = verify_plot_file(&task, &NoEvents).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.checked_nonces, nonces);
+
+        // flip a byte of nonce 3 in scoop 100
+        let pos = 100 * nonces * SCOOP_SIZE + 3 * SCOOP_SIZE + 17;
+        let mut f = crate::utils::open(&file).unwrap();
+        f.seek(SeekFrom::Start(pos)).unwrap();
+        f.write_all(&[!plot[pos as usize]]).unwrap();
+        drop(f);
+
+        let report = verify_plot_file(&task, &NoEvents).unwrap();
+        assert_eq!(report.bad_ranges, vec![start_nonce + 3..start_nonce + 4]);
+
+        // a resume point before the bad nonce leaves it unchecked
+        write_resume_info(&file, 3).unwrap();
+        let report = verify_plot_file(&task, &NoEvents).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.checked_nonces, 3);
+
+        // unreadable resume info isn't taken for a complete file
+        fs::write(crate::resume::resume_file(&file), b"garbage").unwrap();
+        assert!(matches!(verify_plot_file(&task, &NoEvents), Err(HasherError::ReadResumeInfo { .. })));
+
+        // a file converted to PoC1 is checked in its own layout
+        swap_scoop_halves(&mut plot, nonces);
+        fs::write(&file, &plot).unwrap();
+        let info = ResumeInfo::new(numeric_id, start_nonce, nonces, 0).completed();
+        ResumeInfo { poc1: true, ..info }.write(&file).unwrap();
+        assert!(verify_plot_file(&task, &NoEvents).unwrap().is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "http")]
+
+use anne_hasher::{ApiEvents, ApiServer, ApiState, Hasher, NoEvents};
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let code = response[9..12].parse().unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (code, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn test_http_api() {
+    let state = ApiState::new();
+    let server = ApiServer::start("127.0.0.1:0", state.clone()).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (code, status) = request(addr, "GET", "/status", "");
+    assert_eq!(code, 200);
+    assert_eq!(status["state"], "idle");
+
+    let dir = std::env::temp_dir().join(format!("anne-hasher-http-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let task = serde_json::json!({
+        "numeric_id": 7900104405094198526u64,
+        "start_nonce": 1337,
+        "nonces": 8,
+        "paths": [dir],
+        "mem": "8MiB",
+        "cpu_threads": 1,
+        "direct_io": false,
+    });
+    let (code, queued) = request(addr, "POST", "/tasks", &task.to_string());
+    assert_eq!(code, 202);
+    assert_eq!(queued["queued"], 1);
+
+    let (_, tasks) = request(addr, "GET", "/tasks", "");
+    assert_eq!(tasks[0]["nonces"], 8);
+
+    let spec = state.next_task(Duration::from_secs(1)).unwrap();
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let task = spec.to_task(stop_flag.clone(), &NoEvents).unwrap();
+    state.start(&task);
+    let hasher = Hasher::with_events(Arc::new(ApiEvents::new(state.clone(), Arc::new(NoEvents))));
+    hasher.run(task).unwrap();
+
+    let (_, status) = request(addr, "GET", "/status", "");
+    assert_eq!(status["state"], "idle");
+    assert_eq!(status["queued"], 0);
+    assert_eq!(status["last_run"]["hashed_nonces"], 8);
+
+    assert_eq!(request(addr, "POST", "/tasks", "{").0, 400);
+    assert_eq!(request(addr, "GET", "/nope", "").0, 404);
+    assert_eq!(request(addr, "POST", "/status", "").0, 405);
+
+    // nothing runs, so there is nothing to stop
+    let (code, stopping) = request(addr, "POST", "/stop", "");
+    assert_eq!(code, 202);
+    assert_eq!(stopping["stopping"], false);
+    assert!(!stop_flag.load(Ordering::Relaxed));
+
+    let next_flag = Arc::new(AtomicBool::new(false));
+    state.start(&spec.to_task(next_flag.clone(), &NoEvents).unwrap());
+    assert_eq!(request(addr, "POST", "/stop", "").1["stopping"], true);
+    assert!(next_flag.load(Ordering::Relaxed));
+    assert!(!stop_flag.load(Ordering::Relaxed));
+
+    fs::remove_dir_all(&dir).unwrap();
+}